@@ -1,32 +1,15 @@
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use crate::service::{Clock, HttpShowdown, Showdown, SystemClock, TrackUser};
 use lambda_http::{Body, Request, RequestExt, Response};
-use pokemon_showdown_user_stats_model::{Rating, User};
-use serde_json::Value;
-use std::collections::HashMap;
 use std::env;
-use std::io::Write;
-use std::time::SystemTime;
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::storage::UserStore;
 
-pub(crate) async fn function_handler(
+/// Starts tracking the user named in the request in the DynamoDB table named by
+/// `USER_STATS_TABLE`, fetching their profile from `SHOWDOWN_URL` if set.
+pub async fn function_handler(
     ddb: &aws_sdk_dynamodb::Client,
     event: Request,
 ) -> Result<Response<Body>, lambda_http::Error> {
-    let username = match event
-        .path_parameters_ref()
-        .and_then(|params| params.first("username"))
-    {
-        Some(value) => value,
-        None => {
-            return Ok(Response::builder()
-                .status(400)
-                .body("Key 'username' is missing".into())
-                .map_err(Box::new)?);
-        }
-    };
-
-    let id = to_id(username);
-
     let user_stats_table = match env::var("USER_STATS_TABLE") {
         Ok(table) => table,
         Err(_) => {
@@ -38,232 +21,50 @@ pub(crate) async fn function_handler(
         }
     };
 
-    let ddb_response = match ddb
-        .get_item()
-        .table_name(user_stats_table.clone())
-        .key(
-            "userId",
-            aws_sdk_dynamodb::types::AttributeValue::S(id.clone()),
-        )
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
-        Err(_) => {
-            let resp = Response::builder()
-                .status(400)
-                .header("content-type", "text/html")
-                .body(format!("error calling ddb").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
-    };
-
-    if ddb_response.item.is_some() {
-        let resp = Response::builder()
-            .status(400)
-            .header("content-type", "text/html")
-            .body(format!("User has already been added").into())
-            .map_err(Box::new)?;
-        return Ok(resp);
-    }
-
-    // check if is on PS
-    let ps_response =
-        match reqwest::get(format!("https://pokemonshowdown.com/users/{id}.json")).await {
-            Ok(resp) => resp,
-            Err(_) => {
-                let resp = Response::builder()
-                    .status(500)
-                    .header("content-type", "text/html")
-                    .body(format!("id: {id} Unable to connect to PokemonShowdown").into())
-                    .map_err(Box::new)?;
-                return Ok(resp);
-            }
-        };
-
-    if ps_response.status().as_u16() == 404 {
-        let resp = Response::builder()
-            .status(404)
-            .header("content-type", "text/html")
-            .body(format!("User not registered on Pokemon Showdown").into())
-            .map_err(Box::new)?;
-        return Ok(resp);
-    }
-
-    if ps_response.status().as_u16() != 200 {
-        let resp = Response::builder()
-            .status(500)
-            .header("content-type", "text/html")
-            .body(format!("pokemon shodown api replied with status code: {} for user: {username}, id: {id}", 
-                ps_response.status()).into())
-            .map_err(Box::new)?;
-        return Ok(resp);
-    }
-
-    let ps_response_body = match ps_response.text().await {
-        Ok(resp) => resp,
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("Error parsing pokemonshowdown response").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
+    let store = UserStore::new(ddb.clone(), user_stats_table);
+    let showdown = match env::var("SHOWDOWN_URL") {
+        Ok(url) => HttpShowdown::new(url),
+        Err(_) => HttpShowdown::default(),
     };
-
-    let user_stats: serde_json::Value = match serde_json::from_str(&ps_response_body) {
-        Ok(resp) => resp,
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("Error parsing pokemonshowdown response").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
+    let service = TrackUser {
+        store: &store,
+        showdown: &showdown,
+        clock: &SystemClock,
     };
 
-    let mut user = User {
-        username: match user_stats["username"].as_str() {
-            Some(resp) => resp.to_string(),
-            None => {
-                let resp = Response::builder()
-                    .status(500)
-                    .header("content-type", "text/html")
-                    .body(format!("Error parsing pokemonshowdown response username").into())
-                    .map_err(Box::new)?;
-                return Ok(resp);
-            }
-        },
-        userid: match user_stats["userid"].as_str() {
-            Some(resp) => resp.to_string(),
-            None => {
-                let resp = Response::builder()
-                    .status(500)
-                    .header("content-type", "text/html")
-                    .body(format!("Error parsing pokemonshowdown response userid").into())
-                    .map_err(Box::new)?;
-                return Ok(resp);
-            }
-        },
-        formats: HashMap::new(),
-    };
-
-    let current_time = get_current_timestamp();
-
-    if let Some(Value::Object(map)) = user_stats.get("ratings") {
-        for (format, rating) in map {
-            let elo = match rating.get("elo").and_then(Value::as_f64) {
-                Some(val) => val,
-                None => {
-                    let resp = Response::builder()
-                        .status(500)
-                        .header("content-type", "text/html")
-                        .body("Error parsing pokemonshowdown response elo".into())
-                        .map_err(Box::new)?;
-                    return Ok(resp);
-                }
-            };
-
-            let ratings = vec![Rating {
-                time: current_time,
-                elo,
-            }];
-            user.formats.insert(format.clone(), ratings);
-        }
-    } else {
-        let resp = Response::builder()
-            .status(500)
-            .header("content-type", "text/html")
-            .body("Error parsing pokemonshowdown response".into())
-            .map_err(Box::new)?;
-        return Ok(resp);
-    }
-
-    let user_string = match serde_json::to_string(&user) {
-        Ok(resp) => resp,
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("Error parsing pokemonshowdown response").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
-    };
+    handle(&service, event).await
+}
 
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-    match encoder.write_all(user_string.as_bytes()) {
-        Ok(_) => {}
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("Error compressing json").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
+/// Starts tracking the user named in the request with `service`.
+pub async fn handle<S: StorageBackend + Sync, P: Showdown + Sync, C: Clock>(
+    service: &TrackUser<'_, S, P, C>,
+    event: Request,
+) -> Result<Response<Body>, lambda_http::Error> {
+    let username = match event
+        .path_parameters_ref()
+        .and_then(|params| params.first("username"))
+    {
+        Some(value) => value,
+        None => {
+            return Ok(Response::builder()
+                .status(400)
+                .body("Key 'username' is missing".into())
+                .map_err(Box::new)?);
         }
     };
 
-    let compressed_bytes = match encoder.finish() {
-        Ok(resp) => resp,
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("Error compressing json").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
+    let (status, body) = match service.track(username).await {
+        Ok(user) => match serde_json::to_string(&user) {
+            Ok(val) => (200, val),
+            Err(_) => (500, "Error parsing pokemonshowdown response".to_string()),
+        },
+        Err(e) => (e.status(), e.to_string()),
     };
 
-    match ddb
-        .put_item()
-        .item(
-            "userId",
-            aws_sdk_dynamodb::types::AttributeValue::S(id.clone()),
-        )
-        .item(
-            "stats.json.gz",
-            aws_sdk_dynamodb::types::AttributeValue::B(compressed_bytes.clone().into()),
-        )
-        .table_name(user_stats_table.clone())
-        .send()
-        .await
-    {
-        Ok(_) => {}
-        Err(error) => {
-            let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("Error adding new user to datastore: {error}").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
-    }
-
     let resp = Response::builder()
-        .status(200)
+        .status(status)
         .header("content-type", "text/html")
-        .body(user_string.into())
+        .body(body.into())
         .map_err(Box::new)?;
     Ok(resp)
 }
-
-fn get_current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("Time error")
-        .as_secs()
-}
-
-fn to_id<T: AsRef<str>>(text: T) -> String {
-    // Ensure the input is a string, convert it to lowercase, and remove non-alphanumeric characters
-    text.as_ref()
-        .to_lowercase()
-        .chars()
-        .filter(|c| c.is_ascii_alphanumeric())
-        .collect()
-}
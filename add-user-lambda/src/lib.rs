@@ -0,0 +1,6 @@
+//! Starts tracking users, seeding their history with their current Showdown ratings.
+
+mod http_handler;
+pub mod service;
+
+pub use http_handler::{function_handler, handle};
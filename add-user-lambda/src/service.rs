@@ -0,0 +1,291 @@
+//! Starting to track a user, independent of how the request arrived and of where users and their
+//! ratings come from.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::time::SystemTime;
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::model::{Rating, Record, User};
+use user_stats_core::storage::StoreError;
+use user_stats_core::user_id::{InvalidUserId, UserId};
+
+/// A reply from Showdown's user profile endpoint.
+pub struct ShowdownReply {
+    pub status: u16,
+    /// The profile JSON. Only read for successful replies.
+    pub body: String,
+}
+
+/// Why Showdown could not be asked for a user's profile.
+#[derive(Debug)]
+pub enum ShowdownError {
+    /// The request got no reply.
+    Unreachable(String),
+    /// The reply's body could not be read.
+    Body(String),
+}
+
+/// Where users' current ratings come from.
+pub trait Showdown {
+    /// Fetches the profile of the user with id `user_id`.
+    fn user(
+        &self,
+        user_id: &UserId,
+    ) -> impl Future<Output = Result<ShowdownReply, ShowdownError>> + Send;
+}
+
+/// Where profiles are fetched from unless configured otherwise.
+pub const SHOWDOWN_URL: &str = "https://pokemonshowdown.com";
+
+/// Fetches profiles from a Showdown server over HTTP.
+pub struct HttpShowdown {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpShowdown {
+    /// Fetches profiles from the Showdown server at `base_url`.
+    pub fn new(base_url: impl Into<String>) -> HttpShowdown {
+        HttpShowdown {
+            client: reqwest::Client::new(),
+            base_url: base_url.into(),
+        }
+    }
+}
+
+impl Default for HttpShowdown {
+    fn default() -> HttpShowdown {
+        HttpShowdown::new(SHOWDOWN_URL)
+    }
+}
+
+impl Showdown for HttpShowdown {
+    async fn user(&self, user_id: &UserId) -> Result<ShowdownReply, ShowdownError> {
+        let base_url = self.base_url.trim_end_matches('/');
+        let resp = self
+            .client
+            .get(format!("{base_url}/users/{user_id}.json"))
+            .send()
+            .await
+            .map_err(|e| ShowdownError::Unreachable(e.to_string()))?;
+        let status = resp.status().as_u16();
+        if status != 200 {
+            return Ok(ShowdownReply {
+                status,
+                body: String::new(),
+            });
+        }
+        let body = resp
+            .text()
+            .await
+            .map_err(|e| ShowdownError::Body(e.to_string()))?;
+        Ok(ShowdownReply { status, body })
+    }
+}
+
+/// The time new ratings are stamped with.
+pub trait Clock {
+    /// Seconds since the unix epoch.
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Time error")
+            .as_secs()
+    }
+}
+
+/// Why a user could not be tracked.
+#[derive(Debug)]
+pub enum TrackUserError {
+    /// The username cannot be a Showdown user, so Showdown was not asked.
+    InvalidUsername(InvalidUserId),
+    ShowdownUnreachable {
+        user_id: UserId,
+    },
+    NotOnShowdown,
+    /// Showdown replied with a status other than 200 or 404.
+    ShowdownStatus {
+        status: u16,
+        username: String,
+        user_id: UserId,
+    },
+    /// Showdown's reply was not a profile. Names the part that is missing or malformed, if any.
+    InvalidProfile(Option<&'static str>),
+    /// Showdown reported the profile under a different user id. Players who moved accounts are
+    /// linked with the admin `alias` tool rather than tracked under both ids.
+    UserIdMismatch {
+        user_id: UserId,
+        reported: String,
+    },
+    Load(StoreError),
+    Save(StoreError),
+}
+
+impl TrackUserError {
+    /// The HTTP status code the error is reported with.
+    pub fn status(&self) -> u16 {
+        match self {
+            TrackUserError::NotOnShowdown => 404,
+            TrackUserError::UserIdMismatch { .. } => 409,
+            TrackUserError::InvalidUsername(_) | TrackUserError::Load(_) => 400,
+            _ => 500,
+        }
+    }
+}
+
+impl fmt::Display for TrackUserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackUserError::InvalidUsername(e) => write!(f, "invalid username: {}", e),
+            TrackUserError::ShowdownUnreachable { user_id } => {
+                write!(f, "id: {} Unable to connect to PokemonShowdown", user_id)
+            }
+            TrackUserError::NotOnShowdown => write!(f, "User not registered on Pokemon Showdown"),
+            TrackUserError::ShowdownStatus {
+                status,
+                username,
+                user_id,
+            } => write!(
+                f,
+                "pokemon shodown api replied with status code: {} for user: {}, id: {}",
+                status, username, user_id
+            ),
+            TrackUserError::InvalidProfile(None) => {
+                write!(f, "Error parsing pokemonshowdown response")
+            }
+            TrackUserError::InvalidProfile(Some(part)) => {
+                write!(f, "Error parsing pokemonshowdown response {}", part)
+            }
+            TrackUserError::UserIdMismatch { user_id, reported } => write!(
+                f,
+                "Pokemon Showdown reports user ID: {} for user ID: {}",
+                reported, user_id
+            ),
+            TrackUserError::Load(_) => write!(f, "error calling ddb"),
+            TrackUserError::Save(e) => write!(f, "Error adding new user to datastore: {}", e),
+        }
+    }
+}
+
+/// Starts tracking users, seeding their history with their current ratings on Showdown.
+pub struct TrackUser<'a, S, P, C> {
+    pub store: &'a S,
+    pub showdown: &'a P,
+    pub clock: &'a C,
+}
+
+impl<S: StorageBackend + Sync, P: Showdown + Sync, C: Clock> TrackUser<'_, S, P, C> {
+    /// Starts tracking `username`, appending their current ratings to any history already stored,
+    /// and returns the stored user. Usernames that are aliases track the user they were merged
+    /// into instead.
+    pub async fn track(&self, username: &str) -> Result<User, TrackUserError> {
+        let id = UserId::parse(username).map_err(TrackUserError::InvalidUsername)?;
+        let resolved = self
+            .store
+            .resolve(id.as_str())
+            .await
+            .map_err(TrackUserError::Load)?;
+        let id = UserId::parse(&resolved).map_err(|e| {
+            TrackUserError::Load(StoreError::Corrupt(format!(
+                "alias of {} is not a user id: {}",
+                id, e
+            )))
+        })?;
+
+        let reply = match self.showdown.user(&id).await {
+            Ok(val) => val,
+            Err(ShowdownError::Unreachable(_)) => {
+                return Err(TrackUserError::ShowdownUnreachable { user_id: id })
+            }
+            Err(ShowdownError::Body(_)) => return Err(TrackUserError::InvalidProfile(None)),
+        };
+        match reply.status {
+            200 => {}
+            404 => return Err(TrackUserError::NotOnShowdown),
+            status => {
+                return Err(TrackUserError::ShowdownStatus {
+                    status,
+                    username: username.to_string(),
+                    user_id: id,
+                })
+            }
+        }
+
+        let user_stats: Value =
+            serde_json::from_str(&reply.body).map_err(|_| TrackUserError::InvalidProfile(None))?;
+        let mut user = User {
+            username: match user_stats["username"].as_str() {
+                Some(val) => val.to_string(),
+                None => return Err(TrackUserError::InvalidProfile(Some("username"))),
+            },
+            userid: match user_stats["userid"].as_str() {
+                Some(val) => val.to_string(),
+                None => return Err(TrackUserError::InvalidProfile(Some("userid"))),
+            },
+            formats: HashMap::new(),
+        };
+
+        if user.userid != id.as_str() {
+            return Err(TrackUserError::UserIdMismatch {
+                user_id: id,
+                reported: user.userid,
+            });
+        }
+
+        let current_time = self.clock.now();
+        match user_stats.get("ratings") {
+            Some(Value::Object(map)) => {
+                for (format, rating) in map {
+                    let elo = match rating.get("elo").and_then(Value::as_f64) {
+                        Some(val) => val,
+                        None => return Err(TrackUserError::InvalidProfile(Some("elo"))),
+                    };
+                    let ratings =
+                        vec![Rating::live(current_time, elo)
+                            .with_record(Record::from_showdown(rating))];
+                    user.formats.insert(format.clone(), ratings);
+                }
+            }
+            _ => return Err(TrackUserError::InvalidProfile(None)),
+        }
+
+        let mut existing = match self.store.load_user(id.as_str()).await {
+            Ok(Some(existing)) => existing,
+            Ok(None) => {
+                self.store
+                    .save_user(&user)
+                    .await
+                    .map_err(TrackUserError::Save)?;
+                return Ok(user);
+            }
+            Err(e) => return Err(TrackUserError::Load(e)),
+        };
+
+        // Re-adding a tracked user appends the current ratings that merging would add to their
+        // stored history, without rewriting it. Showdown's username is current; the stored one may
+        // be from before a change in capitalization or spacing.
+        let username = user.username.clone();
+        let new_ratings = existing.new_ratings(user);
+        if new_ratings.is_empty() && existing.username == username {
+            return Ok(existing);
+        }
+        self.store
+            .append_ratings(id.as_str(), &username, &new_ratings)
+            .await
+            .map_err(TrackUserError::Save)?;
+
+        existing.username = username;
+        for (format, rating) in new_ratings {
+            existing.formats.entry(format).or_default().push(rating);
+        }
+        Ok(existing)
+    }
+}
@@ -0,0 +1,507 @@
+use add_user_lambda::service::{
+    Clock, Showdown, ShowdownError, ShowdownReply, TrackUser, TrackUserError,
+};
+use add_user_lambda::{function_handler, handle};
+use aws_sdk_dynamodb::config::{BehaviorVersion, Region};
+use lambda_http::{Body, Request, RequestExt, Response};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::model::{Rating, Record, User};
+use user_stats_core::storage::StoreError;
+use user_stats_core::user_id::UserId;
+
+const NOW: u64 = 1_700_000_000;
+
+const PROFILE: &str = r#"{
+    "username": "Some User",
+    "userid": "someuser",
+    "ratings": {
+        "gen9ou": {"elo": 1500.5, "w": 10, "l": 4, "t": 1},
+        "gen9randombattle": {"elo": 1234}
+    }
+}"#;
+
+/// Ratings appended to a user's history, with their user id.
+type Appended = (String, Vec<(String, Rating)>);
+
+#[derive(Default)]
+struct FakeStore {
+    users: Mutex<HashMap<String, User>>,
+    /// Alias links, from user id to the user id it was merged into.
+    aliases: HashMap<String, String>,
+    fail_load: Option<fn() -> StoreError>,
+    fail_save: Option<fn() -> StoreError>,
+    /// Ids of whole users written.
+    saved: Mutex<Vec<String>>,
+    appended: Mutex<Vec<Appended>>,
+}
+
+impl FakeStore {
+    fn with_user(user: User) -> FakeStore {
+        let store = FakeStore::default();
+        store
+            .users
+            .lock()
+            .unwrap()
+            .insert(user.userid.clone(), user);
+        store
+    }
+
+    fn user(&self, user_id: &str) -> Option<User> {
+        self.users.lock().unwrap().get(user_id).cloned()
+    }
+}
+
+impl StorageBackend for FakeStore {
+    async fn user_ids(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self.users.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn load_user(&self, user_id: &str) -> Result<Option<User>, StoreError> {
+        match self.fail_load {
+            Some(error) => Err(error()),
+            None => Ok(self.user(user_id)),
+        }
+    }
+
+    async fn alias_of(&self, user_id: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.aliases.get(user_id).cloned())
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), StoreError> {
+        if let Some(error) = self.fail_save {
+            return Err(error());
+        }
+        self.saved.lock().unwrap().push(user.userid.clone());
+        let mut users = self.users.lock().unwrap();
+        users.insert(user.userid.clone(), user.clone());
+        Ok(())
+    }
+
+    async fn append_ratings(
+        &self,
+        user_id: &str,
+        username: &str,
+        new_ratings: &[(String, Rating)],
+    ) -> Result<(), StoreError> {
+        if let Some(error) = self.fail_save {
+            return Err(error());
+        }
+        self.appended
+            .lock()
+            .unwrap()
+            .push((user_id.to_string(), new_ratings.to_vec()));
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(user_id).expect("appending to a stored user");
+        user.username = username.to_string();
+        for (format, rating) in new_ratings {
+            user.formats
+                .entry(format.clone())
+                .or_default()
+                .push(rating.clone());
+        }
+        Ok(())
+    }
+}
+
+enum Reply {
+    Profile(u16, &'static str),
+    Unreachable,
+    UnreadableBody,
+}
+
+/// Replies to every profile request the same way and records the ids asked for.
+struct FakeShowdown {
+    reply: Reply,
+    requested: Mutex<Vec<String>>,
+}
+
+impl FakeShowdown {
+    fn new(reply: Reply) -> FakeShowdown {
+        FakeShowdown {
+            reply,
+            requested: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Showdown for FakeShowdown {
+    async fn user(&self, user_id: &UserId) -> Result<ShowdownReply, ShowdownError> {
+        self.requested.lock().unwrap().push(user_id.to_string());
+        match self.reply {
+            Reply::Profile(status, body) => Ok(ShowdownReply {
+                status,
+                body: body.to_string(),
+            }),
+            Reply::Unreachable => Err(ShowdownError::Unreachable("connection refused".into())),
+            Reply::UnreadableBody => Err(ShowdownError::Body("connection reset".into())),
+        }
+    }
+}
+
+struct FixedClock;
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        NOW
+    }
+}
+
+fn put(username: &str) -> Request {
+    Request::default().with_path_parameters(HashMap::from([(
+        "username".to_string(),
+        username.to_string(),
+    )]))
+}
+
+async fn track(store: &FakeStore, showdown: &FakeShowdown, username: &str) -> Response<Body> {
+    let service = TrackUser {
+        store,
+        showdown,
+        clock: &FixedClock,
+    };
+    handle(&service, put(username)).await.unwrap()
+}
+
+/// Tracks `username` against a Showdown that replies with `reply`, returning the status and body.
+async fn track_with(store: &FakeStore, reply: Reply, username: &str) -> (u16, String) {
+    let resp = track(store, &FakeShowdown::new(reply), username).await;
+    (resp.status().as_u16(), body(&resp))
+}
+
+fn body(resp: &Response<Body>) -> String {
+    String::from_utf8(resp.body().to_vec()).unwrap()
+}
+
+fn content_type(resp: &Response<Body>) -> Option<&str> {
+    resp.headers()
+        .get("content-type")
+        .map(|value| value.to_str().unwrap())
+}
+
+fn expected_user() -> User {
+    User {
+        username: "Some User".to_string(),
+        userid: "someuser".to_string(),
+        formats: HashMap::from([
+            (
+                "gen9ou".to_string(),
+                vec![Rating::live(NOW, 1500.5).with_record(Some(Record { w: 10, l: 4, t: 1 }))],
+            ),
+            (
+                "gen9randombattle".to_string(),
+                vec![Rating::live(NOW, 1234.0)],
+            ),
+        ]),
+    }
+}
+
+fn assert_same_user(actual: &User, expected: &User) {
+    assert_eq!(actual.username, expected.username);
+    assert_eq!(actual.userid, expected.userid);
+    assert_eq!(actual.formats, expected.formats);
+}
+
+#[tokio::test]
+async fn tracks_a_new_user_with_their_current_ratings() {
+    let store = FakeStore::default();
+    let showdown = FakeShowdown::new(Reply::Profile(200, PROFILE));
+
+    let resp = track(&store, &showdown, "Some User!").await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(content_type(&resp), Some("text/html"));
+    assert_eq!(*showdown.requested.lock().unwrap(), vec!["someuser"]);
+    let stored = store.user("someuser").unwrap();
+    assert_same_user(&stored, &expected_user());
+    let returned: User = serde_json::from_str(&body(&resp)).unwrap();
+    assert_same_user(&returned, &stored);
+}
+
+#[tokio::test]
+async fn appends_current_ratings_to_existing_history() {
+    let mut existing = expected_user();
+    existing.username = "some user".to_string();
+    existing.formats = HashMap::from([
+        ("gen9ou".to_string(), vec![Rating::live(NOW - 100, 1400.0)]),
+        (
+            "gen9randombattle".to_string(),
+            vec![Rating::live(NOW - 100, 1234.0)],
+        ),
+    ]);
+    let store = FakeStore::with_user(existing);
+
+    let (status, body) = track_with(&store, Reply::Profile(200, PROFILE), "someuser").await;
+
+    assert_eq!(status, 200);
+    let current_ou = Rating::live(NOW, 1500.5).with_record(Some(Record { w: 10, l: 4, t: 1 }));
+    // Only the rating that changed is written, and the history is not rewritten.
+    assert_eq!(
+        *store.appended.lock().unwrap(),
+        vec![(
+            "someuser".to_string(),
+            vec![("gen9ou".to_string(), current_ou.clone())]
+        )]
+    );
+    assert!(store.saved.lock().unwrap().is_empty());
+    let stored = store.user("someuser").unwrap();
+    assert_eq!(stored.username, "Some User");
+    assert_eq!(
+        stored.formats["gen9ou"],
+        vec![Rating::live(NOW - 100, 1400.0), current_ou]
+    );
+    assert_eq!(
+        stored.formats["gen9randombattle"],
+        vec![Rating::live(NOW - 100, 1234.0)]
+    );
+    let returned: User = serde_json::from_str(&body).unwrap();
+    assert_same_user(&returned, &stored);
+}
+
+#[tokio::test]
+async fn re_adding_an_unchanged_user_writes_nothing() {
+    let store = FakeStore::with_user(expected_user());
+    let mut later = expected_user();
+    for ratings in later.formats.values_mut() {
+        ratings[0].time = NOW - 100;
+    }
+    let earlier = FakeStore::with_user(later);
+
+    for store in [&store, &earlier] {
+        let (status, body) = track_with(store, Reply::Profile(200, PROFILE), "someuser").await;
+
+        assert_eq!(status, 200);
+        assert!(store.appended.lock().unwrap().is_empty());
+        assert!(store.saved.lock().unwrap().is_empty());
+        let returned: User = serde_json::from_str(&body).unwrap();
+        assert_same_user(&returned, &store.user("someuser").unwrap());
+    }
+}
+
+#[tokio::test]
+async fn re_adding_a_renamed_user_updates_only_the_username() {
+    let mut existing = expected_user();
+    existing.username = "some user".to_string();
+    let store = FakeStore::with_user(existing);
+
+    let (status, _) = track_with(&store, Reply::Profile(200, PROFILE), "someuser").await;
+
+    assert_eq!(status, 200);
+    assert_eq!(
+        *store.appended.lock().unwrap(),
+        vec![("someuser".to_string(), vec![])]
+    );
+    assert_same_user(&store.user("someuser").unwrap(), &expected_user());
+}
+
+#[tokio::test]
+async fn rejects_requests_without_a_username() {
+    let store = FakeStore::default();
+    let showdown = FakeShowdown::new(Reply::Profile(200, PROFILE));
+    let service = TrackUser {
+        store: &store,
+        showdown: &showdown,
+        clock: &FixedClock,
+    };
+
+    let resp = handle(&service, Request::default()).await.unwrap();
+
+    assert_eq!(resp.status(), 400);
+    assert_eq!(body(&resp), "Key 'username' is missing");
+    assert!(showdown.requested.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn rejects_usernames_showdown_would_not_allow_before_asking_it() {
+    let cases = [
+        ("!!", "invalid username: username must contain a letter or digit"),
+        (
+            "a very long username indeed",
+            "invalid username: username is 23 characters long without spaces and symbols, at most 18 are allowed",
+        ),
+    ];
+    for (username, message) in cases {
+        let store = FakeStore::default();
+        let showdown = FakeShowdown::new(Reply::Profile(200, PROFILE));
+
+        let resp = track(&store, &showdown, username).await;
+
+        assert_eq!(resp.status(), 400);
+        assert_eq!(body(&resp), message);
+        assert!(showdown.requested.lock().unwrap().is_empty());
+        assert!(store.users.lock().unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn tracks_the_user_an_alias_was_merged_into() {
+    let mut store = FakeStore::with_user(expected_user());
+    store
+        .aliases
+        .insert("oldname".to_string(), "someuser".to_string());
+    let showdown = FakeShowdown::new(Reply::Profile(200, PROFILE));
+
+    let resp = track(&store, &showdown, "Old Name").await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(*showdown.requested.lock().unwrap(), vec!["someuser"]);
+    assert!(store.user("oldname").is_none());
+    let returned: User = serde_json::from_str(&body(&resp)).unwrap();
+    assert_same_user(&returned, &expected_user());
+}
+
+#[tokio::test]
+async fn refuses_profiles_showdown_reports_under_another_user_id() {
+    let store = FakeStore::default();
+    assert_eq!(
+        track_with(&store, Reply::Profile(200, PROFILE), "Old Name").await,
+        (
+            409,
+            "Pokemon Showdown reports user ID: someuser for user ID: oldname".to_string()
+        )
+    );
+    assert!(store.users.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reports_an_unreachable_showdown() {
+    let store = FakeStore::default();
+    assert_eq!(
+        track_with(&store, Reply::Unreachable, "Some User").await,
+        (
+            500,
+            "id: someuser Unable to connect to PokemonShowdown".to_string()
+        )
+    );
+    assert!(store.user("someuser").is_none());
+}
+
+#[tokio::test]
+async fn reports_users_not_on_showdown() {
+    let store = FakeStore::default();
+    assert_eq!(
+        track_with(&store, Reply::Profile(404, ""), "someuser").await,
+        (404, "User not registered on Pokemon Showdown".to_string())
+    );
+}
+
+#[tokio::test]
+async fn reports_unexpected_showdown_statuses() {
+    let store = FakeStore::default();
+    assert_eq!(
+        track_with(&store, Reply::Profile(503, ""), "Some User").await,
+        (
+            500,
+            "pokemon shodown api replied with status code: 503 for user: Some User, id: someuser"
+                .to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn reports_unreadable_profiles() {
+    let cases = [
+        (Reply::UnreadableBody, ""),
+        (Reply::Profile(200, "not json"), ""),
+        (
+            Reply::Profile(200, r#"{"userid": "someuser", "ratings": {}}"#),
+            " username",
+        ),
+        (
+            Reply::Profile(200, r#"{"username": "Some User", "ratings": {}}"#),
+            " userid",
+        ),
+        (
+            Reply::Profile(
+                200,
+                r#"{"username": "Some User", "userid": "someuser", "ratings": {"gen9ou": {}}}"#,
+            ),
+            " elo",
+        ),
+        (
+            Reply::Profile(200, r#"{"username": "Some User", "userid": "someuser"}"#),
+            "",
+        ),
+        (
+            Reply::Profile(
+                200,
+                r#"{"username": "Some User", "userid": "someuser", "ratings": []}"#,
+            ),
+            "",
+        ),
+    ];
+    for (reply, part) in cases {
+        let store = FakeStore::default();
+        assert_eq!(
+            track_with(&store, reply, "someuser").await,
+            (
+                500,
+                format!("Error parsing pokemonshowdown response{}", part)
+            )
+        );
+        assert!(store.user("someuser").is_none());
+    }
+}
+
+#[tokio::test]
+async fn reports_failures_to_read_existing_history() {
+    let store = FakeStore {
+        fail_load: Some(|| StoreError::Dynamo("throttled".into())),
+        ..FakeStore::default()
+    };
+    assert_eq!(
+        track_with(&store, Reply::Profile(200, PROFILE), "someuser").await,
+        (400, "error calling ddb".to_string())
+    );
+    assert!(store.user("someuser").is_none());
+}
+
+#[tokio::test]
+async fn reports_failures_to_save() {
+    let store = FakeStore {
+        fail_save: Some(|| StoreError::Dynamo("throttled".into())),
+        ..FakeStore::default()
+    };
+    assert_eq!(
+        track_with(&store, Reply::Profile(200, PROFILE), "someuser").await,
+        (
+            500,
+            "Error adding new user to datastore: database error: throttled".to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn service_errors_carry_their_status() {
+    let store = FakeStore::default();
+    let showdown = FakeShowdown::new(Reply::Profile(404, ""));
+    let service = TrackUser {
+        store: &store,
+        showdown: &showdown,
+        clock: &FixedClock,
+    };
+
+    let error = service.track("someuser").await.unwrap_err();
+
+    assert!(matches!(error, TrackUserError::NotOnShowdown));
+    assert_eq!(error.status(), 404);
+}
+
+#[tokio::test]
+async fn reports_a_missing_table_name() {
+    std::env::remove_var("USER_STATS_TABLE");
+    let config = aws_sdk_dynamodb::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .build();
+    let ddb = aws_sdk_dynamodb::Client::from_conf(config);
+
+    let resp = function_handler(&ddb, put("someuser")).await.unwrap();
+
+    assert_eq!(resp.status(), 500);
+    assert_eq!(
+        body(&resp),
+        "Failed to get USER_STATS_TABLE from environment"
+    );
+}
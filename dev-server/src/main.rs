@@ -0,0 +1,191 @@
+//! Runs the whole project on one machine: the user stats API served by the lambdas' own handlers,
+//! the update sweep, and a directory of JSON files in place of DynamoDB.
+
+use add_user_lambda::service::{HttpShowdown, SystemClock, TrackUser};
+use axum::body::to_bytes;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use clap::Parser;
+use get_user_lambda::service::GetUserStats;
+use get_user_lambda::SITE_URL;
+use lambda_http::{Body, RequestExt};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::cors::{Any, CorsLayer};
+use update_stats::showdown::{MAX_ELO, MIN_ELO, SHOWDOWN_URL};
+use update_stats::update::{Updater, SHARP_DROP_ELO};
+use user_stats_core::local::LocalStore;
+
+/// Largest request body passed on to a handler. No route reads one.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Serves the user stats API and runs update sweeps against local storage"
+)]
+struct Cli {
+    /// Directory users are stored in, one JSON file each.
+    #[arg(long, env = "DEV_SERVER_DATA_DIR", default_value = "local-data")]
+    data_dir: PathBuf,
+
+    /// Address the API listens on.
+    #[arg(long, env = "DEV_SERVER_ADDR", default_value = "127.0.0.1:8080")]
+    addr: SocketAddr,
+
+    /// Seconds between update sweeps. 0 disables sweeps.
+    #[arg(long, env = "DEV_SERVER_UPDATE_INTERVAL_SECS", default_value_t = 300)]
+    update_interval_secs: u64,
+
+    /// Base URL of the Showdown server profiles are fetched from.
+    #[arg(long, env = "DEV_SERVER_SHOWDOWN_URL", default_value = SHOWDOWN_URL)]
+    showdown_url: String,
+
+    /// Base URL of the site that feeds and share pages link to.
+    #[arg(long, env = "DEV_SERVER_SITE_URL", default_value = SITE_URL)]
+    site_url: String,
+}
+
+#[derive(Clone)]
+struct AppState {
+    store: Arc<LocalStore>,
+    showdown: Arc<HttpShowdown>,
+    site_url: Arc<str>,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let store = Arc::new(LocalStore::new(&cli.data_dir));
+
+    if cli.update_interval_secs > 0 {
+        // Clones share the store's lock with the handlers' copy.
+        let updater = Updater {
+            store: LocalStore::clone(&store),
+            showdown_url: cli.showdown_url.clone(),
+            elo_bounds: MIN_ELO..=MAX_ELO,
+            sharp_drop_elo: SHARP_DROP_ELO,
+            dry_run: false,
+        };
+        let period = Duration::from_secs(cli.update_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let pushed = updater.sweep().await;
+                println!("Sweep complete, pushed {} new ratings", pushed);
+            }
+        });
+    }
+
+    // The same routes API Gateway sends to each lambda.
+    let app = Router::new()
+        .route("/user-stats/{username}", get(get_user).put(add_user))
+        .route("/user-stats/{username}/games", get(get_user))
+        .route("/user-stats/{username}/feed.atom", get(get_user))
+        .route("/user-stats/{username}/badge.svg", get(get_user))
+        .route("/user-stats/{username}/chart.png", get(get_user))
+        .route("/user-stats/{username}/share", get(get_user))
+        .layer(
+            CorsLayer::new()
+                .allow_methods([Method::GET, Method::PUT, Method::OPTIONS])
+                .allow_origin(Any),
+        )
+        .with_state(AppState {
+            store,
+            showdown: Arc::new(HttpShowdown::new(cli.showdown_url)),
+            site_url: cli.site_url.trim_end_matches('/').into(),
+        });
+
+    let listener = match tokio::net::TcpListener::bind(cli.addr).await {
+        Ok(val) => val,
+        Err(e) => {
+            println!("ERROR: unable to listen on {}: {}. Exiting...", cli.addr, e);
+            return;
+        }
+    };
+    println!(
+        "Serving http://{}/user-stats with users stored in {}",
+        cli.addr,
+        cli.data_dir.display()
+    );
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        println!("ERROR: server failed: {}", e);
+    }
+}
+
+async fn get_user(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    request: Request,
+) -> Response {
+    match into_event(request, username, query).await {
+        Ok(event) => {
+            let service = GetUserStats {
+                store: state.store.as_ref(),
+                site_url: &state.site_url,
+            };
+            into_response(get_user_lambda::handle(&service, event).await)
+        }
+        Err(resp) => resp,
+    }
+}
+
+async fn add_user(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    request: Request,
+) -> Response {
+    match into_event(request, username, query).await {
+        Ok(event) => {
+            let service = TrackUser {
+                store: state.store.as_ref(),
+                showdown: state.showdown.as_ref(),
+                clock: &SystemClock,
+            };
+            into_response(add_user_lambda::handle(&service, event).await)
+        }
+        Err(resp) => resp,
+    }
+}
+
+/// Converts a request into the event API Gateway would give a lambda for it.
+async fn into_event(
+    request: Request,
+    username: String,
+    query: HashMap<String, String>,
+) -> Result<lambda_http::Request, Response> {
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(val) if val.is_empty() => Body::Empty,
+        Ok(val) => Body::Binary(val.to_vec()),
+        Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+    };
+    Ok(lambda_http::Request::from_parts(parts, body)
+        .with_path_parameters(HashMap::from([("username".to_string(), username)]))
+        .with_query_string_parameters(query))
+}
+
+fn into_response(result: Result<lambda_http::Response<Body>, lambda_http::Error>) -> Response {
+    match result {
+        Ok(resp) => {
+            let (parts, body) = resp.into_parts();
+            Response::from_parts(parts, axum::body::Body::from(body.to_vec()))
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -1,7 +1,7 @@
 import './App.css'
 import { MultiLineChart } from "@/components/ui/multi-line-chart"
 import { useState, useEffect } from "react"
-import { UserStats } from "@/lib/api"
+import { UserStats, getShareUrl } from "@/lib/api"
 import { getRatingsForFormat, getLatestRating, getUserName, getFormat, formatRatings, getFormats} from './lib/user-stats-parser'
 import { tryGetUsernameFromStorage, tryGetFormatFromStorage, updateUserStats } from './lib/defaults'
 import { FormatForm } from './components/format-form'
@@ -22,10 +22,12 @@ function App() {
   useEffect(() => {
     if (useDefault) {
       const fetchDefaultUser = async () => {
+        // Links to a user's chart, such as from notifications, name them in the query string.
+        const params = new URLSearchParams(window.location.search);
         const fallbackDefaultUsername = "MichaelderBeste2";
-        const username = tryGetUsernameFromStorage(localStorage, fallbackDefaultUsername)
+        const username = params.get("user") ?? tryGetUsernameFromStorage(localStorage, fallbackDefaultUsername)
         const fallbackDefaultFormat = "gen9randombattle"
-        const defaultfrmt = tryGetFormatFromStorage(localStorage, fallbackDefaultFormat)
+        const defaultfrmt = params.get("format") ?? tryGetFormatFromStorage(localStorage, fallbackDefaultFormat)
         await updateUserStats(username, defaultfrmt, fallbackDefaultFormat, setUserStats, setFormat)
         setUseDefault(false);
       };
@@ -58,6 +60,7 @@ function App() {
   const formattedRatings = formatRatings(filteredRatings);
   const formats = getFormats(userStats);
   const lastRating = getLatestRating(ratings);
+  const shareUrl = userStats === undefined ? undefined : getShareUrl(userStats.userid, format);
 
   // TODO: derive list of formats from userstats
 
@@ -78,6 +81,7 @@ function App() {
             <div className="App-form">
               <UsernameForm setUserStats={setUserStats} setFormat={setFormat} currentFormat={currentFormat} setAddUserDialog={setAddUserDialog}  setEnteredUsername={setEnteredUsername} setNotRegisteredDialog={setNotRegisteredDialog}/>
               <FormatForm currentFormat={currentFormat} setFormat={setFormat} formats={formats} />
+              {shareUrl && <a className="text-sm underline" href={shareUrl}>Link to this chart with a preview</a>}
             </div>
           </div> :
           <div style={{ display: "grid", gridTemplateColumns: "4fr 1fr", gap: "10px" }}>
@@ -87,6 +91,7 @@ function App() {
             <div className="App-form">
               <UsernameForm setUserStats={setUserStats} setFormat={setFormat} currentFormat={currentFormat} setAddUserDialog={setAddUserDialog}  setEnteredUsername={setEnteredUsername} setNotRegisteredDialog={setNotRegisteredDialog}/>
               <FormatForm currentFormat={currentFormat} setFormat={setFormat} formats={formats} />
+              {shareUrl && <a className="text-sm underline" href={shareUrl}>Link to this chart with a preview</a>}
             </div>
           </div>
       }
@@ -4,6 +4,21 @@ import { toID } from "./user-stats-parser";
 export interface Rating {
     time: number;
     elo: number;
+    // Only set for ratings reconstructed from replays.
+    source?: "replay";
+    // Ladder wins, losses and ties when the rating was taken, if Showdown reported them.
+    record?: WinLossRecord;
+}
+
+export interface WinLossRecord {
+    w: number;
+    l: number;
+    t: number;
+}
+
+export interface GamesPlayed extends WinLossRecord {
+    // Time of the rating the games led up to.
+    time: number;
 }
 
 export interface Formats {
@@ -14,6 +29,55 @@ export interface UserStats {
     username: string;
     userid: string;
     formats: Formats;
+    // Games played between consecutive ratings with a record, per format.
+    games?: { [format: string]: GamesPlayed[] };
+    highlights: { [format: string]: Highlights };
+}
+
+export interface Peak {
+    time: number;
+    elo: number;
+}
+
+export interface Streak {
+    trend: "rising" | "falling";
+    // Number of rating changes in the run.
+    length: number;
+    start: number;
+    end: number;
+    fromElo: number;
+    toElo: number;
+}
+
+export interface Milestone {
+    elo: number;
+    // When the milestone was first reached.
+    time: number;
+}
+
+export interface Highlights {
+    // Every rating that set a new peak; the last one is the current peak.
+    peaks: Peak[];
+    currentStreak?: Streak;
+    longestRise?: Streak;
+    longestFall?: Streak;
+    milestones: Milestone[];
+}
+
+export interface InferredGame {
+    // Time of the rating the game led up to.
+    time: number;
+    eloBefore: number;
+    eloAfter: number;
+    outcome: "win" | "loss" | "tie" | "multi-game";
+    games?: number;
+    opponentElo?: number;
+}
+
+export interface GameLog {
+    username: string;
+    userid: string;
+    formats: { [format: string]: InferredGame[] };
 }
 
 interface UserProfile {
@@ -33,7 +97,8 @@ interface RatingStats {
     rprd: number;
 }
 
-const API_BASE_URL = "https://pokemonshowdownuserstats.com";
+// Point at a local dev-server with VITE_API_BASE_URL=http://127.0.0.1:8080.
+const API_BASE_URL = import.meta.env.VITE_API_BASE_URL ?? "https://pokemonshowdownuserstats.com";
 
 export const getUserStats = async (username: string): Promise<UserStats> => {
     const id = toID(username);
@@ -47,6 +112,20 @@ export const getUserStats = async (username: string): Promise<UserStats> => {
     return response.data;
 };
 
+export const getGameLog = async (username: string): Promise<GameLog> => {
+    const id = toID(username);
+    const response = await axios.get<GameLog>(`${API_BASE_URL}/user-stats/${id}/games`);
+    return response.data;
+};
+
+// Page to share a link to a user's chart. The site itself is rendered by script, which link
+// previews don't run, so this server-rendered page carries the user's preview and chart image.
+export const getShareUrl = (username: string, format?: string): string => {
+    const id = toID(username);
+    const query = format ? `?format=${encodeURIComponent(toID(format))}` : "";
+    return `${API_BASE_URL}/user-stats/${id}/share${query}`;
+};
+
 export const addUser = async (username: string): Promise<UserStats> => {
     const id = toID(username);
     try {
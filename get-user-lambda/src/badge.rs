@@ -0,0 +1,167 @@
+use crate::xml::escape;
+use user_stats_core::model::{Rating, User};
+
+/// Points in the sparkline when a request does not ask for a number.
+pub(crate) const DEFAULT_POINTS: usize = 30;
+/// Range of points a request may ask for.
+pub(crate) const MIN_POINTS: usize = 2;
+pub(crate) const MAX_POINTS: usize = 100;
+
+const HEIGHT: f64 = 20.0;
+/// Horizontal padding on either side of each text section.
+const PADDING: f64 = 5.0;
+const SPARKLINE_WIDTH: f64 = 60.0;
+/// Vertical margin between the sparkline and the badge's edges.
+const SPARKLINE_MARGIN: f64 = 4.0;
+const LABEL_COLOR: &str = "#555";
+const RISING_COLOR: &str = "#4c1";
+const FALLING_COLOR: &str = "#e05d44";
+
+/// A rendered badge.
+pub struct Badge {
+    pub svg: String,
+    /// Time of the rating the badge shows.
+    pub updated: u64,
+}
+
+/// Renders an SVG badge with the user's name, the format, their current elo and a sparkline of
+/// their last `points` ratings in it. Without a format, shows the format they were most recently
+/// rated in. `None` when the user has no ratings in the format.
+pub(crate) fn render(user: &User, format: Option<&str>, points: usize) -> Option<Badge> {
+    let (format, ratings) = match format {
+        Some(format) => user.formats.get_key_value(format)?,
+        None => most_recent_format(user)?,
+    };
+    let latest = ratings.last()?;
+    let recent = &ratings[ratings.len().saturating_sub(points)..];
+
+    let label = escape(&user.username);
+    let value = format!("{} {:.0}", escape(format), latest.elo);
+    let label_width = text_width(&user.username) + 2.0 * PADDING;
+    let value_width = text_width(&value) + 2.0 * PADDING;
+    let width = label_width + value_width + SPARKLINE_WIDTH;
+    let rising = recent.first().is_none_or(|first| latest.elo >= first.elo);
+    let color = if rising { RISING_COLOR } else { FALLING_COLOR };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" role=\"img\" \
+         aria-label=\"{label}: {value}\">\n",
+        w = width,
+        h = HEIGHT,
+        label = label,
+        value = value
+    );
+    svg.push_str(&format!("  <title>{}: {}</title>\n", label, value));
+    svg.push_str(
+        "  <linearGradient id=\"s\" x2=\"0\" y2=\"100%\">\
+         <stop offset=\"0\" stop-color=\"#bbb\" stop-opacity=\".1\"/>\
+         <stop offset=\"1\" stop-opacity=\".1\"/></linearGradient>\n",
+    );
+    svg.push_str(&format!(
+        "  <clipPath id=\"r\"><rect width=\"{}\" height=\"{}\" rx=\"3\" fill=\"#fff\"/></clipPath>\n",
+        width, HEIGHT
+    ));
+    svg.push_str("  <g clip-path=\"url(#r)\">\n");
+    svg.push_str(&format!(
+        "    <rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+        label_width, HEIGHT, LABEL_COLOR
+    ));
+    svg.push_str(&format!(
+        "    <rect x=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+        label_width,
+        value_width + SPARKLINE_WIDTH,
+        HEIGHT,
+        color
+    ));
+    svg.push_str(&format!(
+        "    <rect width=\"{}\" height=\"{}\" fill=\"url(#s)\"/>\n",
+        width, HEIGHT
+    ));
+    svg.push_str("  </g>\n");
+    svg.push_str(
+        "  <g fill=\"#fff\" text-anchor=\"middle\" \
+         font-family=\"Verdana,Geneva,DejaVu Sans,sans-serif\" font-size=\"11\">\n",
+    );
+    svg.push_str(&format!(
+        "    <text x=\"{}\" y=\"14\">{}</text>\n",
+        label_width / 2.0,
+        label
+    ));
+    svg.push_str(&format!(
+        "    <text x=\"{}\" y=\"14\">{}</text>\n",
+        label_width + value_width / 2.0,
+        value
+    ));
+    svg.push_str("  </g>\n");
+    svg.push_str(&format!(
+        "  <polyline points=\"{}\" fill=\"none\" stroke=\"#fff\" stroke-width=\"1.5\" \
+         stroke-linejoin=\"round\"/>\n",
+        sparkline(recent, label_width + value_width)
+    ));
+    svg.push_str("</svg>\n");
+    Some(Badge {
+        svg,
+        updated: latest.time,
+    })
+}
+
+/// The format the user was most recently rated in, with its ratings. On ties, the format that
+/// sorts first.
+pub(crate) fn most_recent_format(user: &User) -> Option<(&String, &Vec<Rating>)> {
+    user.formats
+        .iter()
+        .filter(|(_, ratings)| !ratings.is_empty())
+        .max_by(|(a_format, a), (b_format, b)| {
+            latest_time(a)
+                .cmp(&latest_time(b))
+                .then_with(|| b_format.cmp(a_format))
+        })
+}
+
+fn latest_time(ratings: &[Rating]) -> u64 {
+    ratings.last().map_or(0, |rating| rating.time)
+}
+
+/// Rough width of `text` in 11px Verdana, which is enough to size the badge's sections.
+fn text_width(text: &str) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | 'I' | '.' | ',' | ':' | ' ' | '\'' => 4.0,
+            'm' | 'w' | 'M' | 'W' => 10.0,
+            c if c.is_ascii_uppercase() || c.is_ascii_digit() => 7.5,
+            _ => 7.0,
+        })
+        .sum()
+}
+
+/// SVG polyline points plotting `ratings` evenly spaced across the sparkline, which starts at `x`.
+/// A single rating, or ratings that never change, are drawn as a flat line across the middle.
+fn sparkline(ratings: &[Rating], x: f64) -> String {
+    let left = x + PADDING;
+    let span = SPARKLINE_WIDTH - 2.0 * PADDING;
+    let min = ratings.iter().map(|r| r.elo).fold(f64::INFINITY, f64::min);
+    let max = ratings
+        .iter()
+        .map(|r| r.elo)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let y = |elo: f64| {
+        if max > min {
+            HEIGHT
+                - SPARKLINE_MARGIN
+                - (elo - min) / (max - min) * (HEIGHT - 2.0 * SPARKLINE_MARGIN)
+        } else {
+            HEIGHT / 2.0
+        }
+    };
+    if ratings.len() < 2 {
+        let middle = y(min);
+        return format!("{:.1},{:.1} {:.1},{:.1}", left, middle, left + span, middle);
+    }
+    let step = span / (ratings.len() - 1) as f64;
+    ratings
+        .iter()
+        .enumerate()
+        .map(|(i, rating)| format!("{:.1},{:.1}", left + step * i as f64, y(rating.elo)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
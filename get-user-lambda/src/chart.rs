@@ -0,0 +1,181 @@
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::{register_font, FontStyle};
+use std::sync::Once;
+use user_stats_core::model::{Rating, User};
+use user_stats_core::time::date;
+use user_stats_core::user_id::to_id;
+
+/// Size of OpenGraph images, which social previews show without cropping.
+pub(crate) const WIDTH: u32 = 1200;
+pub(crate) const HEIGHT: u32 = 630;
+/// Most formats drawn on one chart, to keep the lines and legend readable.
+pub(crate) const MAX_FORMATS: usize = 8;
+/// Lambda has no system fonts, so labels are drawn with one bundled with the binary.
+const FONT: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+
+static REGISTER_FONT: Once = Once::new();
+
+/// What a chart request asks to draw.
+pub struct ChartRequest {
+    /// Formats to draw. The format the user was most recently rated in when empty.
+    pub formats: Vec<String>,
+    /// Unix time of the earliest rating to draw.
+    pub from: u64,
+    /// Unix time of the latest rating to draw.
+    pub to: u64,
+}
+
+impl ChartRequest {
+    /// Parses the `formats` (comma separated), `from` and `to` query parameters.
+    pub(crate) fn from_query(
+        formats: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<ChartRequest, String> {
+        let formats: Vec<String> = formats
+            .unwrap_or_default()
+            .split(',')
+            .map(to_id)
+            .filter(|format| !format.is_empty())
+            .collect();
+        if formats.len() > MAX_FORMATS {
+            return Err(format!("at most {} formats can be charted", MAX_FORMATS));
+        }
+        let time = |value: Option<&str>, name: &str, default: u64| match value {
+            None => Ok(default),
+            Some(value) => value
+                .parse::<u64>()
+                .map_err(|_| format!("{} must be a unix time in seconds", name)),
+        };
+        let from = time(from, "from", 0)?;
+        let to = time(to, "to", u64::MAX)?;
+        if from >= to {
+            return Err("from must be before to".to_string());
+        }
+        Ok(ChartRequest { formats, from, to })
+    }
+}
+
+/// Renders the requested ratings as a PNG line chart with one line per format. `None` when none
+/// of the formats have ratings in the time range.
+pub(crate) fn png(user: &User, request: &ChartRequest) -> Option<Result<Vec<u8>, String>> {
+    let series = series(user, request);
+    if series.is_empty() {
+        return None;
+    }
+    REGISTER_FONT.call_once(|| {
+        if register_font("sans-serif", FontStyle::Normal, FONT).is_err() {
+            println!("ERROR: unable to load the bundled chart font");
+        }
+    });
+
+    let mut pixels = vec![0; WIDTH as usize * HEIGHT as usize * 3];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+        if let Err(e) = draw(&root, &user.username, &series).and_then(|()| root.present()) {
+            return Some(Err(format!("Error drawing chart: {}", e)));
+        }
+    }
+    Some(encode(&pixels))
+}
+
+/// The ratings to draw in each format, oldest first, in the order they are listed in the legend.
+fn series<'a>(user: &'a User, request: &ChartRequest) -> Vec<(&'a str, &'a [Rating])> {
+    let in_range = |ratings: &'a [Rating]| {
+        let start = ratings.partition_point(|r| r.time < request.from);
+        let end = ratings.partition_point(|r| r.time <= request.to);
+        &ratings[start..end.max(start)]
+    };
+    let mut series: Vec<(&str, &[Rating])> = if request.formats.is_empty() {
+        user.formats
+            .iter()
+            .filter_map(|(format, ratings)| Some((format, ratings.last()?.time, ratings)))
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(format, _, ratings)| (format.as_str(), in_range(ratings)))
+            .into_iter()
+            .collect()
+    } else {
+        request
+            .formats
+            .iter()
+            .filter_map(|format| {
+                let (format, ratings) = user.formats.get_key_value(format)?;
+                Some((format.as_str(), in_range(ratings)))
+            })
+            .collect()
+    };
+    series.retain(|(_, ratings)| !ratings.is_empty());
+    series
+}
+
+fn draw<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    username: &str,
+    series: &[(&str, &[Rating])],
+) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    let ratings = || series.iter().flat_map(|(_, ratings)| ratings.iter());
+    let first = ratings().map(|r| r.time).min().unwrap_or_default();
+    // A single rating still needs a range to be drawn across.
+    let last = ratings()
+        .map(|r| r.time)
+        .max()
+        .unwrap_or_default()
+        .max(first + 1);
+    let min_elo = ratings().map(|r| r.elo).fold(f64::INFINITY, f64::min);
+    let max_elo = ratings().map(|r| r.elo).fold(f64::NEG_INFINITY, f64::max);
+    let margin = ((max_elo - min_elo) * 0.1).max(10.0);
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(root)
+        .caption(
+            format!("{}'s Pokemon Showdown ratings", username),
+            ("sans-serif", 32),
+        )
+        .margin(24)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(first..last, (min_elo - margin)..(max_elo + margin))?;
+    chart
+        .configure_mesh()
+        .x_labels(6)
+        .x_label_formatter(&|time| date(*time))
+        .y_label_formatter(&|elo| format!("{:.0}", elo))
+        .label_style(("sans-serif", 16))
+        .draw()?;
+
+    for (i, (format, ratings)) in series.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(
+                ratings.iter().map(|r| (r.time, r.elo)),
+                color.stroke_width(3),
+            ))?
+            .label(*format)
+            .legend(move |(x, y)| PathElement::new([(x, y), (x + 20, y)], color.stroke_width(3)));
+    }
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperLeft)
+        .label_font(("sans-serif", 16))
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    Ok(())
+}
+
+/// Encodes RGB pixels from the bitmap backend as a PNG.
+fn encode(pixels: &[u8]) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(pixels)?;
+            writer.finish()
+        })
+        .map_err(|e| format!("Error encoding chart: {}", e))?;
+    Ok(png)
+}
@@ -0,0 +1,127 @@
+use crate::url::encode;
+use crate::xml::escape;
+use user_stats_core::model::{Rating, User};
+use user_stats_core::time::rfc3339;
+
+/// Where the site is served unless `SITE_URL` is set.
+pub const SITE_URL: &str = "https://pokemonshowdownuserstats.com";
+/// Authority and date of the `tag:` URIs identifying feeds and entries. Never change these, or
+/// readers will show every entry again.
+const TAG_PREFIX: &str = "tag:pokemonshowdownuserstats.com,2024:";
+/// Most recent rating changes included in a feed.
+const MAX_ENTRIES: usize = 50;
+
+/// One rating change in a format.
+struct Change<'a> {
+    format: &'a str,
+    previous: Option<&'a Rating>,
+    rating: &'a Rating,
+}
+
+/// Renders the user's most recent rating changes across all formats as an Atom feed, newest
+/// first, linking to the site at `site_url`. Each entry's id is derived from its format and time,
+/// so it is stable across requests.
+pub(crate) fn atom(user: &User, site_url: &str) -> String {
+    let mut changes: Vec<Change> = user
+        .formats
+        .iter()
+        .flat_map(|(format, ratings)| {
+            ratings.iter().enumerate().map(move |(i, rating)| Change {
+                format,
+                previous: i.checked_sub(1).map(|j| &ratings[j]),
+                rating,
+            })
+        })
+        .collect();
+    changes.sort_by(|a, b| {
+        b.rating
+            .time
+            .cmp(&a.rating.time)
+            .then_with(|| a.format.cmp(b.format))
+    });
+    changes.truncate(MAX_ENTRIES);
+
+    let username = escape(&user.username);
+    let updated = changes.first().map_or(0, |change| change.rating.time);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("  <id>{}{}</id>\n", TAG_PREFIX, user.userid));
+    xml.push_str(&format!(
+        "  <title>{}'s Pokemon Showdown ratings</title>\n",
+        username
+    ));
+    xml.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    xml.push_str(&format!("  <author><name>{}</name></author>\n", username));
+    xml.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}/user-stats/{}/feed.atom\"/>\n",
+        escape(site_url),
+        encode(&user.userid)
+    ));
+    xml.push_str(&format!(
+        "  <link rel=\"alternate\" href=\"{}/?user={}\"/>\n",
+        escape(site_url),
+        encode(&user.userid)
+    ));
+    for change in &changes {
+        push_entry(&mut xml, user, change, site_url);
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn push_entry(xml: &mut String, user: &User, change: &Change, site_url: &str) {
+    let format = escape(change.format);
+    let elo = change.rating.elo;
+    let (title, summary) = match change.previous {
+        Some(previous) => (
+            format!(
+                "{}: {:.0} → {:.0} ({:+.0})",
+                format,
+                previous.elo,
+                elo,
+                elo - previous.elo
+            ),
+            format!(
+                "{} went from {:.0} to {:.0} in {}.",
+                escape(&user.username),
+                previous.elo,
+                elo,
+                format
+            ),
+        ),
+        None => (
+            format!("{}: {:.0}", format, elo),
+            format!(
+                "{} was first rated {:.0} in {}.",
+                escape(&user.username),
+                elo,
+                format
+            ),
+        ),
+    };
+    let summary = match &change.rating.record {
+        Some(record) => format!(
+            "{} Ladder record: {}W {}L {}T.",
+            summary, record.w, record.l, record.t
+        ),
+        None => summary,
+    };
+    xml.push_str("  <entry>\n");
+    xml.push_str(&format!(
+        "    <id>{}{}/{}/{}</id>\n",
+        TAG_PREFIX, user.userid, format, change.rating.time
+    ));
+    xml.push_str(&format!("    <title>{}</title>\n", title));
+    xml.push_str(&format!(
+        "    <updated>{}</updated>\n",
+        rfc3339(change.rating.time)
+    ));
+    xml.push_str(&format!(
+        "    <link href=\"{}/?user={}&amp;format={}\"/>\n",
+        escape(site_url),
+        encode(&user.userid),
+        encode(change.format)
+    ));
+    xml.push_str(&format!("    <summary>{}</summary>\n", summary));
+    xml.push_str("  </entry>\n");
+}
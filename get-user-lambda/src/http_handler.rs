@@ -1,41 +1,73 @@
-use flate2::read::GzDecoder;
+use crate::badge;
+use crate::chart::ChartRequest;
+use crate::feed::SITE_URL;
+use crate::service::{GetUserStats, Output, View};
 use lambda_http::{Body, Error, Request, RequestExt, Response};
 use std::env;
-use std::io::Read;
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::storage::UserStore;
+use user_stats_core::time::http_date;
+use user_stats_core::user_id::to_id;
+
+/// How long browsers and proxies may cache feeds, badges, charts and share pages.
+const CACHE_CONTROL: &str = "public, max-age=300";
+
+impl View {
+    /// The view a request's path asks for, with the query parameters it takes. `Ok(None)` for
+    /// unknown paths and `Err` for invalid parameters.
+    fn from_request<'a>(
+        path: &str,
+        param: impl Fn(&str) -> Option<&'a str>,
+    ) -> Result<Option<View>, String> {
+        let mut segments = path.trim_end_matches('/').rsplit('/');
+        let Some(last) = segments.next() else {
+            return Ok(None);
+        };
+        if segments.next() == Some("user-stats") {
+            return Ok(Some(View::Stats));
+        }
+        let view = match last {
+            "games" => View::Games,
+            "feed.atom" => View::Feed,
+            "badge.svg" => View::Badge {
+                format: param("format").map(to_id),
+                points: match param("points") {
+                    None => badge::DEFAULT_POINTS,
+                    Some(points) => points
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|points| (badge::MIN_POINTS..=badge::MAX_POINTS).contains(points))
+                        .ok_or_else(|| {
+                            format!(
+                                "points must be a number from {} to {}",
+                                badge::MIN_POINTS,
+                                badge::MAX_POINTS
+                            )
+                        })?,
+                },
+            },
+            "chart.png" => View::Chart(ChartRequest::from_query(
+                param("formats"),
+                param("from"),
+                param("to"),
+            )?),
+            "share" => View::Share {
+                format: param("format").map(to_id),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(view))
+    }
+}
 
 /// This is the main body for the function.
 /// Write your code inside it.
 /// There are some code example in the following URLs:
 /// - https://github.com/awslabs/aws-lambda-rust-runtime/tree/main/examples
-pub(crate) async fn function_handler(
+pub async fn function_handler(
     ddb: &aws_sdk_dynamodb::Client,
     event: Request,
 ) -> Result<Response<Body>, Error> {
-    let username = match event
-        .path_parameters_ref()
-        .and_then(|params| params.first("username"))
-    {
-        Some(value) => value,
-        None => {
-            // Return a 400 Bad Request response if username is missing.
-            return Ok(Response::builder()
-                .status(400)
-                .header("content-type", "text/html")
-                .body("Key 'username' is missing".into())
-                .map_err(Box::new)?);
-        }
-    };
-
-    let id = to_id(username);
-
-    if id.len() <= 0 && id.len() < 19 {
-        return Ok(Response::builder()
-            .status(400)
-            .header("content-type", "text/html")
-            .body("invalid username".into())
-            .map_err(Box::new)?);
-    }
-
     let user_stats_table = match env::var("USER_STATS_TABLE") {
         Ok(table) => table,
         Err(_) => {
@@ -48,90 +80,86 @@ pub(crate) async fn function_handler(
         }
     };
 
-    let ddb_response = match ddb
-        .get_item()
-        .table_name(user_stats_table.clone())
-        .key(
-            "userId",
-            aws_sdk_dynamodb::types::AttributeValue::S(id.clone()),
-        )
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
-        Err(_) => {
-                        let resp = Response::builder()
-                .status(500)
-                .header("content-type", "text/html")
-                .body(format!("database error").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
+    let store = UserStore::new(ddb.clone(), user_stats_table);
+    let site_url = env::var("SITE_URL").unwrap_or_else(|_| SITE_URL.to_string());
+    let service = GetUserStats {
+        store: &store,
+        site_url: site_url.trim_end_matches('/'),
     };
 
-    let item = match ddb_response.item {
-        Some(item) => item,
+    handle(&service, event).await
+}
+
+/// Serves a request for a user's stats with `service`.
+pub async fn handle<S: StorageBackend + Sync>(
+    service: &GetUserStats<'_, S>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let username = match event
+        .path_parameters_ref()
+        .and_then(|params| params.first("username"))
+    {
+        Some(value) => value,
         None => {
-            let resp = Response::builder()
-                .status(404)
+            // Return a 400 Bad Request response if username is missing.
+            return Ok(Response::builder()
+                .status(400)
                 .header("content-type", "text/html")
-                .body("User Does not exist in the database".into())
-                .map_err(Box::new)?;
-            return Ok(resp);
+                .body("Key 'username' is missing".into())
+                .map_err(Box::new)?);
         }
     };
 
-    let stats_json_gz = match item.get("stats.json.gz") {
-        Some(val) => val,
-        None => {
-            let resp = Response::builder()
-                .status(500)
+    let query = event.query_string_parameters_ref();
+    let param = |name: &str| query.and_then(|params| params.first(name));
+    let view = match View::from_request(event.uri().path(), param) {
+        Ok(Some(view)) => view,
+        Ok(None) => {
+            return Ok(Response::builder()
+                .status(404)
                 .header("content-type", "text/html")
-                .body("User found but no data".into())
-                .map_err(Box::new)?;
-            return Ok(resp);
+                .body("Not found".into())
+                .map_err(Box::new)?);
         }
-    };
-
-    let stats_json_gz = match stats_json_gz.as_b() {
-        Ok(val) => val,
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(400)
                 .header("content-type", "text/html")
-                .body("User found by data in unreadable format".into())
-                .map_err(Box::new)?;
-            return Ok(resp);
+                .body(e.into())
+                .map_err(Box::new)?);
         }
     };
 
-    let mut decoder = GzDecoder::new(stats_json_gz.as_ref());
-
-    let mut stats_json = String::new();
-
-    match decoder.read_to_string(&mut stats_json) {
-        Ok(_) => (),
-        Err(_) => {
-            let resp = Response::builder()
-                .status(500)
-                .body(format!("Error decompressing Gzipped JSON").into())
-                .map_err(Box::new)?;
-            return Ok(resp);
-        }
-    }
-
-    let resp = Response::builder()
-        .status(200)
-        .header("content-type", "application/json")
-        .body(stats_json.into())
-        .map_err(Box::new)?;
-    Ok(resp)
-}
-
-fn to_id<T: AsRef<str>>(text: T) -> String {
-    text.as_ref()
-        .to_lowercase()
-        .chars()
-        .filter(|c| c.is_ascii_alphanumeric())
-        .collect()
+    let resp = match service.get(username, view).await {
+        Ok(Output::Json(json)) => Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(json.into()),
+        Ok(Output::Atom(xml)) => Response::builder()
+            .status(200)
+            .header("content-type", "application/atom+xml; charset=utf-8")
+            .header("cache-control", CACHE_CONTROL)
+            .body(xml.into()),
+        Ok(Output::Svg(badge)) => Response::builder()
+            .status(200)
+            .header("content-type", "image/svg+xml")
+            .header("cache-control", CACHE_CONTROL)
+            .header("last-modified", http_date(badge.updated))
+            .body(badge.svg.into()),
+        Ok(Output::Png(png)) => Response::builder()
+            .status(200)
+            .header("content-type", "image/png")
+            .header("cache-control", CACHE_CONTROL)
+            .body(png.into()),
+        Ok(Output::Html(html)) => Response::builder()
+            .status(200)
+            .header("content-type", "text/html; charset=utf-8")
+            .header("cache-control", CACHE_CONTROL)
+            .body(html.into()),
+        Err(e) => Response::builder()
+            .status(e.status())
+            .header("content-type", "text/html")
+            .body(e.to_string().into()),
+    };
+    Ok(resp.map_err(Box::new)?)
 }
@@ -0,0 +1,15 @@
+//! Serves tracked users' stats, game logs, feeds, badges, charts and share pages.
+
+mod badge;
+mod chart;
+mod feed;
+mod http_handler;
+pub mod service;
+mod share;
+mod url;
+mod xml;
+
+pub use badge::Badge;
+pub use chart::ChartRequest;
+pub use feed::SITE_URL;
+pub use http_handler::{function_handler, handle};
@@ -0,0 +1,190 @@
+//! Looking up a user's stats, independent of how the request arrived and of where users are
+//! stored.
+
+use crate::badge::{self, Badge};
+use crate::chart::{self, ChartRequest};
+use crate::feed;
+use crate::share;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use user_stats_core::analysis::{GamesPlayed, InferredGame};
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::highlights::Highlights;
+use user_stats_core::model::User;
+use user_stats_core::storage::{StoreError, UserWithHighlights};
+use user_stats_core::user_id::{InvalidUserId, UserId};
+
+/// A user's stats as returned by the API: the stored user plus derived data.
+#[derive(Serialize)]
+struct UserStats<'a> {
+    #[serde(flatten)]
+    user: &'a User,
+    /// Games played between consecutive ratings with a win/loss/tie record, per format.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    games: HashMap<String, Vec<GamesPlayed>>,
+    /// Peaks, streaks and milestones per format.
+    highlights: HashMap<String, Highlights>,
+}
+
+/// The games inferred from a user's rating history, per format.
+#[derive(Serialize)]
+struct GameLog<'a> {
+    username: &'a str,
+    userid: &'a str,
+    formats: HashMap<String, Vec<InferredGame>>,
+}
+
+/// What a request asks for about the user.
+pub enum View {
+    /// `/user-stats/{username}`
+    Stats,
+    /// `/user-stats/{username}/games`
+    Games,
+    /// `/user-stats/{username}/feed.atom`
+    Feed,
+    /// `/user-stats/{username}/badge.svg?format=&points=`
+    Badge {
+        format: Option<String>,
+        points: usize,
+    },
+    /// `/user-stats/{username}/chart.png?formats=&from=&to=`
+    Chart(ChartRequest),
+    /// `/user-stats/{username}/share?format=`
+    Share { format: Option<String> },
+}
+
+/// A rendered view of a user.
+pub enum Output {
+    /// The stats or game log as JSON.
+    Json(String),
+    /// An Atom feed.
+    Atom(String),
+    Svg(Badge),
+    Png(Vec<u8>),
+    /// A page for sharing links to the user, with OpenGraph tags for previews.
+    Html(String),
+}
+
+/// Why a view of a user could not be rendered.
+#[derive(Debug)]
+pub enum GetUserStatsError {
+    /// The username cannot be a Showdown user, so it was not looked up.
+    InvalidUsername(InvalidUserId),
+    NotFound,
+    Load(StoreError),
+    Serialize(String),
+    /// The badge's or share page's format has no ratings.
+    NoRatingsInFormat,
+    /// None of the chart's formats have ratings in its time range.
+    NoRatingsInRange,
+    /// The chart could not be drawn.
+    Chart(String),
+}
+
+impl GetUserStatsError {
+    /// The HTTP status code the error is reported with.
+    pub fn status(&self) -> u16 {
+        match self {
+            GetUserStatsError::InvalidUsername(_) => 400,
+            GetUserStatsError::NotFound
+            | GetUserStatsError::NoRatingsInFormat
+            | GetUserStatsError::NoRatingsInRange => 404,
+            _ => 500,
+        }
+    }
+}
+
+impl fmt::Display for GetUserStatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetUserStatsError::InvalidUsername(e) => write!(f, "invalid username: {}", e),
+            GetUserStatsError::NotFound => write!(f, "User Does not exist in the database"),
+            GetUserStatsError::Load(StoreError::Dynamo(_) | StoreError::Io(_)) => {
+                write!(f, "database error")
+            }
+            GetUserStatsError::Load(StoreError::Corrupt(_) | StoreError::UnsupportedVersion(_)) => {
+                write!(f, "User found by data in unreadable format")
+            }
+            GetUserStatsError::Load(StoreError::Codec(_)) => {
+                write!(f, "Error decompressing Gzipped JSON")
+            }
+            GetUserStatsError::Serialize(_) => write!(f, "Error serializing user stats"),
+            GetUserStatsError::NoRatingsInFormat => {
+                write!(f, "User has no ratings in this format")
+            }
+            GetUserStatsError::NoRatingsInRange => {
+                write!(f, "User has no ratings in these formats and time range")
+            }
+            GetUserStatsError::Chart(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Renders views of tracked users.
+pub struct GetUserStats<'a, S> {
+    pub store: &'a S,
+    /// Base URL of the site that feeds and share pages link to, without a trailing slash.
+    pub site_url: &'a str,
+}
+
+impl<S: StorageBackend + Sync> GetUserStats<'_, S> {
+    /// Renders `view` of the user named `username`.
+    pub async fn get(&self, username: &str, view: View) -> Result<Output, GetUserStatsError> {
+        let id = UserId::parse(username).map_err(GetUserStatsError::InvalidUsername)?;
+
+        let (user, highlights) = match self.load(id.as_str()).await {
+            Ok(Some(val)) => val,
+            Ok(None) => return Err(GetUserStatsError::NotFound),
+            Err(e) => return Err(GetUserStatsError::Load(e)),
+        };
+
+        let serialized = match view {
+            View::Stats => serde_json::to_string(&UserStats {
+                user: &user,
+                games: user.games_played(),
+                highlights,
+            }),
+            View::Games => serde_json::to_string(&GameLog {
+                username: &user.username,
+                userid: &user.userid,
+                formats: user.inferred_games(),
+            }),
+            View::Feed => return Ok(Output::Atom(feed::atom(&user, self.site_url))),
+            View::Badge { format, points } => {
+                return match badge::render(&user, format.as_deref(), points) {
+                    Some(badge) => Ok(Output::Svg(badge)),
+                    None => Err(GetUserStatsError::NoRatingsInFormat),
+                };
+            }
+            View::Chart(request) => {
+                return match chart::png(&user, &request) {
+                    Some(Ok(png)) => Ok(Output::Png(png)),
+                    Some(Err(e)) => Err(GetUserStatsError::Chart(e)),
+                    None => Err(GetUserStatsError::NoRatingsInRange),
+                };
+            }
+            View::Share { format } => {
+                return match share::html(&user, format.as_deref(), self.site_url) {
+                    Some(html) => Ok(Output::Html(html)),
+                    None => Err(GetUserStatsError::NoRatingsInFormat),
+                };
+            }
+        };
+        serialized
+            .map(Output::Json)
+            .map_err(|e| GetUserStatsError::Serialize(e.to_string()))
+    }
+
+    /// Reads the user with id `user_id`, or the user it was merged into if it is an alias.
+    async fn load(&self, user_id: &str) -> Result<Option<UserWithHighlights>, StoreError> {
+        if let Some(found) = self.store.load_user_with_highlights(user_id).await? {
+            return Ok(Some(found));
+        }
+        let resolved = self.store.resolve(user_id).await?;
+        if resolved == user_id {
+            return Ok(None);
+        }
+        self.store.load_user_with_highlights(&resolved).await
+    }
+}
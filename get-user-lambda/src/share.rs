@@ -0,0 +1,95 @@
+use crate::badge::most_recent_format;
+use crate::chart;
+use crate::url::encode;
+use crate::xml::escape;
+use user_stats_core::model::User;
+
+/// Renders a page to share a link to the user's ratings. Social previews can't run the site's
+/// script, so the page names the user and their current elo in OpenGraph tags and uses a chart of
+/// the format as the preview image, then sends browsers on to the user's page on the site. Without
+/// a format, shows the format they were most recently rated in. Links point at the site at
+/// `site_url`. `None` when the user has no ratings in the format.
+pub(crate) fn html(user: &User, format: Option<&str>, site_url: &str) -> Option<String> {
+    let (format, ratings) = match format {
+        Some(format) => user.formats.get_key_value(format)?,
+        None => most_recent_format(user)?,
+    };
+    let latest = ratings.last()?;
+
+    let username = escape(&user.username);
+    let format_name = escape(format);
+    let title = format!("{}'s {} rating", username, format_name);
+    let description = match &latest.record {
+        Some(record) => format!(
+            "{} is rated {:.0} in {} with a ladder record of {}W {}L {}T.",
+            username, latest.elo, format_name, record.w, record.l, record.t
+        ),
+        None => format!(
+            "{} is rated {:.0} in {}.",
+            username, latest.elo, format_name
+        ),
+    };
+    let user_id = encode(&user.userid);
+    let format_id = encode(format);
+    let page = escape(&format!(
+        "{}/?user={}&format={}",
+        site_url, user_id, format_id
+    ));
+    let image = escape(&format!(
+        "{}/user-stats/{}/chart.png?formats={}",
+        site_url, user_id, format_id
+    ));
+    let url = escape(&format!(
+        "{}/user-stats/{}/share?format={}",
+        site_url, user_id, format_id
+    ));
+
+    let mut html = String::from("<!doctype html>\n<html lang=\"en\">\n  <head>\n");
+    html.push_str("    <meta charset=\"UTF-8\" />\n");
+    html.push_str(&format!("    <title>{}</title>\n", title));
+    html.push_str(&format!(
+        "    <meta property=\"og:title\" content=\"{}\" />\n",
+        title
+    ));
+    html.push_str(&format!(
+        "    <meta property=\"og:description\" content=\"{}\" />\n",
+        description
+    ));
+    html.push_str("    <meta property=\"og:type\" content=\"website\" />\n");
+    html.push_str(&format!(
+        "    <meta property=\"og:url\" content=\"{}\" />\n",
+        url
+    ));
+    html.push_str(&format!(
+        "    <meta property=\"og:image\" content=\"{}\" />\n",
+        image
+    ));
+    html.push_str(&format!(
+        "    <meta property=\"og:image:width\" content=\"{}\" />\n",
+        chart::WIDTH
+    ));
+    html.push_str(&format!(
+        "    <meta property=\"og:image:height\" content=\"{}\" />\n",
+        chart::HEIGHT
+    ));
+    html.push_str("    <meta name=\"twitter:card\" content=\"summary_large_image\" />\n");
+    html.push_str(&format!(
+        "    <meta property=\"twitter:image\" content=\"{}\" />\n",
+        image
+    ));
+    html.push_str(&format!(
+        "    <link rel=\"canonical\" href=\"{}\" />\n",
+        page
+    ));
+    html.push_str(&format!(
+        "    <meta http-equiv=\"refresh\" content=\"0; url={}\" />\n",
+        page
+    ));
+    html.push_str("  </head>\n  <body>\n");
+    html.push_str(&format!(
+        "    <p>{} <a href=\"{}\">See the chart</a>.</p>\n",
+        description, page
+    ));
+    html.push_str("  </body>\n</html>\n");
+    Some(html)
+}
@@ -0,0 +1,450 @@
+use aws_sdk_dynamodb::config::{BehaviorVersion, Region};
+use get_user_lambda::service::{GetUserStats, GetUserStatsError, Output, View};
+use get_user_lambda::{function_handler, handle, SITE_URL};
+use lambda_http::{Body, Request, RequestExt, Response};
+use std::collections::HashMap;
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::model::{Rating, Record, User};
+use user_stats_core::storage::StoreError;
+use user_stats_core::time::http_date;
+
+const LATEST: u64 = 1_700_000_000;
+
+/// Query parameters as name and value pairs.
+type Query<'a> = &'a [(&'a str, &'a str)];
+
+/// Makes the error a failing read returns.
+type Failure = fn() -> StoreError;
+
+/// Holds at most one user and any alias links to it, or fails every read.
+#[derive(Default)]
+struct FakeStore {
+    user: Option<User>,
+    /// Alias links, from user id to the user id it was merged into.
+    aliases: HashMap<String, String>,
+    fail_load: Option<Failure>,
+}
+
+impl StorageBackend for FakeStore {
+    async fn user_ids(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self.user.iter().map(|user| user.userid.clone()).collect())
+    }
+
+    async fn load_user(&self, user_id: &str) -> Result<Option<User>, StoreError> {
+        if let Some(error) = self.fail_load {
+            return Err(error());
+        }
+        Ok(self.user.clone().filter(|user| user.userid == user_id))
+    }
+
+    async fn alias_of(&self, user_id: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.aliases.get(user_id).cloned())
+    }
+
+    async fn save_user(&self, _user: &User) -> Result<(), StoreError> {
+        unimplemented!("reads never write")
+    }
+}
+
+fn store() -> FakeStore {
+    let rating = |time, elo, w| Rating::live(time, elo).with_record(Some(Record { w, l: 2, t: 0 }));
+    FakeStore {
+        user: Some(User {
+            username: "Some User".to_string(),
+            userid: "someuser".to_string(),
+            formats: HashMap::from([(
+                "gen9ou".to_string(),
+                vec![
+                    rating(LATEST - 200, 1400.0, 3),
+                    rating(LATEST - 100, 1420.0, 4),
+                    rating(LATEST, 1390.0, 4),
+                ],
+            )]),
+        }),
+        aliases: HashMap::new(),
+        fail_load: None,
+    }
+}
+
+fn get(path: &str, username: &str, query: &[(&str, &str)]) -> Request {
+    let query: HashMap<String, String> = query
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    lambda_http::http::Request::builder()
+        .uri(path)
+        .body(Body::Empty)
+        .unwrap()
+        .with_path_parameters(HashMap::from([(
+            "username".to_string(),
+            username.to_string(),
+        )]))
+        .with_query_string_parameters(query)
+}
+
+async fn respond(store: &FakeStore, request: Request) -> Response<Body> {
+    let service = GetUserStats {
+        store,
+        site_url: SITE_URL,
+    };
+    handle(&service, request).await.unwrap()
+}
+
+/// Requests `path` from a store holding the test user, returning the status and body.
+async fn status_and_body(store: &FakeStore, path: &str, query: &[(&str, &str)]) -> (u16, String) {
+    let resp = respond(store, get(path, "someuser", query)).await;
+    assert_eq!(header(&resp, "content-type"), Some("text/html"));
+    (resp.status().as_u16(), body(&resp))
+}
+
+fn body(resp: &Response<Body>) -> String {
+    String::from_utf8(resp.body().to_vec()).unwrap()
+}
+
+fn header<'a>(resp: &'a Response<Body>, name: &str) -> Option<&'a str> {
+    resp.headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn serves_stats() {
+    let resp = respond(&store(), get("/user-stats/SomeUser", "Some User", &[])).await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(header(&resp, "content-type"), Some("application/json"));
+    let stats: serde_json::Value = serde_json::from_str(&body(&resp)).unwrap();
+    assert_eq!(stats["userid"], "someuser");
+    assert_eq!(stats["formats"]["gen9ou"].as_array().unwrap().len(), 3);
+    assert!(stats["highlights"]["gen9ou"].is_object());
+    assert!(stats["games"]["gen9ou"].is_array());
+}
+
+#[tokio::test]
+async fn serves_users_under_names_merged_into_them() {
+    let store = FakeStore {
+        aliases: HashMap::from([
+            ("oldername".to_string(), "oldname".to_string()),
+            ("oldname".to_string(), "someuser".to_string()),
+        ]),
+        ..store()
+    };
+
+    let resp = respond(&store, get("/user-stats/oldername", "Older Name", &[])).await;
+
+    assert_eq!(resp.status(), 200);
+    let stats: serde_json::Value = serde_json::from_str(&body(&resp)).unwrap();
+    assert_eq!(stats["userid"], "someuser");
+    assert_eq!(stats["username"], "Some User");
+}
+
+#[tokio::test]
+async fn serves_the_game_log() {
+    let resp = respond(&store(), get("/user-stats/someuser/games", "someuser", &[])).await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(header(&resp, "content-type"), Some("application/json"));
+    let log: serde_json::Value = serde_json::from_str(&body(&resp)).unwrap();
+    assert_eq!(log["username"], "Some User");
+    assert!(log["formats"]["gen9ou"].is_array());
+}
+
+#[tokio::test]
+async fn serves_the_feed() {
+    let resp = respond(
+        &store(),
+        get("/user-stats/someuser/feed.atom", "someuser", &[]),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        header(&resp, "content-type"),
+        Some("application/atom+xml; charset=utf-8")
+    );
+    assert_eq!(header(&resp, "cache-control"), Some("public, max-age=300"));
+    assert!(body(&resp).contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+}
+
+#[tokio::test]
+async fn serves_badges() {
+    let resp = respond(
+        &store(),
+        get(
+            "/user-stats/someuser/badge.svg",
+            "someuser",
+            &[("format", "gen9ou"), ("points", "2")],
+        ),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(header(&resp, "content-type"), Some("image/svg+xml"));
+    assert_eq!(header(&resp, "last-modified"), Some(&*http_date(LATEST)));
+    assert!(body(&resp).starts_with("<svg"));
+}
+
+#[tokio::test]
+async fn serves_charts() {
+    let resp = respond(
+        &store(),
+        get("/user-stats/someuser/chart.png", "someuser", &[]),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(header(&resp, "content-type"), Some("image/png"));
+    assert!(resp.body().starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[tokio::test]
+async fn serves_share_pages_with_previews() {
+    let resp = respond(
+        &store(),
+        get(
+            "/user-stats/someuser/share",
+            "someuser",
+            &[("format", "Gen9 OU")],
+        ),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        header(&resp, "content-type"),
+        Some("text/html; charset=utf-8")
+    );
+    let html = body(&resp);
+    for tag in [
+        "<meta property=\"og:title\" content=\"Some User's gen9ou rating\" />",
+        "<meta property=\"og:description\" content=\"Some User is rated 1390 in gen9ou with a ladder record of 4W 2L 0T.\" />",
+        "<meta property=\"og:image\" content=\"https://pokemonshowdownuserstats.com/user-stats/someuser/chart.png?formats=gen9ou\" />",
+        "<meta http-equiv=\"refresh\" content=\"0; url=https://pokemonshowdownuserstats.com/?user=someuser&amp;format=gen9ou\" />",
+    ] {
+        assert!(html.contains(tag), "{} is missing {}", html, tag);
+    }
+}
+
+#[tokio::test]
+async fn links_point_at_the_configured_site_with_encoded_values() {
+    let mut store = store();
+    let user = store.user.as_mut().unwrap();
+    let ratings = user.formats.remove("gen9ou").unwrap();
+    user.formats.insert("gen9 ou&x".to_string(), ratings);
+    let service = GetUserStats {
+        store: &store,
+        site_url: "http://localhost:5173",
+    };
+
+    let feed = match service.get("someuser", View::Feed).await {
+        Ok(Output::Atom(xml)) => xml,
+        _ => panic!("expected a feed"),
+    };
+    assert!(feed.contains(
+        "<link href=\"http://localhost:5173/?user=someuser&amp;format=gen9%20ou%26x\"/>"
+    ));
+
+    let html = match service
+        .get(
+            "someuser",
+            View::Share {
+                format: Some("gen9 ou&x".to_string()),
+            },
+        )
+        .await
+    {
+        Ok(Output::Html(html)) => html,
+        _ => panic!("expected a share page"),
+    };
+    assert!(html.contains(
+        "<meta property=\"og:image\" content=\"http://localhost:5173/user-stats/someuser/chart.png?formats=gen9%20ou%26x\" />"
+    ));
+}
+
+#[tokio::test]
+async fn rejects_requests_without_a_username() {
+    let resp = respond(&store(), Request::default()).await;
+
+    assert_eq!(resp.status(), 400);
+    assert_eq!(body(&resp), "Key 'username' is missing");
+}
+
+#[tokio::test]
+async fn rejects_unknown_paths() {
+    assert_eq!(
+        status_and_body(&store(), "/user-stats/someuser/unknown", &[]).await,
+        (404, "Not found".to_string())
+    );
+}
+
+#[tokio::test]
+async fn rejects_invalid_parameters() {
+    let cases: [(&str, Query, &str); 4] = [
+        (
+            "/user-stats/someuser/badge.svg",
+            &[("points", "1")],
+            "points must be a number from 2 to 100",
+        ),
+        (
+            "/user-stats/someuser/chart.png",
+            &[("from", "yesterday")],
+            "from must be a unix time in seconds",
+        ),
+        (
+            "/user-stats/someuser/chart.png",
+            &[("from", "10"), ("to", "10")],
+            "from must be before to",
+        ),
+        (
+            "/user-stats/someuser/chart.png",
+            &[("formats", "a,b,c,d,e,f,g,h,i")],
+            "at most 8 formats can be charted",
+        ),
+    ];
+    for (path, query, message) in cases {
+        assert_eq!(
+            status_and_body(&store(), path, query).await,
+            (400, message.to_string())
+        );
+    }
+}
+
+#[tokio::test]
+async fn rejects_usernames_without_an_id() {
+    let resp = respond(&store(), get("/user-stats/%21%21", "!!", &[])).await;
+
+    assert_eq!(resp.status(), 400);
+    assert_eq!(
+        body(&resp),
+        "invalid username: username must contain a letter or digit"
+    );
+}
+
+#[tokio::test]
+async fn rejects_usernames_longer_than_showdown_allows() {
+    let username = "someuserwithaverylongname";
+    let resp = respond(
+        &FakeStore {
+            fail_load: Some(|| panic!("looked up an invalid username")),
+            ..FakeStore::default()
+        },
+        get(&format!("/user-stats/{}", username), username, &[]),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 400);
+    assert_eq!(
+        body(&resp),
+        "invalid username: username is 25 characters long without spaces and symbols, at most 18 are allowed"
+    );
+}
+
+#[tokio::test]
+async fn reports_untracked_users() {
+    assert_eq!(
+        status_and_body(&FakeStore::default(), "/user-stats/someuser", &[]).await,
+        (404, "User Does not exist in the database".to_string())
+    );
+}
+
+#[tokio::test]
+async fn reports_views_without_ratings() {
+    assert_eq!(
+        status_and_body(
+            &store(),
+            "/user-stats/someuser/badge.svg",
+            &[("format", "gen1ou")]
+        )
+        .await,
+        (404, "User has no ratings in this format".to_string())
+    );
+    assert_eq!(
+        status_and_body(
+            &store(),
+            "/user-stats/someuser/share",
+            &[("format", "gen1ou")]
+        )
+        .await,
+        (404, "User has no ratings in this format".to_string())
+    );
+    assert_eq!(
+        status_and_body(
+            &store(),
+            "/user-stats/someuser/chart.png",
+            &[("to", "1000")]
+        )
+        .await,
+        (
+            404,
+            "User has no ratings in these formats and time range".to_string()
+        )
+    );
+}
+
+#[tokio::test]
+async fn reports_store_errors() {
+    let cases: [(Failure, &str); 5] = [
+        (|| StoreError::Dynamo("throttled".into()), "database error"),
+        (|| StoreError::Io("disk full".into()), "database error"),
+        (
+            || StoreError::Corrupt("missing blob".into()),
+            "User found by data in unreadable format",
+        ),
+        (
+            || StoreError::UnsupportedVersion(9),
+            "User found by data in unreadable format",
+        ),
+        (
+            || StoreError::Codec("bad gzip".into()),
+            "Error decompressing Gzipped JSON",
+        ),
+    ];
+    for (error, message) in cases {
+        let store = FakeStore {
+            fail_load: Some(error),
+            ..store()
+        };
+        assert_eq!(
+            status_and_body(&store, "/user-stats/someuser", &[]).await,
+            (500, message.to_string())
+        );
+    }
+}
+
+#[tokio::test]
+async fn service_errors_carry_their_status() {
+    let store = FakeStore::default();
+    let service = GetUserStats {
+        store: &store,
+        site_url: SITE_URL,
+    };
+
+    let error = match service.get("someuser", View::Stats).await {
+        Ok(Output::Json(json)) => panic!("unexpected stats: {}", json),
+        Ok(_) => panic!("unexpected output"),
+        Err(e) => e,
+    };
+
+    assert!(matches!(error, GetUserStatsError::NotFound));
+    assert_eq!(error.status(), 404);
+}
+
+#[tokio::test]
+async fn reports_a_missing_table_name() {
+    std::env::remove_var("USER_STATS_TABLE");
+    let config = aws_sdk_dynamodb::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .build();
+    let ddb = aws_sdk_dynamodb::Client::from_conf(config);
+
+    let resp = function_handler(&ddb, get("/user-stats/someuser", "someuser", &[]))
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), 500);
+    assert_eq!(
+        body(&resp),
+        "Failed to get USER_STATS_TABLE from environment"
+    );
+}
@@ -30,6 +30,9 @@ export class InfrastructureStack extends cdk.Stack {
   constructor(scope: Construct, id: string, props: InfrastructureStackProps) {
     super(scope, id, props);
 
+    // Feeds, share pages and Discord notifications link back to the site.
+    const siteUrl = 'https://pokemonshowdownuserstats.com';
+
     const addUserLambda = new lambda.Function(this, "AddUser", {
       runtime: lambda.Runtime.PROVIDED_AL2023,
       handler: "does.not.matter",
@@ -99,8 +102,10 @@ export class InfrastructureStack extends cdk.Stack {
       vpc: updateStatsVpc,
     });
 
-    const updateStatsDockerImage
-      = ecs.ContainerImage.fromAsset(path.join(__dirname, '../../update-stats'));
+    // Built from the repository root so the image can include the shared user-stats-core crate.
+    const updateStatsDockerImage = ecs.ContainerImage.fromAsset(path.join(__dirname, '../..'), {
+      file: 'update-stats/Dockerfile',
+    });
 
     const updateStatsTaskDefinition = new ecs.FargateTaskDefinition(this, 'UpdateStatsTaskDef', {
       memoryLimitMiB: 512,
@@ -119,16 +124,32 @@ export class InfrastructureStack extends cdk.Stack {
     const updateStatsContainer = updateStatsTaskDefinition.addContainer('UpdateStatsContainer', {
       image: updateStatsDockerImage,
       logging: updateStatsLogDriver,
+      // Give the updater time to finish in-flight writes and checkpoint after SIGTERM.
+      stopTimeout: cdk.Duration.seconds(120),
+    });
+
+    const updateStatsStateTable = new dynamodb.Table(this, 'UpdateStatsStateTable', {
+      partitionKey: { name: 'stateId', type: dynamodb.AttributeType.STRING },
     });
 
     updateStatsContainer.addEnvironment('USER_STATS_TABLE', userStatsTable.tableName);
+    updateStatsContainer.addEnvironment('UPDATE_STATS_STATE_TABLE', updateStatsStateTable.tableName);
+    // Tasks lease parallel scan segments in turn, sweeping each once before moving to the next,
+    // so one task per segment sweeps them all in parallel.
+    const updateStatsSegments = 1;
+    updateStatsContainer.addEnvironment('UPDATE_STATS_TOTAL_SEGMENTS', `${updateStatsSegments}`);
+    // The alarms below watch metrics in this namespace.
+    const updateStatsMetricsNamespace = 'UpdateStats';
+    updateStatsContainer.addEnvironment('UPDATE_STATS_METRICS_NAMESPACE', updateStatsMetricsNamespace);
+    updateStatsContainer.addEnvironment('UPDATE_STATS_SITE_URL', siteUrl);
 
     userStatsTable.grantReadWriteData(updateStatsContainer.taskDefinition.taskRole);
+    updateStatsStateTable.grantReadWriteData(updateStatsContainer.taskDefinition.taskRole);
 
     const updateStatsFargateService = new ecs.FargateService(this, 'UpdateStatsFargateService', {
       cluster: updateStatsCluster,
       taskDefinition: updateStatsTaskDefinition,
-      desiredCount: 1,
+      desiredCount: updateStatsSegments,
       assignPublicIp: true,
     });
 
@@ -143,6 +164,7 @@ export class InfrastructureStack extends cdk.Stack {
     getUserLambda.currentVersion.applyRemovalPolicy(cdk.RemovalPolicy.DESTROY);
 
     getUserLambda.addEnvironment('USER_STATS_TABLE', userStatsTable.tableName);
+    getUserLambda.addEnvironment('SITE_URL', siteUrl);
     userStatsTable.grantReadWriteData(getUserLambda);
 
     const getUserLambdaIntegration = new integrations.HttpLambdaIntegration(
@@ -156,6 +178,36 @@ export class InfrastructureStack extends cdk.Stack {
       integration: getUserLambdaIntegration,
     });
 
+    userStatsApi.addRoutes({
+      path: `${userStatsApiPath}/games`,
+      methods: [apigatewayv2.HttpMethod.GET],
+      integration: getUserLambdaIntegration,
+    });
+
+    userStatsApi.addRoutes({
+      path: `${userStatsApiPath}/feed.atom`,
+      methods: [apigatewayv2.HttpMethod.GET],
+      integration: getUserLambdaIntegration,
+    });
+
+    userStatsApi.addRoutes({
+      path: `${userStatsApiPath}/badge.svg`,
+      methods: [apigatewayv2.HttpMethod.GET],
+      integration: getUserLambdaIntegration,
+    });
+
+    userStatsApi.addRoutes({
+      path: `${userStatsApiPath}/chart.png`,
+      methods: [apigatewayv2.HttpMethod.GET],
+      integration: getUserLambdaIntegration,
+    });
+
+    userStatsApi.addRoutes({
+      path: `${userStatsApiPath}/share`,
+      methods: [apigatewayv2.HttpMethod.GET],
+      integration: getUserLambdaIntegration,
+    });
+
     const websiteBucket = new s3.Bucket(this, 'WebsiteBucket');
 
     const oai = new cloudfront.OriginAccessIdentity(this, 'OAI');
@@ -174,7 +226,14 @@ export class InfrastructureStack extends cdk.Stack {
       maxTtl: cdk.Duration.seconds(60),
       cookieBehavior: cloudfront.CacheCookieBehavior.none(),
       headerBehavior: cloudfront.CacheHeaderBehavior.none(),
-      queryStringBehavior: cloudfront.CacheQueryStringBehavior.none(),
+      // Badges and charts are rendered from their query string, so it must be part of the key.
+      queryStringBehavior: cloudfront.CacheQueryStringBehavior.allowList(
+        'format',
+        'formats',
+        'points',
+        'from',
+        'to'
+      ),
     });
 
     const distribution = new cloudfront.Distribution(this, 'ApiDistribution', {
@@ -288,7 +347,7 @@ export class InfrastructureStack extends cdk.Stack {
 
     // New WaitTime Alarm for the ECS Container Metric
     const waitTimeMetric = new cloudwatch.Metric({
-      namespace: 'UpdateStats', // Matches your Rust code
+      namespace: updateStatsMetricsNamespace,
       metricName: 'wait_time',  // Matches your Rust code
       unit: cloudwatch.Unit.MILLISECONDS, // Matches your Rust code
       statistic: 'Minimum',     // Aggregates over the period; adjust if needed
@@ -309,6 +368,28 @@ export class InfrastructureStack extends cdk.Stack {
     // Reuse the existing SNS topic for notifications
     waitTimeAlarm.addAlarmAction(new actions.SnsAction(alarmTopic));
 
+    // Users whose stored data could not be read are quarantined by update-stats and stop updating
+    // until they are repaired with the user-stats-admin repair tool.
+    const quarantinedItemsMetric = new cloudwatch.Metric({
+      namespace: updateStatsMetricsNamespace,
+      metricName: 'quarantined_items',
+      unit: cloudwatch.Unit.COUNT,
+      statistic: 'Sum',
+      period: cdk.Duration.minutes(5),
+    });
+
+    const quarantinedItemsAlarm = new cloudwatch.Alarm(this, 'QuarantinedItemsAlarm', {
+      alarmName: `UpdateStats-QuarantinedItems`,
+      alarmDescription: 'Alarm triggers when update-stats quarantines a user with unreadable stored data.',
+      metric: quarantinedItemsMetric,
+      threshold: 0,
+      comparisonOperator: cloudwatch.ComparisonOperator.GREATER_THAN_THRESHOLD,
+      evaluationPeriods: 1,
+      treatMissingData: cloudwatch.TreatMissingData.NOT_BREACHING,
+    });
+
+    quarantinedItemsAlarm.addAlarmAction(new actions.SnsAction(alarmTopic));
+
     new cdk.CfnOutput(this, 'UserStatsApiUrl', {
       value: userStatsApi.apiEndpoint,
       description: 'The endpoint URL of the UserStatsAp HTTP API',
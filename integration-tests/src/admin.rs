@@ -0,0 +1,32 @@
+//! Runs the real `user-stats-admin` tools against the stand-ins.
+
+use crate::dynamo::FakeDynamo;
+use crate::showdown::MockShowdown;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::OnceLock;
+
+/// Runs the `repair` tool with `args` against the `table` in `dynamo`, fetching current ratings
+/// from `showdown`. Nothing else is inherited from the environment.
+pub async fn run_repair(
+    dynamo: &FakeDynamo,
+    showdown: &MockShowdown,
+    table: &str,
+    args: &[&str],
+) -> Output {
+    let output = crate::command(repair(), dynamo)
+        .args(args)
+        .env("USER_STATS_TABLE", table)
+        .env("SHOWDOWN_URL", showdown.url())
+        .output()
+        .await
+        .expect("unable to run repair");
+    println!("{}", String::from_utf8_lossy(&output.stdout));
+    output
+}
+
+/// The `repair` binary, built once per test run.
+fn repair() -> &'static Path {
+    static BINARY: OnceLock<PathBuf> = OnceLock::new();
+    BINARY.get_or_init(|| crate::build("user-stats-admin", "repair"))
+}
@@ -0,0 +1,630 @@
+//! An in-memory stand-in for the DynamoDB operations the project uses, speaking the same JSON
+//! protocol so the real SDK client can be pointed at it.
+//!
+//! Only the subset of expressions the project writes is understood: conditions and filters made of
+//! `attribute_exists`, `attribute_not_exists`, `contains` and comparison clauses joined by `AND`
+//! and `OR`, each optionally negated with `NOT`, and `SET` update expressions assigning values. Anything else is
+//! rejected with a `ValidationException`, as are placeholders that are defined but not used and
+//! items over DynamoDB's size limit, so code that real DynamoDB would reject fails here too.
+
+use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// An item as sent over the wire: attribute names mapped to typed values such as `{"S": "..."}`.
+pub type Item = Map<String, Value>;
+
+const TARGET_PREFIX: &str = "DynamoDB_20120810.";
+/// DynamoDB rejects items larger than 400 KB.
+const MAX_ITEM_BYTES: usize = 400 * 1024;
+/// BatchGetItem accepts at most 100 keys per request.
+const MAX_BATCH_GET_KEYS: usize = 100;
+
+struct Table {
+    /// Name of the string hash key attribute.
+    key: String,
+    items: BTreeMap<String, Item>,
+}
+
+type Tables = Arc<Mutex<HashMap<String, Table>>>;
+
+/// An error reply, named after the DynamoDB exception it stands in for.
+struct DynamoError {
+    kind: &'static str,
+    message: String,
+}
+
+impl DynamoError {
+    fn validation(message: impl Into<String>) -> DynamoError {
+        DynamoError {
+            kind: "ValidationException",
+            message: message.into(),
+        }
+    }
+}
+
+pub struct FakeDynamo {
+    url: String,
+    tables: Tables,
+}
+
+impl FakeDynamo {
+    /// Starts serving empty `tables`, given as table name and hash key attribute pairs. Only
+    /// string hash keys without sort keys are supported.
+    pub async fn start(tables: &[(&str, &str)]) -> FakeDynamo {
+        let tables: Tables = Arc::new(Mutex::new(
+            tables
+                .iter()
+                .map(|(name, key)| {
+                    let table = Table {
+                        key: key.to_string(),
+                        items: BTreeMap::new(),
+                    };
+                    (name.to_string(), table)
+                })
+                .collect(),
+        ));
+        let app = Router::new()
+            .route("/", post(handle))
+            .with_state(tables.clone());
+        FakeDynamo {
+            url: crate::serve(app).await,
+            tables,
+        }
+    }
+
+    /// Endpoint URL to configure clients with.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A client sending every request to this stand-in with dummy credentials.
+    pub fn client(&self) -> aws_sdk_dynamodb::Client {
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(&self.url)
+            .build();
+        aws_sdk_dynamodb::Client::from_conf(config)
+    }
+
+    /// Every item in `table`, keyed by hash key.
+    pub fn items(&self, table: &str) -> BTreeMap<String, Item> {
+        let tables = self.tables.lock().unwrap();
+        tables[table].items.clone()
+    }
+
+    /// Hash keys of every item in `table`, in order.
+    pub fn keys(&self, table: &str) -> Vec<String> {
+        self.items(table).into_keys().collect()
+    }
+
+    /// The decoded value of the binary attribute `attribute` of the item keyed `key`.
+    pub fn binary(&self, table: &str, key: &str, attribute: &str) -> Option<Vec<u8>> {
+        let items = self.items(table);
+        let encoded = items.get(key)?.get(attribute)?.get("B")?.as_str()?;
+        Some(
+            STANDARD
+                .decode(encoded)
+                .expect("binary attributes are stored as base64"),
+        )
+    }
+}
+
+async fn handle(State(tables): State<Tables>, headers: HeaderMap, body: Bytes) -> Response {
+    let operation = headers
+        .get("x-amz-target")
+        .and_then(|target| target.to_str().ok())
+        .and_then(|target| target.strip_prefix(TARGET_PREFIX))
+        .unwrap_or_default()
+        .to_string();
+    let result = match serde_json::from_slice::<Value>(&body) {
+        Ok(request) => {
+            let mut tables = tables.lock().unwrap();
+            dispatch(&mut tables, &operation, &request)
+        }
+        Err(e) => Err(DynamoError {
+            kind: "SerializationException",
+            message: e.to_string(),
+        }),
+    };
+    let (status, body) = match result {
+        Ok(body) => (StatusCode::OK, body),
+        Err(e) => {
+            println!("Fake DynamoDB rejected {}: {}", operation, e.message);
+            let body = json!({
+                "__type": format!("com.amazonaws.dynamodb.v20120810#{}", e.kind),
+                "message": e.message,
+            });
+            (StatusCode::BAD_REQUEST, body)
+        }
+    };
+    (
+        status,
+        [("content-type", "application/x-amz-json-1.0")],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+fn dispatch(
+    tables: &mut HashMap<String, Table>,
+    operation: &str,
+    request: &Value,
+) -> Result<Value, DynamoError> {
+    check_placeholders(request)?;
+    match operation {
+        "GetItem" => get_item(tables, request),
+        "PutItem" => put_item(tables, request),
+        "DeleteItem" => delete_item(tables, request),
+        "UpdateItem" => update_item(tables, request),
+        "Scan" => scan(tables, request),
+        "BatchGetItem" => batch_get_item(tables, request),
+        "TransactWriteItems" => transact_write_items(tables, request),
+        other => Err(DynamoError {
+            kind: "UnknownOperationException",
+            message: format!("the fake does not support '{}'", other),
+        }),
+    }
+}
+
+fn get_item(tables: &mut HashMap<String, Table>, request: &Value) -> Result<Value, DynamoError> {
+    let table = table(tables, &request["TableName"])?;
+    let key = key_of(table, &request["Key"])?;
+    match table.items.get(&key) {
+        Some(item) => Ok(json!({ "Item": item })),
+        None => Ok(json!({})),
+    }
+}
+
+fn put_item(tables: &mut HashMap<String, Table>, request: &Value) -> Result<Value, DynamoError> {
+    let table = table(tables, &request["TableName"])?;
+    let item = object(&request["Item"], "Item")?.clone();
+    let key = key_of(table, &request["Item"])?;
+    check_condition(request, table.items.get(&key))?;
+    check_size(&item)?;
+    table.items.insert(key, item);
+    Ok(json!({}))
+}
+
+fn delete_item(tables: &mut HashMap<String, Table>, request: &Value) -> Result<Value, DynamoError> {
+    let table = table(tables, &request["TableName"])?;
+    let key = key_of(table, &request["Key"])?;
+    check_condition(request, table.items.get(&key))?;
+    table.items.remove(&key);
+    Ok(json!({}))
+}
+
+fn update_item(tables: &mut HashMap<String, Table>, request: &Value) -> Result<Value, DynamoError> {
+    let table = table(tables, &request["TableName"])?;
+    let key = key_of(table, &request["Key"])?;
+    check_condition(request, table.items.get(&key))?;
+    let mut item = match table.items.get(&key) {
+        Some(item) => item.clone(),
+        None => object(&request["Key"], "Key")?.clone(),
+    };
+    let expression = request["UpdateExpression"]
+        .as_str()
+        .ok_or_else(|| DynamoError::validation("UpdateExpression is required"))?;
+    let assignments = expression
+        .strip_prefix("SET ")
+        .ok_or_else(|| unsupported(expression))?;
+    for assignment in assignments.split(',') {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| unsupported(assignment))?;
+        let name = attribute_name(request, name.trim())?;
+        let value = attribute_value(request, value.trim())?;
+        item.insert(name, value.clone());
+    }
+    check_size(&item)?;
+    table.items.insert(key, item);
+    Ok(json!({}))
+}
+
+fn scan(tables: &mut HashMap<String, Table>, request: &Value) -> Result<Value, DynamoError> {
+    let table = table(tables, &request["TableName"])?;
+    let limit = request["Limit"].as_u64().map_or(usize::MAX, |n| n as usize);
+    let segment = match (
+        request["Segment"].as_u64(),
+        request["TotalSegments"].as_u64(),
+    ) {
+        (Some(segment), Some(total)) if segment < total => Some((segment, total)),
+        (None, None) => None,
+        _ => return Err(DynamoError::validation("invalid Segment or TotalSegments")),
+    };
+    let start = match request.get("ExclusiveStartKey") {
+        Some(start) => Some(key_of(table, start)?),
+        None => None,
+    };
+
+    let mut scanned = Vec::new();
+    for (key, item) in &table.items {
+        if scanned.len() == limit {
+            break;
+        }
+        let after_start = start.as_ref().is_none_or(|start| key > start);
+        let in_segment = segment.is_none_or(|(segment, total)| hash(key) % total == segment);
+        if after_start && in_segment {
+            scanned.push((key, item));
+        }
+    }
+    let mut items = Vec::new();
+    for (_, item) in &scanned {
+        let matches = match request["FilterExpression"].as_str() {
+            Some(filter) => evaluate(request, filter, Some(item))?,
+            None => true,
+        };
+        if matches {
+            items.push(Value::Object((*item).clone()));
+        }
+    }
+
+    let mut response = json!({
+        "Count": items.len(),
+        "ScannedCount": scanned.len(),
+        "Items": items,
+    });
+    // Like DynamoDB, a page that reaches the limit always says where to resume, even if nothing
+    // is left.
+    if let Some((key, _)) = scanned.last().filter(|_| scanned.len() == limit) {
+        response["LastEvaluatedKey"] = json!({ table.key.as_str(): { "S": key } });
+    }
+    Ok(response)
+}
+
+fn batch_get_item(
+    tables: &mut HashMap<String, Table>,
+    request: &Value,
+) -> Result<Value, DynamoError> {
+    let request_items = object(&request["RequestItems"], "RequestItems")?;
+    let requested: usize = request_items
+        .values()
+        .map(|request| request["Keys"].as_array().map_or(0, Vec::len))
+        .sum();
+    if requested > MAX_BATCH_GET_KEYS {
+        return Err(DynamoError::validation(
+            "Too many items requested for the BatchGetItem call",
+        ));
+    }
+    let mut responses = Map::new();
+    for (name, keys) in request_items {
+        let table = table(tables, &Value::String(name.clone()))?;
+        let mut items = Vec::new();
+        for key in keys["Keys"].as_array().into_iter().flatten() {
+            if let Some(item) = table.items.get(&key_of(table, key)?) {
+                items.push(Value::Object(item.clone()));
+            }
+        }
+        responses.insert(name.clone(), Value::Array(items));
+    }
+    Ok(json!({ "Responses": responses, "UnprocessedKeys": {} }))
+}
+
+/// Applies `ConditionCheck`, `Put`, `Update` and `Delete` actions all or nothing: if any condition
+/// fails, nothing is written and the transaction is cancelled.
+fn transact_write_items(
+    tables: &mut HashMap<String, Table>,
+    request: &Value,
+) -> Result<Value, DynamoError> {
+    let actions = request["TransactItems"]
+        .as_array()
+        .ok_or_else(|| DynamoError::validation("TransactItems is required"))?;
+    let mut writes = Vec::new();
+    let mut reasons = Vec::new();
+    for action in actions {
+        let (kind, action) = object(action, "TransactItem")?
+            .iter()
+            .next()
+            .ok_or_else(|| DynamoError::validation("TransactItem must have one action"))?;
+        check_placeholders(action)?;
+        let table = table(tables, &action["TableName"])?;
+        let key = match kind.as_str() {
+            "Put" => key_of(table, &action["Item"])?,
+            "ConditionCheck" | "Update" | "Delete" => key_of(table, &action["Key"])?,
+            other => return Err(unsupported(other)),
+        };
+        let holds = match action["ConditionExpression"].as_str() {
+            Some(condition) => evaluate(action, condition, table.items.get(&key))?,
+            None => true,
+        };
+        reasons.push(if holds {
+            "None"
+        } else {
+            "ConditionalCheckFailed"
+        });
+        writes.push((kind.as_str(), action));
+    }
+    if reasons.iter().any(|reason| *reason != "None") {
+        return Err(DynamoError {
+            kind: "TransactionCanceledException",
+            message: format!(
+                "Transaction cancelled, please refer cancellation reasons for specific reasons [{}]",
+                reasons.join(", ")
+            ),
+        });
+    }
+    for (kind, action) in writes {
+        match kind {
+            "Put" => put_item(tables, action)?,
+            "Update" => update_item(tables, action)?,
+            "Delete" => delete_item(tables, action)?,
+            _ => continue,
+        };
+    }
+    Ok(json!({}))
+}
+
+fn table<'a>(
+    tables: &'a mut HashMap<String, Table>,
+    name: &Value,
+) -> Result<&'a mut Table, DynamoError> {
+    name.as_str()
+        .and_then(|name| tables.get_mut(name))
+        .ok_or_else(|| DynamoError {
+            kind: "ResourceNotFoundException",
+            message: "Requested resource not found".to_string(),
+        })
+}
+
+fn object<'a>(value: &'a Value, name: &str) -> Result<&'a Item, DynamoError> {
+    value
+        .as_object()
+        .ok_or_else(|| DynamoError::validation(format!("{} is required", name)))
+}
+
+/// The string hash key of `item`, which may be a whole item or just its key attributes.
+fn key_of(table: &Table, item: &Value) -> Result<String, DynamoError> {
+    item[table.key.as_str()]["S"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| {
+            DynamoError::validation(format!("missing string key attribute '{}'", table.key))
+        })
+}
+
+/// Rejects the request if its `ConditionExpression` does not hold for `item`.
+fn check_condition(request: &Value, item: Option<&Item>) -> Result<(), DynamoError> {
+    let Some(condition) = request["ConditionExpression"].as_str() else {
+        return Ok(());
+    };
+    if evaluate(request, condition, item)? {
+        Ok(())
+    } else {
+        Err(DynamoError {
+            kind: "ConditionalCheckFailedException",
+            message: "The conditional request failed".to_string(),
+        })
+    }
+}
+
+fn evaluate(request: &Value, expression: &str, item: Option<&Item>) -> Result<bool, DynamoError> {
+    for alternative in expression.split(" OR ") {
+        if evaluate_all(request, alternative, item)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Evaluates clauses joined by `AND`.
+fn evaluate_all(
+    request: &Value,
+    expression: &str,
+    item: Option<&Item>,
+) -> Result<bool, DynamoError> {
+    for clause in expression.split(" AND ") {
+        let clause = clause.trim();
+        let (negated, clause) = match clause.strip_prefix("NOT ") {
+            Some(rest) => (true, rest.trim()),
+            None => (false, clause),
+        };
+        if evaluate_clause(request, clause, item)? == negated {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn evaluate_clause(
+    request: &Value,
+    clause: &str,
+    item: Option<&Item>,
+) -> Result<bool, DynamoError> {
+    if let Some(holds) = evaluate_comparison(request, clause, item)? {
+        return Ok(holds);
+    }
+    let (function, args) = clause
+        .strip_suffix(')')
+        .and_then(|clause| clause.split_once('('))
+        .ok_or_else(|| unsupported(clause))?;
+    let args: Vec<&str> = args.split(',').map(str::trim).collect();
+    let attribute = |index: usize| -> Result<Option<&Value>, DynamoError> {
+        let name = args.get(index).ok_or_else(|| unsupported(clause))?;
+        let name = attribute_name(request, name)?;
+        Ok(item.and_then(|item| item.get(&name)))
+    };
+    match (function, args.len()) {
+        ("attribute_exists", 1) => Ok(attribute(0)?.is_some()),
+        ("attribute_not_exists", 1) => Ok(attribute(0)?.is_none()),
+        ("contains", 2) => {
+            let needle = attribute_value(request, args[1])?["S"]
+                .as_str()
+                .ok_or_else(|| unsupported(clause))?;
+            Ok(attribute(0)?
+                .and_then(|value| value["S"].as_str())
+                .is_some_and(|value| value.contains(needle)))
+        }
+        _ => Err(unsupported(clause)),
+    }
+}
+
+/// Evaluates `operand <op> operand`, or returns `None` if `clause` is not a comparison. Like
+/// DynamoDB, comparing a missing attribute or values of different types never holds.
+fn evaluate_comparison(
+    request: &Value,
+    clause: &str,
+    item: Option<&Item>,
+) -> Result<Option<bool>, DynamoError> {
+    let parts: Vec<&str> = clause.split_whitespace().collect();
+    let [left, operator, right] = parts[..] else {
+        return Ok(None);
+    };
+    let operand = |operand: &str| -> Result<Option<Value>, DynamoError> {
+        if operand.starts_with(':') {
+            return attribute_value(request, operand).map(|value| Some(value.clone()));
+        }
+        let name = attribute_name(request, operand)?;
+        Ok(item.and_then(|item| item.get(&name)).cloned())
+    };
+    let (Some(left), Some(right)) = (operand(left)?, operand(right)?) else {
+        return Ok(Some(false));
+    };
+    let ordering = match (
+        left.get("N").and_then(Value::as_str),
+        right.get("N").and_then(Value::as_str),
+        left.get("S").and_then(Value::as_str),
+        right.get("S").and_then(Value::as_str),
+    ) {
+        (Some(left), Some(right), _, _) => {
+            let parse = |n: &str| {
+                n.parse::<f64>()
+                    .map_err(|_| DynamoError::validation(format!("invalid number '{}'", n)))
+            };
+            parse(left)?.partial_cmp(&parse(right)?)
+        }
+        (_, _, Some(left), Some(right)) => Some(left.cmp(right)),
+        _ => None,
+    };
+    let Some(ordering) = ordering else {
+        return Ok(Some(false));
+    };
+    let holds = match operator {
+        "=" => ordering.is_eq(),
+        "<>" => ordering.is_ne(),
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        ">=" => ordering.is_ge(),
+        _ => return Err(unsupported(clause)),
+    };
+    Ok(Some(holds))
+}
+
+fn attribute_name(request: &Value, name: &str) -> Result<String, DynamoError> {
+    if !name.starts_with('#') {
+        return Ok(name.to_string());
+    }
+    request["ExpressionAttributeNames"][name]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| DynamoError::validation(format!("undefined attribute name {}", name)))
+}
+
+fn attribute_value<'a>(request: &'a Value, name: &str) -> Result<&'a Value, DynamoError> {
+    request["ExpressionAttributeValues"]
+        .get(name)
+        .ok_or_else(|| DynamoError::validation(format!("undefined attribute value {}", name)))
+}
+
+fn unsupported(expression: &str) -> DynamoError {
+    DynamoError::validation(format!(
+        "the fake does not support the expression '{}'",
+        expression
+    ))
+}
+
+/// Rejects `#name` and `:value` placeholders that are defined but unused, as DynamoDB does.
+fn check_placeholders(request: &Value) -> Result<(), DynamoError> {
+    let mut used = BTreeSet::new();
+    for expression in [
+        "ConditionExpression",
+        "FilterExpression",
+        "UpdateExpression",
+        "ProjectionExpression",
+        "KeyConditionExpression",
+    ] {
+        let Some(expression) = request[expression].as_str() else {
+            continue;
+        };
+        let mut chars = expression.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c != '#' && c != ':' {
+                continue;
+            }
+            let mut end = start + 1;
+            while let Some((index, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || *c == '_') {
+                    break;
+                }
+                end = index + c.len_utf8();
+                chars.next();
+            }
+            used.insert(&expression[start..end]);
+        }
+    }
+    for field in ["ExpressionAttributeNames", "ExpressionAttributeValues"] {
+        let defined = request[field].as_object().into_iter().flat_map(Map::keys);
+        let unused: Vec<&String> = defined
+            .filter(|name| !used.contains(name.as_str()))
+            .collect();
+        if !unused.is_empty() {
+            return Err(DynamoError::validation(format!(
+                "Value provided in {} unused in expressions: keys: {:?}",
+                field, unused
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Approximates an item's size the way DynamoDB counts it: attribute names plus values.
+fn check_size(item: &Item) -> Result<(), DynamoError> {
+    let size: usize = item
+        .iter()
+        .map(|(name, value)| name.len() + value_size(value))
+        .sum();
+    if size > MAX_ITEM_BYTES {
+        return Err(DynamoError::validation(
+            "Item size has exceeded the maximum allowed size",
+        ));
+    }
+    Ok(())
+}
+
+fn value_size(value: &Value) -> usize {
+    let Some((kind, value)) = value.as_object().and_then(|value| value.iter().next()) else {
+        return 0;
+    };
+    match (kind.as_str(), value) {
+        ("B", Value::String(encoded)) => encoded.len() / 4 * 3,
+        (_, Value::String(text)) => text.len(),
+        // Sets hold bare strings, lists hold typed values.
+        (_, Value::Array(values)) => values
+            .iter()
+            .map(|value| value.as_str().map_or_else(|| value_size(value), str::len))
+            .sum(),
+        (_, Value::Object(map)) => map
+            .iter()
+            .map(|(name, value)| name.len() + value_size(value))
+            .sum(),
+        _ => 1,
+    }
+}
+
+/// FNV-1a, used to spread keys over scan segments the same way on every request.
+fn hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
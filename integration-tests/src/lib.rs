@@ -0,0 +1,72 @@
+//! Stand-ins for DynamoDB, Showdown and webhook endpoints, served over HTTP so the lambdas and the
+//! real `update-stats` and admin binaries can run against them end to end.
+
+use crate::dynamo::FakeDynamo;
+use axum::Router;
+use serde_json::Value;
+use std::env;
+use std::path::{Path, PathBuf};
+
+pub mod admin;
+pub mod dynamo;
+pub mod showdown;
+pub mod sweep;
+pub mod webhook;
+
+/// Serves `app` on a free local port until the runtime shuts down, returning its base URL.
+async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("unable to bind a local port");
+    let addr = listener.local_addr().expect("unable to read local address");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            println!("ERROR: stand-in server failed: {}", e);
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// A command running `binary` against `dynamo` with fake credentials. Nothing else is inherited,
+/// so local AWS credentials and config never leak into a run.
+fn command(binary: &Path, dynamo: &FakeDynamo) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(binary);
+    command
+        .env_clear()
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .env("AWS_REGION", "us-east-1")
+        .env("AWS_ENDPOINT_URL_DYNAMODB", dynamo.url())
+        .env("AWS_EC2_METADATA_DISABLED", "true");
+    command
+}
+
+/// Builds the binary `bin` of the sibling crate `package`, returning its path.
+fn build(package: &str, bin: &str) -> PathBuf {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // A target directory of its own, so the build never waits on the one running the tests.
+    let output =
+        std::process::Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+            .arg("build")
+            .arg("--message-format=json")
+            .arg("--manifest-path")
+            .arg(crate_dir.join("..").join(package).join("Cargo.toml"))
+            .arg("--bin")
+            .arg(bin)
+            .arg("--target-dir")
+            .arg(crate_dir.join("target").join(package))
+            .output()
+            .expect("unable to run cargo");
+    assert!(
+        output.status.success(),
+        "building {} failed:\n{}",
+        bin,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|message| message["target"]["name"] == bin)
+        .find_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .unwrap_or_else(|| panic!("cargo did not report a {} executable", bin))
+}
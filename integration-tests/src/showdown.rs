@@ -0,0 +1,77 @@
+//! A stand-in for Showdown's `/users/{id}.json` profile endpoint.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Profiles {
+    /// Status and body served per user id. Unknown ids get a 404.
+    replies: HashMap<String, (u16, String)>,
+    /// Ids requested so far, in order.
+    requests: Vec<String>,
+}
+
+pub struct MockShowdown {
+    url: String,
+    profiles: Arc<Mutex<Profiles>>,
+}
+
+impl MockShowdown {
+    pub async fn start() -> MockShowdown {
+        let profiles = Arc::new(Mutex::new(Profiles::default()));
+        let app = Router::new()
+            .route("/users/{file}", get(profile))
+            .with_state(profiles.clone());
+        MockShowdown {
+            url: crate::serve(app).await,
+            profiles,
+        }
+    }
+
+    /// Base URL to configure in place of `https://pokemonshowdown.com`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Serves `profile` for the user with id `user_id`.
+    pub fn set_profile(&self, user_id: &str, profile: Value) {
+        self.set_reply(user_id, 200, profile.to_string());
+    }
+
+    /// Replies to requests for the user with id `user_id` with `status` and `body`.
+    pub fn set_reply(&self, user_id: &str, status: u16, body: impl Into<String>) {
+        let mut profiles = self.profiles.lock().unwrap();
+        profiles
+            .replies
+            .insert(user_id.to_string(), (status, body.into()));
+    }
+
+    /// Ids of every profile requested so far, in order.
+    pub fn requests(&self) -> Vec<String> {
+        self.profiles.lock().unwrap().requests.clone()
+    }
+}
+
+async fn profile(
+    State(profiles): State<Arc<Mutex<Profiles>>>,
+    Path(file): Path<String>,
+) -> Response {
+    let Some(user_id) = file.strip_suffix(".json") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut profiles = profiles.lock().unwrap();
+    profiles.requests.push(user_id.to_string());
+    match profiles.replies.get(user_id) {
+        Some((status, body)) => {
+            let status = StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            (status, [("content-type", "application/json")], body.clone()).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
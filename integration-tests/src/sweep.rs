@@ -0,0 +1,75 @@
+//! Runs the real `update-stats` binary against the stand-ins.
+
+use crate::dynamo::FakeDynamo;
+use crate::showdown::MockShowdown;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::OnceLock;
+
+/// Set to a prebuilt `update-stats` binary to skip building it.
+const BIN_VAR: &str = "UPDATE_STATS_BIN";
+
+/// Runs `update-stats` with `args` against the `table` in `dynamo`, fetching ratings from
+/// `showdown`. `vars` are passed on as extra environment variables; nothing else is inherited, so
+/// local AWS credentials and config never leak into a run.
+pub async fn run_update_stats(
+    dynamo: &FakeDynamo,
+    showdown: &MockShowdown,
+    table: &str,
+    args: &[&str],
+    vars: &[(&str, &str)],
+) -> Output {
+    let output = command(dynamo, showdown, table, args, vars)
+        .output()
+        .await
+        .expect("unable to run update-stats");
+    println!("{}", String::from_utf8_lossy(&output.stdout));
+    output
+}
+
+/// Starts `update-stats` like [`run_update_stats`] without waiting for it, for continuous runs.
+/// Its output is captured for [`tokio::process::Child::wait_with_output`].
+pub fn spawn_update_stats(
+    dynamo: &FakeDynamo,
+    showdown: &MockShowdown,
+    table: &str,
+    args: &[&str],
+    vars: &[(&str, &str)],
+) -> tokio::process::Child {
+    command(dynamo, showdown, table, args, vars)
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("unable to start update-stats")
+}
+
+fn command(
+    dynamo: &FakeDynamo,
+    showdown: &MockShowdown,
+    table: &str,
+    args: &[&str],
+    vars: &[(&str, &str)],
+) -> tokio::process::Command {
+    let mut command = crate::command(binary(), dynamo);
+    command
+        .args(args)
+        .env("USER_STATS_TABLE", table)
+        .env("UPDATE_STATS_SHOWDOWN_URL", showdown.url())
+        .env("UPDATE_STATS_PAGE_INTERVAL_MS", "0")
+        // Metrics go to the stand-in, which refuses them, rather than to AWS.
+        .env("AWS_ENDPOINT_URL_CLOUDWATCH", dynamo.url())
+        .envs(vars.iter().copied());
+    command
+}
+
+/// The `update-stats` binary, built once per test run unless `UPDATE_STATS_BIN` is set.
+pub fn binary() -> &'static Path {
+    static BINARY: OnceLock<PathBuf> = OnceLock::new();
+    BINARY.get_or_init(
+        || match env::var_os(BIN_VAR).filter(|path| !path.is_empty()) {
+            Some(path) => PathBuf::from(path),
+            None => crate::build("update-stats", "update-stats"),
+        },
+    )
+}
@@ -0,0 +1,94 @@
+//! A stand-in for webhook endpoints, recording every delivery posted to `/hooks/{name}`.
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// One request received by a hook.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    /// Name of the hook posted to.
+    pub hook: String,
+    pub received_at: Instant,
+    /// Headers, with lowercase names.
+    pub headers: HashMap<String, String>,
+    pub body: Value,
+}
+
+#[derive(Default)]
+struct Hooks {
+    /// Status and body to reply with per hook, in order. Hooks reply 204 once these run out.
+    replies: HashMap<String, VecDeque<(u16, String)>>,
+    /// Requests received so far, in order.
+    deliveries: Vec<Delivery>,
+}
+
+pub struct MockWebhook {
+    url: String,
+    hooks: Arc<Mutex<Hooks>>,
+}
+
+impl MockWebhook {
+    pub async fn start() -> MockWebhook {
+        let hooks = Arc::new(Mutex::new(Hooks::default()));
+        let app = Router::new()
+            .route("/hooks/{name}", post(receive))
+            .with_state(hooks.clone());
+        MockWebhook {
+            url: crate::serve(app).await,
+            hooks,
+        }
+    }
+
+    /// URL of the hook named `name`.
+    pub fn url(&self, name: &str) -> String {
+        format!("{}/hooks/{}", self.url, name)
+    }
+
+    /// Replies to the next request to the hook named `name` with `status` and `body`, after any
+    /// replies already queued for it.
+    pub fn push_reply(&self, name: &str, status: u16, body: impl Into<String>) {
+        let mut hooks = self.hooks.lock().unwrap();
+        hooks
+            .replies
+            .entry(name.to_string())
+            .or_default()
+            .push_back((status, body.into()));
+    }
+
+    /// Every request received so far, in order.
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.hooks.lock().unwrap().deliveries.clone()
+    }
+}
+
+async fn receive(
+    State(hooks): State<Arc<Mutex<Hooks>>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let mut hooks = hooks.lock().unwrap();
+    hooks.deliveries.push(Delivery {
+        hook: name.clone(),
+        received_at: Instant::now(),
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: serde_json::from_str(&body).unwrap_or(Value::String(body)),
+    });
+    match hooks.replies.get_mut(&name).and_then(VecDeque::pop_front) {
+        Some((status, body)) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            (status, [("content-type", "application/json")], body).into_response()
+        }
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
@@ -0,0 +1,828 @@
+use add_user_lambda::service::{Clock, HttpShowdown, TrackUser};
+use aws_sdk_dynamodb::types::AttributeValue;
+use flate2::read::GzDecoder;
+use get_user_lambda::service::GetUserStats;
+use get_user_lambda::SITE_URL;
+use integration_tests::admin::run_repair;
+use integration_tests::dynamo::FakeDynamo;
+use integration_tests::showdown::MockShowdown;
+use integration_tests::sweep::{run_update_stats, spawn_update_stats};
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::Output;
+use std::time::{Duration, SystemTime};
+use user_stats_core::codec::{decode_ratings, Encoding};
+use user_stats_core::model::{Rating, Record, User};
+use user_stats_core::storage::{StoredUser, UserStore};
+use user_stats_core::time::month_of;
+
+const TABLE: &str = "user-stats";
+const STATE_TABLE: &str = "update-stats-state";
+/// When the user is added: 2023-11-14.
+const ADDED: u64 = 1_700_000_000;
+const HEAD: &str = "head.json.gz";
+const CHUNK: &str = "ratings.json.gz";
+/// `PSUS`, schema version 5, then the encoding.
+const JSON_GZIP_HEADER: &[u8] = b"PSUS\x05\x00";
+const COLUMNAR_HEADER: &[u8] = b"PSUS\x05\x01";
+
+struct FixedClock(u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+struct Env {
+    dynamo: FakeDynamo,
+    showdown: MockShowdown,
+    store: UserStore,
+}
+
+impl Env {
+    async fn start() -> Env {
+        let dynamo = FakeDynamo::start(&[(TABLE, "userId"), (STATE_TABLE, "stateId")]).await;
+        let store = UserStore::new(dynamo.client(), TABLE);
+        Env {
+            dynamo,
+            showdown: MockShowdown::start().await,
+            store,
+        }
+    }
+
+    /// Serves a profile for `someuser` with the given gen9ou elo and wins.
+    fn set_profile(&self, elo: f64, wins: u32) {
+        self.showdown.set_profile(
+            "someuser",
+            json!({
+                "username": "Some User",
+                "userid": "someuser",
+                "ratings": {
+                    "gen9ou": {"elo": elo, "w": wins, "l": 4, "t": 1},
+                    "gen9randombattle": {"elo": 1234},
+                },
+            }),
+        );
+    }
+
+    async fn add(&self, username: &str) -> Response<Body> {
+        self.add_at(username, ADDED).await
+    }
+
+    /// Adds `username` with new ratings stamped `time`.
+    async fn add_at(&self, username: &str, time: u64) -> Response<Body> {
+        let service = TrackUser {
+            store: &self.store,
+            showdown: &HttpShowdown::new(self.showdown.url()),
+            clock: &FixedClock(time),
+        };
+        let request = Request::default().with_path_parameters(HashMap::from([(
+            "username".to_string(),
+            username.to_string(),
+        )]));
+        add_user_lambda::handle(&service, request).await.unwrap()
+    }
+
+    /// Requests `path`, which must start with `/user-stats/{username}`.
+    async fn get(&self, path: &str) -> Response<Body> {
+        let username = path.split('/').nth(2).unwrap();
+        let request = lambda_http::http::Request::builder()
+            .uri(path)
+            .body(Body::Empty)
+            .unwrap()
+            .with_path_parameters(HashMap::from([(
+                "username".to_string(),
+                username.to_string(),
+            )]));
+        let service = GetUserStats {
+            store: &self.store,
+            site_url: SITE_URL,
+        };
+        get_user_lambda::handle(&service, request).await.unwrap()
+    }
+
+    /// Runs one sweep, returning its output and the time range its new ratings were stamped in.
+    async fn sweep(&self, vars: &[(&str, &str)]) -> (Output, (u64, u64)) {
+        let before = now();
+        let output = run_update_stats(&self.dynamo, &self.showdown, TABLE, &["once"], vars).await;
+        (output, (before, now()))
+    }
+
+    fn blob(&self, key: &str, attribute: &str) -> Vec<u8> {
+        self.dynamo
+            .binary(TABLE, key, attribute)
+            .unwrap_or_else(|| panic!("{} has no '{}'", key, attribute))
+    }
+
+    /// The JSON inside a gzipped JSON blob, after checking its header.
+    fn json_blob(&self, key: &str, attribute: &str) -> Value {
+        let blob = self.blob(key, attribute);
+        assert_eq!(&blob[..JSON_GZIP_HEADER.len()], JSON_GZIP_HEADER, "{}", key);
+        let mut json = String::new();
+        GzDecoder::new(&blob[JSON_GZIP_HEADER.len()..])
+            .read_to_string(&mut json)
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn body(resp: &Response<Body>) -> String {
+    String::from_utf8(resp.body().to_vec()).unwrap()
+}
+
+fn json_body(resp: &Response<Body>) -> Value {
+    serde_json::from_str(&body(resp)).unwrap()
+}
+
+/// The counts in the summary of a sweep that updated every user.
+fn summary(output: &Output) -> String {
+    assert!(output.status.success(), "update-stats failed");
+    summary_line(output)
+}
+
+/// The counts in the summary of a sweep that failed to update some users, which exits with 1.
+fn failed_summary(output: &Output) -> String {
+    assert_eq!(output.status.code(), Some(1), "update-stats did not fail");
+    summary_line(output)
+}
+
+fn summary_line(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("Sweep summary: "))
+        .expect("update-stats printed no summary")
+        .split(" in ")
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+fn added_ou() -> Value {
+    json!({"time": ADDED, "elo": 1500.5, "record": {"w": 10, "l": 4, "t": 1}})
+}
+
+fn added_randombattle() -> Value {
+    json!({"time": ADDED, "elo": 1234.0})
+}
+
+fn chunk_key(month: &str) -> String {
+    format!("someuser#{}", month)
+}
+
+#[tokio::test]
+async fn add_sweep_and_get_agree_on_storage() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+
+    // Adding writes a head and one chunk for the month of the first ratings.
+    let resp = env.add("Some User").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        json_body(&resp),
+        json!({
+            "username": "Some User",
+            "userid": "someuser",
+            "formats": {
+                "gen9ou": [added_ou()],
+                "gen9randombattle": [added_randombattle()],
+            },
+        })
+    );
+    assert_eq!(env.showdown.requests(), vec!["someuser"]);
+    assert_eq!(env.dynamo.keys(TABLE), vec!["someuser", "someuser#2023-11"]);
+    assert_eq!(
+        env.json_blob("someuser", HEAD),
+        json!({
+            "username": "Some User",
+            "userid": "someuser",
+            "latest": {
+                "gen9ou": added_ou(),
+                "gen9randombattle": added_randombattle(),
+            },
+            "chunks": ["2023-11"],
+            "highlights": {
+                "gen9ou": {
+                    "peaks": [{"time": ADDED, "elo": 1500.5}],
+                    "milestones": [{"elo": 1500, "time": ADDED}],
+                },
+                "gen9randombattle": {
+                    "peaks": [{"time": ADDED, "elo": 1234.0}],
+                    "milestones": [],
+                },
+            },
+        })
+    );
+    assert_eq!(
+        env.json_blob("someuser#2023-11", CHUNK),
+        json!({
+            "gen9ou": [added_ou()],
+            "gen9randombattle": [added_randombattle()],
+        })
+    );
+
+    // The sweep appends the one changed rating to a chunk for the current month.
+    env.set_profile(1550.0, 11);
+    let (output, (before, after)) = env.sweep(&[]).await;
+    assert_eq!(
+        summary(&output),
+        "processed 1 users, updated 1, pushed 1 new ratings, skipped 0 unchanged, 0 failed, 0 quarantined"
+    );
+    let head = env.json_blob("someuser", HEAD);
+    let swept = head["latest"]["gen9ou"]["time"].as_u64().unwrap();
+    assert!((before..=after).contains(&swept));
+    let month = month_of(swept);
+    let swept_ou = json!({"time": swept, "elo": 1550.0, "record": {"w": 11, "l": 4, "t": 1}});
+    let ou_highlights = json!({
+        "peaks": [{"time": ADDED, "elo": 1500.5}, {"time": swept, "elo": 1550.0}],
+        "currentStreak": {
+            "trend": "rising",
+            "length": 1,
+            "start": ADDED,
+            "end": swept,
+            "fromElo": 1500.5,
+            "toElo": 1550.0,
+        },
+        "longestRise": {
+            "trend": "rising",
+            "length": 1,
+            "start": ADDED,
+            "end": swept,
+            "fromElo": 1500.5,
+            "toElo": 1550.0,
+        },
+        "milestones": [{"elo": 1500, "time": ADDED}],
+    });
+    let randombattle_highlights = json!({
+        "peaks": [{"time": ADDED, "elo": 1234.0}],
+        "milestones": [],
+    });
+    assert_eq!(
+        head,
+        json!({
+            "username": "Some User",
+            "userid": "someuser",
+            "latest": {
+                "gen9ou": swept_ou,
+                "gen9randombattle": added_randombattle(),
+            },
+            "chunks": ["2023-11", month],
+            "highlights": {
+                "gen9ou": ou_highlights,
+                "gen9randombattle": randombattle_highlights,
+            },
+        })
+    );
+    assert_eq!(
+        env.dynamo.keys(TABLE),
+        vec![
+            "someuser".to_string(),
+            chunk_key("2023-11"),
+            chunk_key(&month)
+        ]
+    );
+    assert_eq!(
+        env.json_blob("someuser#2023-11", CHUNK),
+        json!({
+            "gen9ou": [added_ou()],
+            "gen9randombattle": [added_randombattle()],
+        })
+    );
+    assert_eq!(
+        env.json_blob(&chunk_key(&month), CHUNK),
+        json!({"gen9ou": [swept_ou]})
+    );
+
+    // Reading puts the history back together from the head and both chunks.
+    let resp = env.get("/user-stats/someuser").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers()["content-type"].to_str().unwrap(),
+        "application/json"
+    );
+    assert_eq!(
+        json_body(&resp),
+        json!({
+            "username": "Some User",
+            "userid": "someuser",
+            "formats": {
+                "gen9ou": [added_ou(), swept_ou],
+                "gen9randombattle": [added_randombattle()],
+            },
+            "games": {
+                "gen9ou": [{"time": swept, "w": 1, "l": 0, "t": 0}],
+            },
+            "highlights": {
+                "gen9ou": ou_highlights,
+                "gen9randombattle": randombattle_highlights,
+            },
+        })
+    );
+    let resp = env.get("/user-stats/someuser/games").await;
+    assert_eq!(resp.status(), 200);
+    // A rise of 49.5 is more than the K-factor at 1500 allows, so no opponent is estimated.
+    assert_eq!(
+        json_body(&resp),
+        json!({
+            "username": "Some User",
+            "userid": "someuser",
+            "formats": {
+                "gen9ou": [{
+                    "time": swept,
+                    "eloBefore": 1500.5,
+                    "eloAfter": 1550.0,
+                    "outcome": "win",
+                    "games": 1,
+                }],
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn sweeping_unchanged_ratings_writes_nothing() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    let stored = env.dynamo.items(TABLE);
+
+    let (output, _) = env.sweep(&[]).await;
+
+    assert_eq!(
+        summary(&output),
+        "processed 1 users, updated 0, pushed 0 new ratings, skipped 1 unchanged, 0 failed, 0 quarantined"
+    );
+    assert_eq!(env.dynamo.items(TABLE), stored);
+    assert_eq!(env.showdown.requests(), vec!["someuser", "someuser"]);
+}
+
+#[tokio::test]
+async fn re_adding_appends_only_changed_ratings_to_their_month() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    let added_chunk = env.dynamo.items(TABLE)["someuser#2023-11"].clone();
+
+    // Re-adding unchanged ratings writes nothing.
+    let stored = env.dynamo.items(TABLE);
+    assert_eq!(env.add_at("someuser", ADDED + 60).await.status(), 200);
+    assert_eq!(env.dynamo.items(TABLE), stored);
+
+    // 2023-12-01.
+    let readded = 1_701_388_800;
+    env.set_profile(1560.0, 12);
+    let resp = env.add_at("someuser", readded).await;
+
+    assert_eq!(resp.status(), 200);
+    let readded_ou = json!({"time": readded, "elo": 1560.0, "record": {"w": 12, "l": 4, "t": 1}});
+    assert_eq!(
+        json_body(&resp)["formats"],
+        json!({
+            "gen9ou": [added_ou(), readded_ou],
+            "gen9randombattle": [added_randombattle()],
+        })
+    );
+    assert_eq!(
+        env.dynamo.keys(TABLE),
+        vec!["someuser", "someuser#2023-11", "someuser#2023-12"]
+    );
+    assert_eq!(env.dynamo.items(TABLE)["someuser#2023-11"], added_chunk);
+    assert_eq!(
+        env.json_blob("someuser#2023-12", CHUNK),
+        json!({ "gen9ou": [readded_ou] })
+    );
+    let head = env.json_blob("someuser", HEAD);
+    assert_eq!(head["latest"]["gen9ou"], readded_ou);
+    assert_eq!(head["chunks"], json!(["2023-11", "2023-12"]));
+    assert_eq!(
+        head["highlights"]["gen9ou"]["peaks"],
+        json!([{"time": ADDED, "elo": 1500.5}, {"time": readded, "elo": 1560.0}])
+    );
+}
+
+#[tokio::test]
+async fn columnar_chunks_written_by_the_sweep_are_read_back() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+
+    env.set_profile(1480.0, 10);
+    let (output, (before, after)) = env
+        .sweep(&[("UPDATE_STATS_CHUNK_ENCODING", "columnar")])
+        .await;
+    assert_eq!(
+        summary(&output),
+        "processed 1 users, updated 1, pushed 1 new ratings, skipped 0 unchanged, 0 failed, 0 quarantined"
+    );
+
+    let head = env.json_blob("someuser", HEAD);
+    let swept = head["latest"]["gen9ou"]["time"].as_u64().unwrap();
+    assert!((before..=after).contains(&swept));
+    let blob = env.blob(&chunk_key(&month_of(swept)), CHUNK);
+    assert_eq!(&blob[..COLUMNAR_HEADER.len()], COLUMNAR_HEADER);
+    let swept_rating = Rating::live(swept, 1480.0).with_record(Some(Record { w: 10, l: 4, t: 1 }));
+    assert_eq!(
+        decode_ratings(&blob).unwrap(),
+        (
+            5,
+            HashMap::from([("gen9ou".to_string(), vec![swept_rating])])
+        )
+    );
+
+    let resp = env.get("/user-stats/someuser").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        json_body(&resp)["formats"],
+        json!({
+            "gen9ou": [
+                added_ou(),
+                {"time": swept, "elo": 1480.0, "record": {"w": 10, "l": 4, "t": 1}},
+            ],
+            "gen9randombattle": [added_randombattle()],
+        })
+    );
+}
+
+#[tokio::test]
+async fn retried_appends_to_columnar_chunks_are_not_duplicated() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    let store = UserStore::new(env.dynamo.client(), TABLE).with_chunk_encoding(Encoding::Columnar);
+    let Some(StoredUser::Chunked(head)) = store.load("someuser").await.unwrap() else {
+        panic!("someuser is not stored in chunks");
+    };
+    // Finer than columnar chunks store elos.
+    let swept = Rating::live(ADDED + 60, 1480.123);
+    let new_ratings = [("gen9ou".to_string(), swept.clone())];
+
+    store.append(&mut head.clone(), &new_ratings).await.unwrap();
+    // As if the head write had failed and the update were retried from the old head.
+    store.append(&mut head.clone(), &new_ratings).await.unwrap();
+
+    let (_, chunk) = decode_ratings(&env.blob(&chunk_key("2023-11"), CHUNK)).unwrap();
+    assert_eq!(chunk["gen9ou"].len(), 2);
+    assert!(chunk["gen9ou"][1].stores_as(&swept));
+}
+
+#[tokio::test]
+async fn users_missing_from_showdown_are_not_stored() {
+    let env = Env::start().await;
+
+    let resp = env.add("nobody").await;
+
+    assert_eq!(resp.status(), 404);
+    assert_eq!(body(&resp), "User not registered on Pokemon Showdown");
+    assert!(env.dynamo.keys(TABLE).is_empty());
+    let resp = env.get("/user-stats/nobody").await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn sweeps_follow_display_username_changes() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+
+    env.showdown.set_profile(
+        "someuser",
+        json!({
+            "username": "SOME user",
+            "userid": "someuser",
+            "ratings": {
+                "gen9ou": {"elo": 1500.5, "w": 10, "l": 4, "t": 1},
+                "gen9randombattle": {"elo": 1234},
+            },
+        }),
+    );
+    let (output, _) = env.sweep(&[]).await;
+
+    assert_eq!(
+        summary(&output),
+        "processed 1 users, updated 1, pushed 0 new ratings, skipped 0 unchanged, 0 failed, 0 quarantined"
+    );
+    assert_eq!(env.json_blob("someuser", HEAD)["username"], "SOME user");
+    let resp = env.get("/user-stats/someuser").await;
+    assert_eq!(json_body(&resp)["username"], "SOME user");
+}
+
+#[tokio::test]
+async fn sweeps_quarantine_users_whose_profile_names_another_user_id() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    let stored = env.dynamo.items(TABLE);
+
+    env.showdown.set_profile(
+        "someuser",
+        json!({
+            "username": "Other User",
+            "userid": "otheruser",
+            "ratings": {"gen9ou": {"elo": 1600, "w": 20, "l": 4, "t": 1}},
+        }),
+    );
+    let (output, _) = env.sweep(&[]).await;
+
+    assert_eq!(
+        failed_summary(&output),
+        "processed 1 users, updated 0, pushed 0 new ratings, skipped 0 unchanged, 0 failed, 1 quarantined"
+    );
+    let mut items = env.dynamo.items(TABLE);
+    let head = items.get_mut("someuser").unwrap();
+    assert!(head.remove("quarantinedAt").is_some());
+    let reason = "Showdown reports user ID: otheruser for user ID: someuser; link them with the \
+                  admin `alias` tool";
+    assert_eq!(head.remove("quarantineError"), Some(json!({ "S": reason })));
+    assert_eq!(items, stored);
+
+    // Quarantined users are left out of later sweeps rather than failing every time.
+    let (output, _) = env.sweep(&[]).await;
+    assert_eq!(
+        summary_line(&output),
+        "processed 0 users, updated 0, pushed 0 new ratings, skipped 0 unchanged, 0 failed, 0 quarantined"
+    );
+}
+
+#[tokio::test]
+async fn repair_rebuilds_quarantined_users_from_salvage_and_showdown() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    env.showdown.set_profile(
+        "someuser",
+        json!({"username": "Other User", "userid": "otheruser", "ratings": {}}),
+    );
+    env.sweep(&[]).await;
+    assert!(env.dynamo.items(TABLE)["someuser"].contains_key("quarantinedAt"));
+
+    // A profile naming another user is still refused.
+    let output = run_repair(&env.dynamo, &env.showdown, TABLE, &[]).await;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(env.dynamo.items(TABLE)["someuser"].contains_key("quarantinedAt"));
+
+    env.set_profile(1550.0, 11);
+    let before = now();
+    let output = run_repair(&env.dynamo, &env.showdown, TABLE, &[]).await;
+    let after = now();
+
+    assert!(output.status.success(), "repair failed");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 users repaired, 0 failed"));
+    let head = &env.dynamo.items(TABLE)["someuser"];
+    assert!(!head.contains_key("quarantinedAt"));
+    assert!(!head.contains_key("quarantineError"));
+    let user = json_body(&env.get("/user-stats/someuser").await);
+    let ou = user["formats"]["gen9ou"].as_array().unwrap();
+    assert_eq!(ou.len(), 2);
+    assert_eq!(ou[0], added_ou());
+    assert!((before..=after).contains(&ou[1]["time"].as_u64().unwrap()));
+    assert_eq!(ou[1]["elo"], 1550.0);
+    assert_eq!(ou[1]["record"], json!({"w": 11, "l": 4, "t": 1}));
+    assert_eq!(
+        user["formats"]["gen9randombattle"],
+        json!([added_randombattle()])
+    );
+    assert_eq!(
+        env.showdown.requests(),
+        vec!["someuser", "someuser", "someuser", "someuser"]
+    );
+}
+
+#[tokio::test]
+async fn sweeps_move_users_whose_data_names_another_user_id_under_their_key() {
+    let env = Env::start().await;
+    env.showdown.set_profile(
+        "oldname",
+        json!({
+            "username": "Old Name",
+            "userid": "oldname",
+            "ratings": {"gen9ou": {"elo": 1500.5, "w": 10, "l": 4, "t": 1}},
+        }),
+    );
+    assert_eq!(env.add("oldname").await.status(), 200);
+    // A head keyed by `someuser` whose data still names `oldname`, as left by restoring or
+    // renaming items by hand.
+    env.dynamo
+        .client()
+        .put_item()
+        .table_name(TABLE)
+        .item("userId", AttributeValue::S("someuser".to_string()))
+        .item(HEAD, AttributeValue::B(env.blob("oldname", HEAD).into()))
+        .send()
+        .await
+        .unwrap();
+
+    env.set_profile(1550.0, 11);
+    let (output, (before, after)) = env.sweep(&[]).await;
+
+    assert_eq!(
+        summary(&output),
+        "processed 2 users, updated 1, pushed 2 new ratings, skipped 1 unchanged, 0 failed, 0 quarantined"
+    );
+    let head = env.json_blob("someuser", HEAD);
+    assert_eq!(head["userid"], "someuser");
+    assert_eq!(head["username"], "Some User");
+    let swept = head["latest"]["gen9ou"]["time"].as_u64().unwrap();
+    assert!((before..=after).contains(&swept));
+    let resp = env.get("/user-stats/someuser").await;
+    assert_eq!(
+        json_body(&resp)["formats"]["gen9ou"],
+        json!([
+            added_ou(),
+            {"time": swept, "elo": 1550.0, "record": {"w": 11, "l": 4, "t": 1}},
+        ])
+    );
+    assert!(env
+        .dynamo
+        .keys(TABLE)
+        .contains(&chunk_key(&month_of(ADDED))));
+    // `oldname` is still tracked, so its chunks stay.
+    assert!(env
+        .dynamo
+        .keys(TABLE)
+        .contains(&"oldname#2023-11".to_string()));
+}
+
+#[tokio::test]
+async fn rewriting_a_user_deletes_chunks_nothing_references() {
+    let env = Env::start().await;
+    // 2023-12-01.
+    let december = 1_701_388_800;
+    let mut user = User {
+        username: "Ghost".to_string(),
+        userid: "ghost".to_string(),
+        formats: HashMap::from([(
+            "gen9ou".to_string(),
+            vec![Rating::live(ADDED, 1500.0), Rating::live(december, 1550.0)],
+        )]),
+    };
+    env.store.save_user(&user).await.unwrap();
+    assert_eq!(
+        env.dynamo.keys(TABLE),
+        vec!["ghost", "ghost#2023-11", "ghost#2023-12"]
+    );
+
+    // A repair or merge that drops December's ratings leaves nothing in its chunk.
+    user.formats.get_mut("gen9ou").unwrap().pop();
+    env.store.save_user(&user).await.unwrap();
+    assert_eq!(env.dynamo.keys(TABLE), vec!["ghost", "ghost#2023-11"]);
+
+    // Moving the head under another key leaves `ghost`'s chunks to no one once its head is gone.
+    let head = env.blob("ghost", HEAD);
+    env.dynamo
+        .client()
+        .put_item()
+        .table_name(TABLE)
+        .item("userId", AttributeValue::S("someuser".to_string()))
+        .item(HEAD, AttributeValue::B(head.into()))
+        .send()
+        .await
+        .unwrap();
+    env.dynamo
+        .client()
+        .delete_item()
+        .table_name(TABLE)
+        .key("userId", AttributeValue::S("ghost".to_string()))
+        .send()
+        .await
+        .unwrap();
+    let stored = env.store.load("someuser").await.unwrap().unwrap();
+    env.store.rekey(stored, "someuser").await.unwrap();
+    assert_eq!(env.dynamo.keys(TABLE), vec!["someuser", "someuser#2023-11"]);
+}
+
+#[tokio::test]
+async fn aliases_resolve_to_the_user_they_were_merged_into() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    env.store.put_alias("oldname", "someuser").await.unwrap();
+
+    let resp = env.get("/user-stats/oldname").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(json_body(&resp)["userid"], "someuser");
+
+    assert_eq!(env.add("Old Name").await.status(), 200);
+    assert_eq!(env.showdown.requests(), vec!["someuser", "someuser"]);
+    assert!(!env.dynamo.keys(TABLE).contains(&"oldname".to_string()));
+}
+
+#[tokio::test]
+async fn continuous_runs_lease_checkpoint_and_stop_on_sigterm() {
+    let env = Env::start().await;
+    env.set_profile(1500.5, 10);
+    assert_eq!(env.add("someuser").await.status(), 200);
+    // The state table and segment count the stack gives the service.
+    let vars = [
+        ("UPDATE_STATS_STATE_TABLE", STATE_TABLE),
+        ("UPDATE_STATS_TOTAL_SEGMENTS", "1"),
+        ("UPDATE_STATS_WORKER_ID", "worker-1"),
+    ];
+
+    let output = run_update_stats(
+        &env.dynamo,
+        &env.showdown,
+        TABLE,
+        &["--print-config"],
+        &vars,
+    )
+    .await;
+    assert!(output.status.success(), "printing the config failed");
+    let printed = String::from_utf8_lossy(&output.stdout);
+    assert!(printed.contains(&format!("state_table = \"{}\"", STATE_TABLE)));
+    assert!(env.dynamo.keys(STATE_TABLE).is_empty());
+
+    env.set_profile(1550.0, 11);
+    let before = now();
+    let child = spawn_update_stats(&env.dynamo, &env.showdown, TABLE, &["run"], &vars);
+    let swept = async {
+        loop {
+            let items = env.dynamo.items(STATE_TABLE);
+            if let Some(checkpoint) = items.get("sweep-checkpoint#0") {
+                if checkpoint.contains_key("lastSweepAt") {
+                    return checkpoint.clone();
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let checkpoint = tokio::time::timeout(Duration::from_secs(60), swept)
+        .await
+        .expect("the sweep was never checkpointed");
+
+    let swept_at: u64 = checkpoint["lastSweepAt"]["N"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((before..=now()).contains(&swept_at));
+    assert_eq!(checkpoint["totalSegments"], json!({"N": "1"}));
+    assert_eq!(
+        env.json_blob("someuser", HEAD)["latest"]["gen9ou"]["elo"],
+        1550.0
+    );
+
+    let status = std::process::Command::new("kill")
+        .args(["-TERM", &child.id().unwrap().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = tokio::time::timeout(Duration::from_secs(30), child.wait_with_output())
+        .await
+        .expect("update-stats did not stop after SIGTERM")
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("{}", stdout);
+    assert!(output.status.success(), "update-stats failed");
+    assert!(stdout.contains("Sweeping segment 0 of 1"));
+    assert!(stdout.contains("Received SIGTERM"));
+    assert!(stdout.contains("Shutdown complete. Exiting..."));
+    // The segment was given up after its sweep, leaving only the segment layout behind.
+    assert_eq!(
+        env.dynamo.keys(STATE_TABLE),
+        vec!["segment-layout", "sweep-checkpoint#0"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_configuration_exits_with_an_error() {
+    let env = Env::start().await;
+    let output = run_update_stats(
+        &env.dynamo,
+        &env.showdown,
+        TABLE,
+        &["once"],
+        &[("UPDATE_STATS_TOTAL_SEGMENTS", "0")],
+    )
+    .await;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("ERROR: total_segments must be at least 1, got 0. Exiting..."));
+}
+
+#[tokio::test]
+async fn refreshing_an_untracked_user_exits_with_an_error() {
+    let env = Env::start().await;
+    let output = run_update_stats(
+        &env.dynamo,
+        &env.showdown,
+        TABLE,
+        &["user", "someuser"],
+        &[],
+    )
+    .await;
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("ERROR: User ID: someuser is not being tracked"));
+    assert!(env.dynamo.keys(TABLE).is_empty());
+}
@@ -0,0 +1,344 @@
+use crate::lease::Segment;
+use aws_sdk_dynamodb as dynamodb;
+use dynamodb::types::builders::PutBuilder;
+use dynamodb::types::{AttributeValue, ConditionCheck, Put, TransactWriteItem};
+use std::collections::HashMap;
+
+/// Progress of a table sweep, persisted so a restarted task can pick up where the last one stopped.
+#[derive(Debug, Clone)]
+pub(crate) struct SweepCheckpoint {
+    pub(crate) sweep_id: String,
+    pub(crate) last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+    pub(crate) updated_at: u64,
+}
+
+/// Reads and writes the per-segment sweep checkpoint items in the update-stats state table. Once a
+/// sweep completes, its checkpoint is replaced by a record of when that sweep started, which tells
+/// workers when the segment is next due.
+pub(crate) struct CheckpointStore {
+    ddb: dynamodb::Client,
+    table: String,
+}
+
+impl CheckpointStore {
+    pub(crate) fn new(ddb: dynamodb::Client, table: String) -> CheckpointStore {
+        CheckpointStore { ddb, table }
+    }
+
+    /// Loads the checkpoint for `segment`. Checkpoints written under a different segment count
+    /// cover a different slice of the table, so they are ignored.
+    pub(crate) async fn load(&self, segment: Segment) -> Result<Option<SweepCheckpoint>, String> {
+        let resp = self
+            .ddb
+            .get_item()
+            .table_name(&self.table)
+            .key("stateId", AttributeValue::S(checkpoint_id(segment)))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| format!("error reading checkpoint: {:?}", e))?;
+
+        let item = match resp.item {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+
+        let total_segments = item
+            .get("totalSegments")
+            .and_then(|val| val.as_n().ok())
+            .and_then(|val| val.parse::<i32>().ok());
+        if total_segments != Some(segment.total) {
+            println!(
+                "Ignoring checkpoint for segment {} written with {:?} total segments",
+                segment.index, total_segments
+            );
+            return Ok(None);
+        }
+
+        let sweep_id = match item.get("sweepId").and_then(|val| val.as_s().ok()) {
+            Some(val) => val.clone(),
+            None if item.contains_key("lastSweepAt") => return Ok(None),
+            None => return Err(format!("checkpoint {:?} is missing 'sweepId'", item)),
+        };
+        let last_evaluated_key = match item.get("lastEvaluatedKey") {
+            Some(val) => match val.as_m() {
+                Ok(key) => Some(key.clone()),
+                Err(_) => {
+                    return Err(format!(
+                        "checkpoint {:?} 'lastEvaluatedKey' is not a map",
+                        item
+                    ))
+                }
+            },
+            None => None,
+        };
+        let updated_at = item
+            .get("updatedAt")
+            .and_then(|val| val.as_n().ok())
+            .and_then(|val| val.parse().ok())
+            .unwrap_or(0);
+
+        Ok(Some(SweepCheckpoint {
+            sweep_id,
+            last_evaluated_key,
+            updated_at,
+        }))
+    }
+
+    /// Saves the checkpoint for `segment`. With a `lease` condition, the checkpoint is only written
+    /// while the lease is held, and false is returned if it no longer is.
+    pub(crate) async fn save(
+        &self,
+        segment: Segment,
+        checkpoint: &SweepCheckpoint,
+        lease: Option<ConditionCheck>,
+    ) -> Result<bool, String> {
+        let mut put = Put::builder()
+            .item("stateId", AttributeValue::S(checkpoint_id(segment)))
+            .item(
+                "totalSegments",
+                AttributeValue::N(segment.total.to_string()),
+            )
+            .item("sweepId", AttributeValue::S(checkpoint.sweep_id.clone()))
+            .item(
+                "updatedAt",
+                AttributeValue::N(checkpoint.updated_at.to_string()),
+            );
+        if let Some(key) = &checkpoint.last_evaluated_key {
+            put = put.item("lastEvaluatedKey", AttributeValue::M(key.clone()));
+        }
+        self.put(put, lease)
+            .await
+            .map_err(|e| format!("error writing checkpoint: {}", e))
+    }
+
+    /// Replaces the checkpoint once a sweep has completed, so the next sweep starts afresh, and
+    /// records `started_at`, when the completed sweep began. Like [`save`](Self::save), returns
+    /// false without writing if the `lease` is no longer held.
+    pub(crate) async fn complete(
+        &self,
+        segment: Segment,
+        started_at: u64,
+        lease: Option<ConditionCheck>,
+    ) -> Result<bool, String> {
+        let put = Put::builder()
+            .item("stateId", AttributeValue::S(checkpoint_id(segment)))
+            .item(
+                "totalSegments",
+                AttributeValue::N(segment.total.to_string()),
+            )
+            .item("lastSweepAt", AttributeValue::N(started_at.to_string()));
+        self.put(put, lease)
+            .await
+            .map_err(|e| format!("error completing checkpoint: {}", e))
+    }
+
+    /// Writes the item `put` holds, in a transaction with the `lease` condition if there is one.
+    async fn put(&self, put: PutBuilder, lease: Option<ConditionCheck>) -> Result<bool, String> {
+        let put = put.table_name(&self.table);
+        let lease = match lease {
+            Some(lease) => lease,
+            None => {
+                return self
+                    .ddb
+                    .put_item()
+                    .table_name(&self.table)
+                    .set_item(put.get_item().clone())
+                    .send()
+                    .await
+                    .map(|_| true)
+                    .map_err(|e| format!("{:?}", e));
+            }
+        };
+        let put = put.build().map_err(|e| format!("{:?}", e))?;
+        let result = self
+            .ddb
+            .transact_write_items()
+            .transact_items(TransactWriteItem::builder().condition_check(lease).build())
+            .transact_items(TransactWriteItem::builder().put(put).build())
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                let e = e.into_service_error();
+                // The only condition is on the lease, so a cancelled transaction means it was lost.
+                if e.is_transaction_canceled_exception() {
+                    Ok(false)
+                } else {
+                    Err(format!("{:?}", e))
+                }
+            }
+        }
+    }
+
+    /// When the last completed sweep of `segment` started, if one has completed under the current
+    /// segment count.
+    pub(crate) async fn last_sweep_at(&self, segment: Segment) -> Result<Option<u64>, String> {
+        let resp = self
+            .ddb
+            .get_item()
+            .table_name(&self.table)
+            .key("stateId", AttributeValue::S(checkpoint_id(segment)))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| format!("error reading checkpoint: {:?}", e))?;
+        let item = match resp.item {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let total_segments = item
+            .get("totalSegments")
+            .and_then(|val| val.as_n().ok())
+            .and_then(|val| val.parse::<i32>().ok());
+        if total_segments != Some(segment.total) {
+            return Ok(None);
+        }
+        Ok(item
+            .get("lastSweepAt")
+            .and_then(|val| val.as_n().ok())
+            .and_then(|val| val.parse().ok()))
+    }
+}
+
+fn checkpoint_id(segment: Segment) -> String {
+    format!("sweep-checkpoint#{}", segment.index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lease::LeaseManager;
+    use integration_tests::dynamo::FakeDynamo;
+    use std::time::Duration;
+
+    const SEGMENT: Segment = Segment { index: 0, total: 2 };
+
+    async fn store() -> (FakeDynamo, CheckpointStore) {
+        let fake = FakeDynamo::start(&[("state", "stateId")]).await;
+        let store = CheckpointStore::new(fake.client(), "state".to_string());
+        (fake, store)
+    }
+
+    fn checkpoint() -> SweepCheckpoint {
+        SweepCheckpoint {
+            sweep_id: "1700000000".to_string(),
+            last_evaluated_key: Some(HashMap::from([(
+                "userId".to_string(),
+                AttributeValue::S("someuser".to_string()),
+            )])),
+            updated_at: 1700000100,
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_saved_checkpoints() {
+        let (_fake, store) = store().await;
+        assert!(store.load(SEGMENT).await.unwrap().is_none());
+
+        store.save(SEGMENT, &checkpoint(), None).await.unwrap();
+        let loaded = store.load(SEGMENT).await.unwrap().unwrap();
+        assert_eq!(loaded.sweep_id, "1700000000");
+        assert_eq!(loaded.last_evaluated_key, checkpoint().last_evaluated_key);
+        assert_eq!(loaded.updated_at, 1700000100);
+        // Segments have their own checkpoints.
+        let other = Segment { index: 1, total: 2 };
+        assert!(store.load(other).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ignores_checkpoints_from_other_segment_counts() {
+        let (_fake, store) = store().await;
+        let resized = Segment { index: 0, total: 4 };
+        store.save(resized, &checkpoint(), None).await.unwrap();
+        assert!(store.load(SEGMENT).await.unwrap().is_none());
+
+        store.complete(resized, 1700000000, None).await.unwrap();
+        assert_eq!(store.last_sweep_at(SEGMENT).await.unwrap(), None);
+        assert_eq!(
+            store.last_sweep_at(resized).await.unwrap(),
+            Some(1700000000)
+        );
+    }
+
+    #[tokio::test]
+    async fn completing_a_sweep_replaces_its_checkpoint() {
+        let (fake, store) = store().await;
+        store.save(SEGMENT, &checkpoint(), None).await.unwrap();
+        assert_eq!(store.last_sweep_at(SEGMENT).await.unwrap(), None);
+
+        store.complete(SEGMENT, 1700000000, None).await.unwrap();
+        assert!(store.load(SEGMENT).await.unwrap().is_none());
+        assert_eq!(
+            store.last_sweep_at(SEGMENT).await.unwrap(),
+            Some(1700000000)
+        );
+        assert_eq!(fake.keys("state"), ["sweep-checkpoint#0"]);
+
+        // The next sweep's progress is checkpointed over the completion record.
+        store.save(SEGMENT, &checkpoint(), None).await.unwrap();
+        assert!(store.load(SEGMENT).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn writes_only_while_the_lease_is_held() {
+        let (fake, store) = store().await;
+        let lease = |owner: &str| {
+            LeaseManager::new(
+                fake.client(),
+                "state".to_string(),
+                owner.to_string(),
+                Duration::from_secs(300),
+            )
+        };
+        let (a, b) = (lease("a"), lease("b"));
+        a.try_acquire(SEGMENT).await.unwrap();
+
+        assert_eq!(
+            store
+                .save(SEGMENT, &checkpoint(), Some(a.held(SEGMENT)))
+                .await,
+            Ok(true)
+        );
+        let written = fake.items("state");
+        assert_eq!(
+            store
+                .save(SEGMENT, &checkpoint(), Some(b.held(SEGMENT)))
+                .await,
+            Ok(false)
+        );
+        assert_eq!(
+            store
+                .complete(SEGMENT, 1700000000, Some(b.held(SEGMENT)))
+                .await,
+            Ok(false)
+        );
+        assert_eq!(fake.items("state"), written);
+
+        assert_eq!(
+            store
+                .complete(SEGMENT, 1700000000, Some(a.held(SEGMENT)))
+                .await,
+            Ok(true)
+        );
+        assert_eq!(
+            store.last_sweep_at(SEGMENT).await.unwrap(),
+            Some(1700000000)
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_checkpoints_without_a_sweep_id() {
+        let (fake, store) = store().await;
+        fake.client()
+            .put_item()
+            .table_name("state")
+            .item("stateId", AttributeValue::S(checkpoint_id(SEGMENT)))
+            .item("totalSegments", AttributeValue::N("2".to_string()))
+            .send()
+            .await
+            .unwrap();
+        assert!(store.load(SEGMENT).await.is_err());
+    }
+}
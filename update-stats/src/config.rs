@@ -0,0 +1,610 @@
+use clap::{Args, FromArgMatches, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
+use update_stats::showdown::{MAX_ELO, MIN_ELO, SHOWDOWN_URL};
+use update_stats::update::SHARP_DROP_ELO;
+use user_stats_core::codec::Encoding;
+use user_stats_core::user_id::UserId;
+
+/// Command line interface for update-stats. Every setting can also come from the environment or
+/// from a TOML config file; flags take precedence over environment variables, which take
+/// precedence over the file.
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Keeps tracked Pokemon Showdown user stats up to date",
+    after_help = "Every flag taking a value can also be set through the environment: \
+                  --user-stats-table as USER_STATS_TABLE, and the others as UPDATE_STATS_ \
+                  followed by the flag name in capitals with underscores, e.g. \
+                  UPDATE_STATS_PAGE_SIZE for --page-size."
+)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Path to a TOML config file.
+    #[arg(long, global = true)]
+    pub(crate) config: Option<PathBuf>,
+
+    /// Print the resolved configuration as TOML and exit.
+    #[arg(long, global = true)]
+    pub(crate) print_config: bool,
+
+    /// Fetch ratings and print the entries that would be added instead of writing them.
+    #[arg(long, global = true)]
+    pub(crate) dry_run: bool,
+
+    #[command(flatten)]
+    pub(crate) settings: Settings,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Sweep the table continuously. This is the default.
+    Run,
+    /// Sweep every segment once, print a summary and exit. Exits with status 1 if any user failed
+    /// to update or was quarantined.
+    Once,
+    /// Refresh a single user and exit. Exits with status 1 if the update failed.
+    User {
+        /// Showdown user id or username of a tracked user.
+        user_id: UserId,
+    },
+}
+
+/// Settings that may be set in the config file, the environment or on the command line. Unset
+/// fields fall through to the next source and finally to the defaults in [`Config::default`].
+#[derive(Args, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Settings {
+    /// DynamoDB table holding tracked users.
+    #[arg(long, global = true)]
+    user_stats_table: Option<String>,
+
+    /// DynamoDB table for sweep checkpoints and segment leases.
+    #[arg(long, global = true)]
+    state_table: Option<String>,
+
+    /// Number of parallel scan segments the table is split into.
+    #[arg(long, global = true)]
+    total_segments: Option<i32>,
+
+    /// Identity used when leasing segments. Defaults to hostname, pid and start time.
+    #[arg(long, global = true)]
+    worker_id: Option<String>,
+
+    /// Minimum time between the start of two sweeps of a segment.
+    #[arg(long, global = true)]
+    sweep_interval_secs: Option<u64>,
+
+    /// Minimum time between two scan pages.
+    #[arg(long, global = true)]
+    page_interval_ms: Option<u64>,
+
+    /// Number of users requested per scan page.
+    #[arg(long, global = true)]
+    page_size: Option<i32>,
+
+    /// Back-off after a failed scan.
+    #[arg(long, global = true)]
+    error_sleep_secs: Option<u64>,
+
+    /// How long a segment lease is valid without renewal.
+    #[arg(long, global = true)]
+    lease_duration_secs: Option<u64>,
+
+    /// Ratings below this are treated as bad data from Showdown and ignored.
+    #[arg(long, global = true)]
+    min_elo: Option<f64>,
+
+    /// Ratings above this are treated as bad data from Showdown and ignored.
+    #[arg(long, global = true)]
+    max_elo: Option<f64>,
+
+    /// CloudWatch namespace for sweep metrics.
+    #[arg(long, global = true)]
+    metrics_namespace: Option<String>,
+
+    /// Encoding for written history chunks: json-gzip or columnar.
+    #[arg(long, global = true)]
+    chunk_encoding: Option<Encoding>,
+
+    /// A rating falling by at least this much raises a sharp-drop webhook event.
+    #[arg(long, global = true)]
+    sharp_drop_elo: Option<f64>,
+
+    /// Times a webhook delivery is tried before giving up.
+    #[arg(long, global = true)]
+    webhook_max_attempts: Option<u32>,
+
+    /// How long events for a Discord webhook are collected before being sent as one message.
+    #[arg(long, global = true)]
+    discord_batch_secs: Option<u64>,
+
+    /// Base URL of the site, linked from Discord notifications.
+    #[arg(long, global = true)]
+    site_url: Option<String>,
+
+    /// Base URL of the Showdown server ratings are fetched from.
+    #[arg(long, global = true)]
+    showdown_url: Option<String>,
+}
+
+/// Fully resolved and validated update-stats configuration.
+#[derive(Serialize, Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) user_stats_table: String,
+    pub(crate) state_table: Option<String>,
+    pub(crate) total_segments: i32,
+    pub(crate) worker_id: Option<String>,
+    pub(crate) sweep_interval_secs: u64,
+    pub(crate) page_interval_ms: u64,
+    pub(crate) page_size: i32,
+    pub(crate) error_sleep_secs: u64,
+    pub(crate) lease_duration_secs: u64,
+    pub(crate) min_elo: f64,
+    pub(crate) max_elo: f64,
+    pub(crate) metrics_namespace: String,
+    pub(crate) chunk_encoding: Encoding,
+    pub(crate) sharp_drop_elo: f64,
+    pub(crate) webhook_max_attempts: u32,
+    pub(crate) discord_batch_secs: u64,
+    pub(crate) site_url: String,
+    pub(crate) showdown_url: String,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            user_stats_table: String::new(),
+            state_table: None,
+            total_segments: 1,
+            worker_id: None,
+            sweep_interval_secs: 60,
+            page_interval_ms: 1000,
+            page_size: 50,
+            error_sleep_secs: 3600,
+            lease_duration_secs: 300,
+            min_elo: MIN_ELO,
+            max_elo: MAX_ELO,
+            metrics_namespace: "UpdateStats".to_string(),
+            chunk_encoding: Encoding::JsonGzip,
+            sharp_drop_elo: SHARP_DROP_ELO,
+            webhook_max_attempts: 5,
+            discord_batch_secs: 10,
+            site_url: "https://pokemonshowdownuserstats.com".to_string(),
+            showdown_url: SHOWDOWN_URL.to_string(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the settings set by `env`, the environment variables named by [`env_var`]. Values are
+    /// parsed the same way as the flags they stand in for.
+    fn from_env(env: &HashMap<String, String>) -> Result<Vec<Settings>, String> {
+        let command = Settings::augment_args(clap::Command::new("update-stats"));
+        let mut settings = Vec::new();
+        for arg in command.get_arguments() {
+            let (Some(long), name) = (arg.get_long(), env_var(arg.get_id().as_str())) else {
+                continue;
+            };
+            let Some(value) = env.get(&name) else {
+                continue;
+            };
+            let parsed = command
+                .clone()
+                .no_binary_name(true)
+                .try_get_matches_from([format!("--{}={}", long, value)])
+                .and_then(|matches| Settings::from_arg_matches(&matches))
+                .map_err(|e| match e.source() {
+                    Some(cause) => format!("invalid {} '{}': {}", name, value, cause),
+                    None => format!("invalid {} '{}': {}", name, value, e.kind()),
+                })?;
+            settings.push(parsed);
+        }
+        Ok(settings)
+    }
+}
+
+/// The environment variable setting the [`Settings`] field `id`.
+fn env_var(id: &str) -> String {
+    match id {
+        "user_stats_table" => "USER_STATS_TABLE".to_string(),
+        _ => format!("UPDATE_STATS_{}", id.to_uppercase()),
+    }
+}
+
+impl Config {
+    /// Builds the configuration from defaults, the optional config file, the environment
+    /// variables in `env` and the command line flags parsed into `cli`.
+    pub(crate) fn load(cli: &Cli, env: &HashMap<String, String>) -> Result<Config, String> {
+        let mut config = Config::default();
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| env.get(&env_var("config")).map(PathBuf::from));
+        if let Some(path) = path {
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| format!("unable to read config file {}: {}", path.display(), e))?;
+            let file_settings: Settings = toml::from_str(&contents)
+                .map_err(|e| format!("unable to parse config file {}: {}", path.display(), e))?;
+            config.apply(file_settings);
+        }
+        for settings in Settings::from_env(env)? {
+            config.apply(settings);
+        }
+        config.apply(cli.settings.clone());
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&mut self, settings: Settings) {
+        if let Some(val) = settings.user_stats_table {
+            self.user_stats_table = val;
+        }
+        if let Some(val) = settings.state_table {
+            self.state_table = Some(val);
+        }
+        if let Some(val) = settings.total_segments {
+            self.total_segments = val;
+        }
+        if let Some(val) = settings.worker_id {
+            self.worker_id = Some(val);
+        }
+        if let Some(val) = settings.sweep_interval_secs {
+            self.sweep_interval_secs = val;
+        }
+        if let Some(val) = settings.page_interval_ms {
+            self.page_interval_ms = val;
+        }
+        if let Some(val) = settings.page_size {
+            self.page_size = val;
+        }
+        if let Some(val) = settings.error_sleep_secs {
+            self.error_sleep_secs = val;
+        }
+        if let Some(val) = settings.lease_duration_secs {
+            self.lease_duration_secs = val;
+        }
+        if let Some(val) = settings.min_elo {
+            self.min_elo = val;
+        }
+        if let Some(val) = settings.max_elo {
+            self.max_elo = val;
+        }
+        if let Some(val) = settings.metrics_namespace {
+            self.metrics_namespace = val;
+        }
+        if let Some(val) = settings.chunk_encoding {
+            self.chunk_encoding = val;
+        }
+        if let Some(val) = settings.sharp_drop_elo {
+            self.sharp_drop_elo = val;
+        }
+        if let Some(val) = settings.webhook_max_attempts {
+            self.webhook_max_attempts = val;
+        }
+        if let Some(val) = settings.discord_batch_secs {
+            self.discord_batch_secs = val;
+        }
+        if let Some(val) = settings.site_url {
+            self.site_url = val;
+        }
+        if let Some(val) = settings.showdown_url {
+            self.showdown_url = val;
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.user_stats_table.is_empty() {
+            return Err("user_stats_table is not set (USER_STATS_TABLE)".to_string());
+        }
+        if self.total_segments < 1 {
+            return Err(format!(
+                "total_segments must be at least 1, got {}",
+                self.total_segments
+            ));
+        }
+        if self.total_segments > 1 && self.state_table.is_none() {
+            return Err(
+                "state_table is required to lease segments when total_segments is greater than 1"
+                    .to_string(),
+            );
+        }
+        if !(1..=1000).contains(&self.page_size) {
+            return Err(format!(
+                "page_size must be between 1 and 1000, got {}",
+                self.page_size
+            ));
+        }
+        if self.min_elo >= self.max_elo {
+            return Err(format!(
+                "min_elo ({}) must be less than max_elo ({})",
+                self.min_elo, self.max_elo
+            ));
+        }
+        // Leases are renewed once per scan page and while updating a page's users, so they must
+        // outlive the wait between pages. Leasing workers give their segment up before backing
+        // off after a failed scan, so the back-off can be longer than the lease.
+        if self.state_table.is_some()
+            && self.lease_duration().as_millis() <= self.page_interval_ms as u128
+        {
+            return Err(format!(
+                "lease_duration_secs ({}) must be longer than page_interval_ms ({})",
+                self.lease_duration_secs, self.page_interval_ms
+            ));
+        }
+        if self.sharp_drop_elo <= 0.0 {
+            return Err(format!(
+                "sharp_drop_elo must be positive, got {}",
+                self.sharp_drop_elo
+            ));
+        }
+        if self.webhook_max_attempts < 1 {
+            return Err("webhook_max_attempts must be at least 1".to_string());
+        }
+        if self.discord_batch_secs < 1 {
+            return Err("discord_batch_secs must be at least 1".to_string());
+        }
+        if self.metrics_namespace.is_empty() {
+            return Err("metrics_namespace must not be empty".to_string());
+        }
+        if self.showdown_url.is_empty() {
+            return Err("showdown_url must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval_secs)
+    }
+
+    pub(crate) fn page_interval(&self) -> Duration {
+        Duration::from_millis(self.page_interval_ms)
+    }
+
+    pub(crate) fn error_sleep(&self) -> Duration {
+        Duration::from_secs(self.error_sleep_secs)
+    }
+
+    pub(crate) fn discord_batch_window(&self) -> Duration {
+        Duration::from_secs(self.discord_batch_secs)
+    }
+
+    pub(crate) fn lease_duration(&self) -> Duration {
+        Duration::from_secs(self.lease_duration_secs)
+    }
+
+    /// Elos outside this range are treated as bad data from Showdown.
+    pub(crate) fn elo_bounds(&self) -> RangeInclusive<f64> {
+        self.min_elo..=self.max_elo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn valid() -> Config {
+        Config {
+            user_stats_table: "UserStatsTable".to_string(),
+            ..Config::default()
+        }
+    }
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Config, String> {
+        let cli = Cli::try_parse_from([&["update-stats"], args].concat()).unwrap();
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::load(&cli, &env)
+    }
+
+    /// Writes `contents` to a config file unique to the calling test.
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("update-stats-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let path = config_file(
+            "layering",
+            "user_stats_table = \"FileTable\"\n\
+             page_size = 10\n\
+             page_interval_ms = 10\n\
+             sweep_interval_secs = 10\n",
+        );
+        let config = load(
+            &["--page-size", "30"],
+            &[
+                ("UPDATE_STATS_CONFIG", path.to_str().unwrap()),
+                ("UPDATE_STATS_PAGE_SIZE", "20"),
+                ("UPDATE_STATS_PAGE_INTERVAL_MS", "20"),
+            ],
+        );
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.page_size, 30);
+        assert_eq!(config.page_interval_ms, 20);
+        assert_eq!(config.sweep_interval_secs, 10);
+        assert_eq!(config.user_stats_table, "FileTable");
+        // Settings no source sets keep their defaults.
+        assert_eq!(config.metrics_namespace, "UpdateStats");
+    }
+
+    #[test]
+    fn rejects_unknown_and_unreadable_config_files() {
+        let path = config_file("unknown", "user_stats_table = \"T\"\npage_sise = 10\n");
+        let error = load(&["--config", path.to_str().unwrap()], &[]).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(
+            error.starts_with("unable to parse config file"),
+            "{}",
+            error
+        );
+        assert!(error.contains("page_sise"), "{}", error);
+
+        let error = load(&["--config", "/nonexistent/update-stats.toml"], &[]).unwrap_err();
+        assert!(error.starts_with("unable to read config file"), "{}", error);
+    }
+
+    #[test]
+    fn reads_every_setting_from_the_environment() {
+        let config = load(
+            &[],
+            &[
+                ("USER_STATS_TABLE", "EnvTable"),
+                ("UPDATE_STATS_CHUNK_ENCODING", "columnar"),
+                ("UPDATE_STATS_MIN_ELO", "900"),
+                ("UPDATE_STATS_SHOWDOWN_URL", "http://localhost:8000"),
+                // Not a setting.
+                ("UPDATE_STATS_BIN", "update-stats"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.user_stats_table, "EnvTable");
+        assert_eq!(config.chunk_encoding, Encoding::Columnar);
+        assert_eq!(config.min_elo, 900.0);
+        assert_eq!(config.showdown_url, "http://localhost:8000");
+
+        let error = load(
+            &[],
+            &[
+                ("USER_STATS_TABLE", "EnvTable"),
+                ("UPDATE_STATS_PAGE_SIZE", "many"),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "invalid UPDATE_STATS_PAGE_SIZE 'many': invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn accepts_the_defaults_once_a_table_is_set() {
+        assert_eq!(valid().validate(), Ok(()));
+        assert_eq!(
+            Config::default().validate(),
+            Err("user_stats_table is not set (USER_STATS_TABLE)".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let cases: [(Config, &str); 9] = [
+            (
+                Config {
+                    total_segments: 0,
+                    ..valid()
+                },
+                "total_segments must be at least 1, got 0",
+            ),
+            (
+                Config {
+                    total_segments: 2,
+                    ..valid()
+                },
+                "state_table is required to lease segments when total_segments is greater than 1",
+            ),
+            (
+                Config {
+                    page_size: 1001,
+                    ..valid()
+                },
+                "page_size must be between 1 and 1000, got 1001",
+            ),
+            (
+                Config {
+                    min_elo: 2000.0,
+                    max_elo: 2000.0,
+                    ..valid()
+                },
+                "min_elo (2000) must be less than max_elo (2000)",
+            ),
+            (
+                Config {
+                    state_table: Some("StateTable".to_string()),
+                    page_interval_ms: 300_000,
+                    ..valid()
+                },
+                "lease_duration_secs (300) must be longer than page_interval_ms (300000)",
+            ),
+            (
+                Config {
+                    sharp_drop_elo: 0.0,
+                    ..valid()
+                },
+                "sharp_drop_elo must be positive, got 0",
+            ),
+            (
+                Config {
+                    webhook_max_attempts: 0,
+                    ..valid()
+                },
+                "webhook_max_attempts must be at least 1",
+            ),
+            (
+                Config {
+                    metrics_namespace: String::new(),
+                    ..valid()
+                },
+                "metrics_namespace must not be empty",
+            ),
+            (
+                Config {
+                    showdown_url: String::new(),
+                    ..valid()
+                },
+                "showdown_url must not be empty",
+            ),
+        ];
+        for (config, error) in cases {
+            assert_eq!(config.validate(), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn segments_can_be_leased_with_a_state_table() {
+        let config = Config {
+            total_segments: 4,
+            state_table: Some("StateTable".to_string()),
+            ..valid()
+        };
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    /// The environment infrastructure/lib/infrastructure-stack.ts gives the container, which
+    /// leaves every other setting at its default.
+    #[test]
+    fn accepts_the_environment_the_stack_deploys() {
+        let config = load(
+            &[],
+            &[
+                ("USER_STATS_TABLE", "UserStatsTable"),
+                ("UPDATE_STATS_STATE_TABLE", "UpdateStatsStateTable"),
+                ("UPDATE_STATS_TOTAL_SEGMENTS", "1"),
+                ("UPDATE_STATS_METRICS_NAMESPACE", "UpdateStats"),
+                (
+                    "UPDATE_STATS_SITE_URL",
+                    "https://pokemonshowdownuserstats.com",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.state_table.as_deref(), Some("UpdateStatsStateTable"));
+        assert_eq!(config.metrics_namespace, "UpdateStats");
+        assert_eq!(config.error_sleep_secs, 3600);
+    }
+}
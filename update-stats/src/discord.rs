@@ -0,0 +1,318 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use user_stats_core::webhooks::{EventKind, RatingEvent};
+
+/// Discord accepts at most 10 embeds per message.
+const MAX_EMBEDS: usize = 10;
+/// Minimum time between two messages to one webhook, which keeps under Discord's limit of 30
+/// messages a minute per channel.
+const CHANNEL_INTERVAL: Duration = Duration::from_secs(2);
+/// Delay before the first retry of a failed message, doubled for each one after.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const PEAK_COLOR: u32 = 0xf1c40f;
+const MILESTONE_COLOR: u32 = 0x2ecc71;
+const DROP_COLOR: u32 = 0xe74c3c;
+
+/// Batches events for Discord webhooks into messages of up to [`MAX_EMBEDS`] embeds and sends
+/// them, one message at a time per webhook.
+pub(crate) struct Discord {
+    client: reqwest::Client,
+    max_attempts: u32,
+    site_url: String,
+    /// Embeds not yet sent, by webhook URL.
+    pending: HashMap<String, Vec<Value>>,
+    /// Message queue of the task sending to each webhook URL.
+    channels: HashMap<String, mpsc::UnboundedSender<Vec<Value>>>,
+}
+
+impl Discord {
+    pub(crate) fn new(client: reqwest::Client, max_attempts: u32, site_url: &str) -> Discord {
+        Discord {
+            client,
+            max_attempts,
+            site_url: site_url.trim_end_matches('/').to_string(),
+            pending: HashMap::new(),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Adds an embed for `event` to the webhook's next message, sending it once it is full.
+    pub(crate) fn push(&mut self, url: &str, event: &RatingEvent, tasks: &mut JoinSet<()>) {
+        let embeds = self.pending.entry(url.to_string()).or_default();
+        embeds.push(embed(event, &self.site_url));
+        if embeds.len() >= MAX_EMBEDS {
+            let embeds = std::mem::take(embeds);
+            self.send(url, embeds, tasks);
+        }
+    }
+
+    /// Sends every partly filled message.
+    pub(crate) fn flush(&mut self, tasks: &mut JoinSet<()>) {
+        let pending: Vec<(String, Vec<Value>)> = self.pending.drain().collect();
+        for (url, embeds) in pending {
+            if !embeds.is_empty() {
+                self.send(&url, embeds, tasks);
+            }
+        }
+    }
+
+    fn send(&mut self, url: &str, embeds: Vec<Value>, tasks: &mut JoinSet<()>) {
+        let sender = self.channels.entry(url.to_string()).or_insert_with(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tasks.spawn(run_channel(
+                self.client.clone(),
+                url.to_string(),
+                receiver,
+                self.max_attempts,
+            ));
+            sender
+        });
+        if sender.send(embeds).is_err() {
+            println!("ERROR: Discord channel task has stopped, dropping message");
+        }
+    }
+}
+
+/// Sends a webhook's messages in order, spaced at least [`CHANNEL_INTERVAL`] apart. Ends once the
+/// [`Discord`] that spawned it is dropped and its queue is empty.
+async fn run_channel(
+    client: reqwest::Client,
+    url: String,
+    mut receiver: mpsc::UnboundedReceiver<Vec<Value>>,
+    max_attempts: u32,
+) {
+    while let Some(embeds) = receiver.recv().await {
+        let count = embeds.len();
+        let body = json!({ "embeds": embeds });
+        if !post(&client, &url, &body, max_attempts).await {
+            println!("ERROR: giving up on Discord message with {} events", count);
+        }
+        tokio::time::sleep(CHANNEL_INTERVAL).await;
+    }
+}
+
+/// Posts one message, retrying failures. Rate limited requests wait as long as Discord asks.
+async fn post(client: &reqwest::Client, url: &str, body: &Value, max_attempts: u32) -> bool {
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=max_attempts {
+        let wait = match client.post(url).json(body).send().await {
+            Ok(resp) if resp.status().is_success() => return true,
+            Ok(resp) if resp.status().as_u16() == 429 => {
+                let retry_after = resp
+                    .json::<Value>()
+                    .await
+                    .ok()
+                    .and_then(|body| body["retry_after"].as_f64())
+                    .unwrap_or(delay.as_secs_f64());
+                println!(
+                    "Discord rate limited a message, retrying in {:.1} secs (attempt {} of {})",
+                    retry_after, attempt, max_attempts
+                );
+                Duration::from_secs_f64(retry_after.clamp(0.0, 60.0))
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                println!(
+                    "ERROR: Discord replied with status code: {} (attempt {} of {})",
+                    status, attempt, max_attempts
+                );
+                // Other client errors, such as a deleted webhook, will not succeed on a retry.
+                if !status.is_server_error() {
+                    return false;
+                }
+                delay
+            }
+            Err(e) => {
+                println!(
+                    "ERROR: unable to reach Discord: {} (attempt {} of {})",
+                    e, attempt, max_attempts
+                );
+                delay
+            }
+        };
+        if attempt < max_attempts {
+            tokio::time::sleep(wait).await;
+            delay *= 2;
+        }
+    }
+    false
+}
+
+/// A Discord embed describing `event`, linking to the user's chart on the site.
+fn embed(event: &RatingEvent, site_url: &str) -> Value {
+    let (title, color) = match event.kind {
+        EventKind::NewPeak => (
+            format!("{} set a new peak in {}", event.username, event.format),
+            PEAK_COLOR,
+        ),
+        EventKind::Milestone => (
+            format!(
+                "{} reached {} in {}",
+                event.username,
+                event.milestone.unwrap_or_default(),
+                event.format
+            ),
+            MILESTONE_COLOR,
+        ),
+        EventKind::SharpDrop => (
+            format!("{} dropped in {}", event.username, event.format),
+            DROP_COLOR,
+        ),
+    };
+    let elo = match event.previous_elo {
+        Some(previous) => format!("{:.0} → {:.0}", previous, event.elo),
+        None => format!("{:.0}", event.elo),
+    };
+    let mut fields = vec![
+        json!({ "name": "Player", "value": event.username, "inline": true }),
+        json!({ "name": "Format", "value": event.format, "inline": true }),
+        json!({ "name": "Elo", "value": elo, "inline": true }),
+    ];
+    if let Some(previous) = event.previous_elo {
+        fields.push(json!({
+            "name": "Change",
+            "value": format!("{:+.0}", event.elo - previous),
+            "inline": true,
+        }));
+    }
+    if event.kind == EventKind::NewPeak {
+        fields.push(json!({ "name": "Peak", "value": "🏆 New personal best", "inline": true }));
+    }
+    json!({
+        "title": title,
+        "url": format!("{}/?user={}&format={}", site_url, event.userid, event.format),
+        "color": color,
+        "fields": fields,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integration_tests::webhook::MockWebhook;
+
+    fn event(kind: EventKind, elo: f64, previous_elo: Option<f64>) -> RatingEvent {
+        RatingEvent {
+            kind,
+            userid: "someuser".to_string(),
+            username: "Some User".to_string(),
+            format: "gen9ou".to_string(),
+            time: 100,
+            elo,
+            previous_elo,
+            milestone: (kind == EventKind::Milestone).then_some(1600),
+        }
+    }
+
+    #[test]
+    fn renders_peaks_with_the_change_and_a_link_to_the_chart() {
+        let event = event(EventKind::NewPeak, 1612.4, Some(1580.0));
+        assert_eq!(
+            embed(&event, "https://example.com"),
+            json!({
+                "title": "Some User set a new peak in gen9ou",
+                "url": "https://example.com/?user=someuser&format=gen9ou",
+                "color": PEAK_COLOR,
+                "fields": [
+                    { "name": "Player", "value": "Some User", "inline": true },
+                    { "name": "Format", "value": "gen9ou", "inline": true },
+                    { "name": "Elo", "value": "1580 → 1612", "inline": true },
+                    { "name": "Change", "value": "+32", "inline": true },
+                    { "name": "Peak", "value": "🏆 New personal best", "inline": true },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn renders_milestones_and_drops() {
+        let milestone = embed(
+            &event(EventKind::Milestone, 1600.0, None),
+            "https://example.com",
+        );
+        assert_eq!(milestone["title"], "Some User reached 1600 in gen9ou");
+        assert_eq!(milestone["color"], MILESTONE_COLOR);
+        assert_eq!(
+            milestone["fields"],
+            json!([
+                { "name": "Player", "value": "Some User", "inline": true },
+                { "name": "Format", "value": "gen9ou", "inline": true },
+                { "name": "Elo", "value": "1600", "inline": true },
+            ])
+        );
+
+        let drop = embed(
+            &event(EventKind::SharpDrop, 1450.0, Some(1600.0)),
+            "https://example.com",
+        );
+        assert_eq!(drop["title"], "Some User dropped in gen9ou");
+        assert_eq!(drop["color"], DROP_COLOR);
+        assert_eq!(drop["fields"][2]["value"], "1600 → 1450");
+        assert_eq!(drop["fields"][3]["value"], "-150");
+    }
+
+    #[tokio::test]
+    async fn splits_batches_and_spaces_messages_per_webhook() {
+        let hooks = MockWebhook::start().await;
+        let mut discord = Discord::new(reqwest::Client::new(), 3, "https://example.com/");
+        let mut tasks = JoinSet::new();
+
+        for elo in 0..12 {
+            let event = event(EventKind::NewPeak, 1500.0 + elo as f64, None);
+            discord.push(&hooks.url("a"), &event, &mut tasks);
+        }
+        let other = event(EventKind::SharpDrop, 1400.0, Some(1500.0));
+        discord.push(&hooks.url("b"), &other, &mut tasks);
+        discord.flush(&mut tasks);
+        drop(discord);
+        while tasks.join_next().await.is_some() {}
+
+        let deliveries = hooks.deliveries();
+        let embeds = |hook: &str| -> Vec<usize> {
+            deliveries
+                .iter()
+                .filter(|d| d.hook == hook)
+                .map(|d| d.body["embeds"].as_array().unwrap().len())
+                .collect()
+        };
+        assert_eq!(embeds("a"), [10, 2]);
+        assert_eq!(embeds("b"), [1]);
+        let a: Vec<_> = deliveries.iter().filter(|d| d.hook == "a").collect();
+        assert_eq!(
+            a[0].body["embeds"][0]["url"],
+            "https://example.com/?user=someuser&format=gen9ou"
+        );
+        assert!(a[1].received_at - a[0].received_at >= CHANNEL_INTERVAL);
+        // Messages to another webhook do not wait for the first one's interval.
+        let b = deliveries.iter().find(|d| d.hook == "b").unwrap();
+        assert!(b.received_at < a[1].received_at);
+    }
+
+    #[tokio::test]
+    async fn waits_as_long_as_discord_asks_when_rate_limited() {
+        let hooks = MockWebhook::start().await;
+        hooks.push_reply("a", 429, json!({ "retry_after": 0.5 }).to_string());
+        let body = json!({ "embeds": [] });
+
+        assert!(post(&reqwest::Client::new(), &hooks.url("a"), &body, 2).await);
+
+        let deliveries = hooks.deliveries();
+        assert_eq!(deliveries.len(), 2);
+        let wait = deliveries[1].received_at - deliveries[0].received_at;
+        assert!(wait >= Duration::from_millis(500) && wait < FIRST_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let hooks = MockWebhook::start().await;
+        hooks.push_reply("a", 404, "");
+        let body = json!({ "embeds": [] });
+
+        assert!(!post(&reqwest::Client::new(), &hooks.url("a"), &body, 3).await);
+        assert_eq!(hooks.deliveries().len(), 1);
+    }
+}
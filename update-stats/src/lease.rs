@@ -0,0 +1,348 @@
+use crate::current_timestamp;
+use aws_sdk_dynamodb as dynamodb;
+use dynamodb::types::{AttributeValue, ConditionCheck, Put, TransactWriteItem, Update};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+/// One slice of a DynamoDB parallel scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Segment {
+    pub(crate) index: i32,
+    pub(crate) total: i32,
+}
+
+/// Key of the item recording the segment count leases are being taken with, and until when.
+const LAYOUT_ID: &str = "segment-layout";
+
+/// Grants a worker exclusive, expiring ownership of a scan segment through conditional writes to
+/// the update-stats state table, so that concurrent workers never sweep the same users.
+///
+/// Segments of different counts cover overlapping users, so every lease is taken and renewed
+/// together with the layout item, and a worker using another segment count is refused until
+/// every lease taken with the current count has expired.
+pub(crate) struct LeaseManager {
+    ddb: dynamodb::Client,
+    table: String,
+    owner: String,
+    duration: Duration,
+}
+
+impl LeaseManager {
+    pub(crate) fn new(
+        ddb: dynamodb::Client,
+        table: String,
+        owner: String,
+        duration: Duration,
+    ) -> LeaseManager {
+        LeaseManager {
+            ddb,
+            table,
+            owner,
+            duration,
+        }
+    }
+
+    pub(crate) fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Segment to try first, so that workers starting together don't all contend for segment 0.
+    pub(crate) fn preferred_index(&self, total: i32) -> i32 {
+        let mut hasher = DefaultHasher::new();
+        self.owner.hash(&mut hasher);
+        (hasher.finish() % total as u64) as i32
+    }
+
+    /// Takes the lease if it is free, expired or already ours and no live lease was taken with
+    /// another segment count. Returns false otherwise.
+    pub(crate) async fn try_acquire(&self, segment: Segment) -> Result<bool, String> {
+        let now = current_timestamp();
+        let lease = Put::builder()
+            .table_name(&self.table)
+            .item("stateId", AttributeValue::S(lease_id(segment)))
+            .item("owner", AttributeValue::S(self.owner.clone()))
+            .item(
+                "totalSegments",
+                AttributeValue::N(segment.total.to_string()),
+            )
+            .item("expiresAt", AttributeValue::N(self.expiry(now).to_string()))
+            .condition_expression(
+                "attribute_not_exists(stateId) OR expiresAt < :now OR #owner = :owner",
+            )
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .expression_attribute_values(":owner", AttributeValue::S(self.owner.clone()))
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+        let layout = self.layout(
+            segment,
+            now,
+            "attribute_not_exists(stateId) OR totalSegments = :total OR expiresAt < :now",
+        )?;
+        self.transact(TransactWriteItem::builder().put(lease).build(), layout)
+            .await
+            .map_err(|e| format!("error acquiring lease for segment {}: {}", segment.index, e))
+    }
+
+    /// Extends a lease we hold. Returns false if the lease has been taken over by another worker.
+    pub(crate) async fn renew(&self, segment: Segment) -> Result<bool, String> {
+        let now = current_timestamp();
+        let lease = Update::builder()
+            .table_name(&self.table)
+            .key("stateId", AttributeValue::S(lease_id(segment)))
+            .update_expression("SET expiresAt = :expiresAt")
+            .condition_expression("#owner = :owner")
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_values(
+                ":expiresAt",
+                AttributeValue::N(self.expiry(now).to_string()),
+            )
+            .expression_attribute_values(":owner", AttributeValue::S(self.owner.clone()))
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+        let layout = self.layout(
+            segment,
+            now,
+            "attribute_not_exists(stateId) OR totalSegments = :total",
+        )?;
+        self.transact(TransactWriteItem::builder().update(lease).build(), layout)
+            .await
+            .map_err(|e| format!("error renewing lease for segment {}: {}", segment.index, e))
+    }
+
+    /// Records that `segment`'s count is leased until our lease expires, if `condition` holds.
+    fn layout(&self, segment: Segment, now: u64, condition: &str) -> Result<Update, String> {
+        let mut layout = Update::builder()
+            .table_name(&self.table)
+            .key("stateId", AttributeValue::S(LAYOUT_ID.to_string()))
+            .update_expression("SET totalSegments = :total, expiresAt = :expiresAt")
+            .condition_expression(condition)
+            .expression_attribute_values(":total", AttributeValue::N(segment.total.to_string()))
+            .expression_attribute_values(
+                ":expiresAt",
+                AttributeValue::N(self.expiry(now).to_string()),
+            );
+        if condition.contains(":now") {
+            layout = layout.expression_attribute_values(":now", AttributeValue::N(now.to_string()));
+        }
+        layout.build().map_err(|e| format!("{:?}", e))
+    }
+
+    /// Writes `lease` and `layout` together. Returns false if either condition failed.
+    async fn transact(&self, lease: TransactWriteItem, layout: Update) -> Result<bool, String> {
+        let result = self
+            .ddb
+            .transact_write_items()
+            .transact_items(lease)
+            .transact_items(TransactWriteItem::builder().update(layout).build())
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                let e = e.into_service_error();
+                if e.is_transaction_canceled_exception() {
+                    Ok(false)
+                } else {
+                    Err(format!("{:?}", e))
+                }
+            }
+        }
+    }
+
+    /// A transaction condition that holds only while we own the lease, so that writes made on
+    /// behalf of the segment fail once another worker has taken it over.
+    pub(crate) fn held(&self, segment: Segment) -> ConditionCheck {
+        ConditionCheck::builder()
+            .table_name(&self.table)
+            .key("stateId", AttributeValue::S(lease_id(segment)))
+            .condition_expression("#owner = :owner")
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_values(":owner", AttributeValue::S(self.owner.clone()))
+            .build()
+            .expect("table name, key and condition are set")
+    }
+
+    /// Gives the lease up so a replacement worker can take the segment over immediately.
+    pub(crate) async fn release(&self, segment: Segment) -> Result<(), String> {
+        let result = self
+            .ddb
+            .delete_item()
+            .table_name(&self.table)
+            .key("stateId", AttributeValue::S(lease_id(segment)))
+            .condition_expression("#owner = :owner")
+            .expression_attribute_names("#owner", "owner")
+            .expression_attribute_values(":owner", AttributeValue::S(self.owner.clone()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let e = e.into_service_error();
+                if e.is_conditional_check_failed_exception() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "error releasing lease for segment {}: {:?}",
+                        segment.index, e
+                    ))
+                }
+            }
+        }
+    }
+
+    fn expiry(&self, now: u64) -> u64 {
+        now + self.duration.as_secs()
+    }
+}
+
+fn lease_id(segment: Segment) -> String {
+    format!("segment-lease#{}", segment.index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integration_tests::dynamo::FakeDynamo;
+
+    const SEGMENT: Segment = Segment { index: 1, total: 4 };
+
+    async fn fake() -> FakeDynamo {
+        FakeDynamo::start(&[("state", "stateId")]).await
+    }
+
+    fn manager(fake: &FakeDynamo, owner: &str) -> LeaseManager {
+        LeaseManager::new(
+            fake.client(),
+            "state".to_string(),
+            owner.to_string(),
+            Duration::from_secs(300),
+        )
+    }
+
+    #[tokio::test]
+    async fn only_one_worker_holds_a_lease() {
+        let fake = fake().await;
+        let (a, b) = (manager(&fake, "a"), manager(&fake, "b"));
+
+        assert_eq!(a.try_acquire(SEGMENT).await, Ok(true));
+        assert_eq!(b.try_acquire(SEGMENT).await, Ok(false));
+        // Holding a lease already is no reason to fail taking it again.
+        assert_eq!(a.try_acquire(SEGMENT).await, Ok(true));
+        // Other segments are leased independently.
+        assert_eq!(
+            b.try_acquire(Segment { index: 2, total: 4 }).await,
+            Ok(true)
+        );
+
+        let item = &fake.items("state")["segment-lease#1"];
+        assert_eq!(item["owner"]["S"], "a");
+        assert_eq!(item["totalSegments"]["N"], "4");
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_renews_a_lease() {
+        let fake = fake().await;
+        let (a, b) = (manager(&fake, "a"), manager(&fake, "b"));
+
+        assert_eq!(a.renew(SEGMENT).await, Ok(false));
+        a.try_acquire(SEGMENT).await.unwrap();
+        assert_eq!(a.renew(SEGMENT).await, Ok(true));
+        assert_eq!(b.renew(SEGMENT).await, Ok(false));
+        assert_eq!(fake.items("state")["segment-lease#1"]["owner"]["S"], "a");
+    }
+
+    #[tokio::test]
+    async fn released_leases_are_free_for_others() {
+        let fake = fake().await;
+        let (a, b) = (manager(&fake, "a"), manager(&fake, "b"));
+        a.try_acquire(SEGMENT).await.unwrap();
+
+        // Releasing someone else's lease leaves it in place.
+        assert_eq!(b.release(SEGMENT).await, Ok(()));
+        assert_eq!(b.try_acquire(SEGMENT).await, Ok(false));
+
+        assert_eq!(a.release(SEGMENT).await, Ok(()));
+        assert_eq!(fake.keys("state"), [LAYOUT_ID]);
+        assert_eq!(b.try_acquire(SEGMENT).await, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn expired_leases_are_taken_over() {
+        let fake = fake().await;
+        let (a, b) = (manager(&fake, "a"), manager(&fake, "b"));
+        fake.client()
+            .put_item()
+            .table_name("state")
+            .item("stateId", AttributeValue::S(lease_id(SEGMENT)))
+            .item("owner", AttributeValue::S("a".to_string()))
+            .item("totalSegments", AttributeValue::N("4".to_string()))
+            .item("expiresAt", AttributeValue::N("1".to_string()))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(b.try_acquire(SEGMENT).await, Ok(true));
+        assert_eq!(a.renew(SEGMENT).await, Ok(false));
+        let expires_at: u64 = fake.items("state")["segment-lease#1"]["expiresAt"]["N"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(expires_at >= current_timestamp() + 299);
+    }
+
+    #[test]
+    fn preferred_index_is_a_valid_segment() {
+        let ddb = aws_sdk_dynamodb::Client::from_conf(
+            aws_sdk_dynamodb::Config::builder()
+                .behavior_version(aws_sdk_dynamodb::config::BehaviorVersion::latest())
+                .build(),
+        );
+        for owner in ["a", "b", "worker-1", "worker-2"] {
+            let leases = LeaseManager::new(
+                ddb.clone(),
+                "state".to_string(),
+                owner.to_string(),
+                Duration::from_secs(300),
+            );
+            assert!((0..3).contains(&leases.preferred_index(3)));
+            assert_eq!(leases.preferred_index(1), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn segment_counts_are_not_leased_side_by_side() {
+        let fake = fake().await;
+        let (a, b) = (manager(&fake, "a"), manager(&fake, "b"));
+        let other_count = Segment { index: 2, total: 2 };
+
+        assert_eq!(a.try_acquire(SEGMENT).await, Ok(true));
+        a.release(SEGMENT).await.unwrap();
+        // The layout does not track which leases are still out, so the count stays reserved
+        // until the last lease taken with it would have expired.
+        assert_eq!(b.try_acquire(other_count).await, Ok(false));
+        assert_eq!(
+            b.try_acquire(Segment { index: 3, total: 4 }).await,
+            Ok(true)
+        );
+        assert_eq!(
+            fake.items("state")["segment-layout"]["totalSegments"]["N"],
+            "4"
+        );
+
+        fake.client()
+            .put_item()
+            .table_name("state")
+            .item("stateId", AttributeValue::S(LAYOUT_ID.to_string()))
+            .item("totalSegments", AttributeValue::N("4".to_string()))
+            .item("expiresAt", AttributeValue::N("1".to_string()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(b.try_acquire(other_count).await, Ok(true));
+        // Workers still on the old count lose their leases at the next renewal.
+        assert_eq!(b.renew(Segment { index: 3, total: 4 }).await, Ok(false));
+    }
+}
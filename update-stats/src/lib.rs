@@ -0,0 +1,4 @@
+//! The parts of the update sweep shared with the local development server.
+
+pub mod showdown;
+pub mod update;
@@ -1,297 +1,165 @@
 use aws_config::BehaviorVersion;
 use aws_sdk_dynamodb as dynamodb;
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use pokemon_showdown_user_stats_model::{Rating, User};
-use serde_json::Value;
+use checkpoint::CheckpointStore;
+use clap::Parser;
+use config::{Cli, Command, Config};
+use lease::LeaseManager;
+use shutdown::Shutdown;
 use std::env;
-use std::io::Read;
-use std::io::Write;
-use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
+use update_stats::update::Updater;
+use user_stats_core::storage::UserStore;
+use webhooks::Notifier;
+use worker::Worker;
+
+mod checkpoint;
+mod config;
+mod discord;
+mod lease;
+mod shutdown;
+mod webhooks;
+mod worker;
 
 #[tokio::main]
 async fn main() {
-    let user_stats_table_env = "USER_STATS_TABLE";
-    let config = aws_config::defaults(BehaviorVersion::latest()).load().await;
-    let ddb = dynamodb::Client::new(&config);
-    let cloud_watch = aws_sdk_cloudwatch::Client::new(&config);
-    let user_stats_table = match env::var(user_stats_table_env) {
+    let cli = Cli::parse();
+    // Variables that are not valid unicode cannot hold any setting.
+    let env_vars = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    let config = match Config::load(&cli, &env_vars) {
         Ok(val) => val,
-        Err(_) => {
-            println!("ERROR: {} is not set. Exiting...", user_stats_table_env);
-            return;
+        Err(e) => {
+            println!("ERROR: {}. Exiting...", e);
+            std::process::exit(1);
         }
     };
 
-    loop {
-        let mut exclusive_start_key = None;
-        let scan_start_time = Instant::now();
-        let mut item_count = 0;
-        loop {
-            let scan_page_start_time = Instant::now();
-            let user_stats_table_scan_resp = match ddb
-                .scan()
-                .set_exclusive_start_key(exclusive_start_key.clone())
-                .table_name(&user_stats_table)
-                .limit(50)
-                .send()
-                .await
-            {
-                Ok(resp) => resp,
-                Err(e) => {
-                    println!("ERROR: error scanning table: {:?}", e);
-                    tokio::time::sleep(Duration::from_secs(3600)).await;
-                    continue;
-                }
-            };
-
-            if let Some(items) = user_stats_table_scan_resp.items {
-                for item in items {
-                    item_count += 1;
-                    let user_id = match item.get("userId") {
-                        Some(val) => val,
-                        None => {
-                            println!("ERROR: Item {:?} is missing 'userId' key", item);
-                            continue;
-                        }
-                    };
-                    let user_id = match user_id.as_s() {
-                        Ok(val) => val,
-                        Err(_) => {
-                            println!("ERROR: Item {:?} 'userId' key is not a string", item);
-                            continue;
-                        }
-                    };
-
-                    println!("Processing user: {}", user_id);
-                    let stats_json_gz = match item.get("stats.json.gz") {
-                        Some(val) => val,
-                        None => {
-                            println!("Item {:?} is missing 'stats.json.gz' key", item);
-                            continue;
-                        }
-                    };
-                    let stats_json_gz = match stats_json_gz.as_b() {
-                        Ok(val) => val,
-                        Err(_) => {
-                            println!("Item {:?} 'stats.json.gz' key is not a binary", item);
-                            continue;
-                        }
-                    };
-
-                    let mut decoder = GzDecoder::new(stats_json_gz.as_ref());
-
-                    let mut stats_json = String::new();
-
-                    match decoder.read_to_string(&mut stats_json) {
-                        Ok(_) => (),
-                        Err(e) => {
-                            println!("Error decompressing Gzipped JSON: {:?}", e);
-                            continue;
-                        }
-                    }
-
-                    let mut user: User = match serde_json::from_str(&stats_json) {
-                        Ok(resp) => resp,
-                        Err(e) => {
-                            println!("Error parsing JSON: {:?}", e);
-                            continue;
-                        }
-                    };
-
-                    let ps_response = match reqwest::get(format!(
-                        "https://pokemonshowdown.com/users/{user_id}.json"
-                    ))
-                    .await
-                    {
-                        Ok(resp) => resp,
-                        Err(e) => {
-                            println!(
-                                "Error getting PS user JSON for user ID: {}, err: {}",
-                                user_id, e
-                            );
-                            continue;
-                        }
-                    };
-
-                    let ps_response_body = match ps_response.text().await {
-                        Ok(resp) => resp,
-                        Err(_) => {
-                            println!("Error getting text from PS user for user ID: {}", user_id);
-                            continue;
-                        }
-                    };
-
-                    let ps_user_stats: Value = match serde_json::from_str(&ps_response_body) {
-                        Ok(resp) => resp,
-                        Err(_) => {
-                            println!("Error parsing PS user JSON for user ID: {}", user_id);
-                            continue;
-                        }
-                    };
-
-                    let current_time =
-                        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                            Ok(val) => val.as_secs(),
-                            Err(e) => {
-                                println!("Error getting current time: {:?}", e);
-                                continue;
-                            }
-                        };
-
-                    if let Value::Object(map) = ps_user_stats["ratings"].clone() {
-                        for (format, rating) in map {
-                            let new_elo = match rating["elo"].as_f64() {
-                                Some(resp) => resp,
-                                None => {
-                                    println!(
-                                        "Error parsing PS user JSON elo for user ID: {}",
-                                        user_id
-                                    );
-                                    continue;
-                                }
-                            };
-
-                            if (new_elo < 1000.0) || (new_elo > 10000.0) {
-                                println!(
-                                    "Elo out of bounds for user ID: {}, elo: {}",
-                                    user_id, new_elo
-                                );
-                                println!("full ps response: {}", ps_response_body);
-                                continue;
-                            }
-
-                            let new_rating = Rating {
-                                time: current_time,
-                                elo: new_elo,
-                            };
-
-                            let ratings = user.formats.entry(format).or_insert_with(Vec::new);
-                            if ratings.last().map(|r| r.elo) != Some(new_elo) {
-                                println!("Pushing new rating");
-                                ratings.push(new_rating);
-                            }
-                        }
-                    } else {
-                        println!(
-                            "Error parsing PS user JSON ratings for user ID: {}",
-                            user_id
-                        );
-                        continue;
-                    }
-                    let user_string = match serde_json::to_string(&user) {
-                        Ok(resp) => resp,
-                        Err(e) => {
-                            println!("Error serializing user object: {:?}", e);
-                            continue;
-                        }
-                    };
+    if cli.print_config {
+        match toml::to_string_pretty(&config) {
+            Ok(val) => print!("{}", val),
+            Err(e) => {
+                println!("ERROR: unable to print config: {}. Exiting...", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                    match encoder.write_all(user_string.as_bytes()) {
-                        Ok(_) => {}
-                        Err(_) => {
-                            println!("Error writing to Gzipped JSON");
-                            continue;
-                        }
-                    }
+    let aws_config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+    let ddb = dynamodb::Client::new(&aws_config);
+    let cloud_watch = aws_sdk_cloudwatch::Client::new(&aws_config);
+    let worker_id = match &config.worker_id {
+        Some(val) => val.clone(),
+        None => format!(
+            "{}-{}-{}",
+            env::var("HOSTNAME").unwrap_or_else(|_| "update-stats".to_string()),
+            std::process::id(),
+            current_timestamp()
+        ),
+    };
 
-                    let compressed_bytes = match encoder.finish() {
-                        Ok(resp) => resp,
-                        Err(_) => {
-                            println!("Error finishing Gzipped JSON");
-                            continue;
-                        }
-                    };
+    let shutdown = Shutdown::listen();
+    let notifier = Notifier::start(&config, shutdown.clone());
+    let updater = Updater {
+        store: UserStore::new(ddb.clone(), config.user_stats_table.clone())
+            .with_chunk_encoding(config.chunk_encoding),
+        showdown_url: config.showdown_url.clone(),
+        elo_bounds: config.elo_bounds(),
+        sharp_drop_elo: config.sharp_drop_elo,
+        dry_run: cli.dry_run,
+    };
 
-                    match ddb
-                        .put_item()
-                        .item(
-                            "userId",
-                            aws_sdk_dynamodb::types::AttributeValue::S(user.userid),
-                        )
-                        .item(
-                            "stats.json.gz",
-                            aws_sdk_dynamodb::types::AttributeValue::B(
-                                compressed_bytes.clone().into(),
-                            ),
-                        )
-                        .table_name(user_stats_table.clone())
-                        .send()
-                        .await
-                    {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("Error writing to DDB: {:?}", e);
-                            continue;
-                        }
-                    };
+    match cli.command {
+        Some(Command::User { user_id }) => {
+            let result = updater.update_user_id(user_id.as_str()).await;
+            match &result {
+                Ok(update) => {
+                    println!(
+                        "Updated user ID: {} with {} new ratings",
+                        update.user_id,
+                        update.new_ratings.len()
+                    );
+                    notifier
+                        .notify(&updater.store, &update.user_id, &update.events)
+                        .await;
                 }
+                Err(e) => println!("ERROR: {}", e),
             }
-
-            exclusive_start_key = user_stats_table_scan_resp.last_evaluated_key;
-
-            if exclusive_start_key.is_none() {
-                break;
+            notifier.finish().await;
+            if result.is_err() {
+                std::process::exit(1);
             }
-
-            let time_passed = scan_page_start_time.elapsed();
-            let wait_time = if time_passed < Duration::new(1, 0) {
-                Duration::new(1, 0) - time_passed
-            } else {
-                Duration::new(0, 0)
-            };
-
-            println!("page scan time: {} milis", time_passed.as_millis());
-            println!("Scan page once per second; Waiting for {} milis...", wait_time.as_millis());
-            tokio::time::sleep(wait_time).await;
+            return;
         }
-        let time_passed = scan_start_time.elapsed();
-        let wait_time = if time_passed < Duration::new(60, 0) {
-            Duration::new(60, 0) - time_passed
-        } else {
-            Duration::new(0, 0)
-        };
-        println!("scan time: {} milis", time_passed.as_millis());
-        println!("Scan table once per min. Waiting for {} milis...", wait_time.as_millis());
-        let name_space = "UpdateStats";
-        match cloud_watch
-            .put_metric_data()
-            .namespace(name_space)
-            .metric_data(
-            aws_sdk_cloudwatch::types::MetricDatum::builder()
-                .metric_name("wait_time")
-                .value(wait_time.as_millis() as f64)
-                .unit(aws_sdk_cloudwatch::types::StandardUnit::Milliseconds) 
-                .build(),
-            )
-            .send()
-            .await {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error writing to CloudWatch: {:?}", e);
-            }
-        };
-        match cloud_watch
-            .put_metric_data()
-            .namespace(name_space)
-            .metric_data(
-            aws_sdk_cloudwatch::types::MetricDatum::builder()
-                .metric_name("item_count")
-                .value(item_count as f64)
-                .unit(aws_sdk_cloudwatch::types::StandardUnit::Count) 
-                .build(),
-            )
-            .send()
-            .await {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error writing to CloudWatch: {:?}", e);
+        Some(Command::Once) => {
+            let worker = Worker {
+                cloud_watch,
+                config,
+                updater,
+                notifier,
+                checkpoints: None,
+                leases: None,
+                shutdown,
+            };
+            let start_time = Instant::now();
+            let stats = worker.run_once().await;
+            println!(
+                "Sweep summary: processed {} users, updated {}, pushed {} new ratings, skipped {} unchanged, {} failed, {} quarantined in {} milis{}",
+                stats.processed,
+                stats.updated,
+                stats.new_ratings,
+                stats.skipped_writes,
+                stats.failed,
+                stats.quarantined,
+                start_time.elapsed().as_millis(),
+                if cli.dry_run { " (dry run)" } else { "" }
+            );
+            worker.notifier.finish().await;
+            // Scripts running one-shot sweeps need to notice users that could not be updated.
+            if stats.failed > 0 || stats.quarantined > 0 {
+                std::process::exit(1);
             }
-        };
-        tokio::time::sleep(wait_time).await;
+            return;
+        }
+        Some(Command::Run) | None => {}
+    }
+
+    // A continuous dry run would still advance checkpoints, hold leases and publish metrics.
+    if cli.dry_run {
+        println!("ERROR: --dry-run is only supported with `once` and `user`. Exiting...");
+        std::process::exit(1);
+    }
+
+    if config.state_table.is_none() {
+        println!("WARNING: state_table is not set. Sweeps will not be checkpointed");
     }
+
+    let worker = Worker {
+        cloud_watch,
+        updater,
+        notifier,
+        checkpoints: config
+            .state_table
+            .clone()
+            .map(|table| CheckpointStore::new(ddb.clone(), table)),
+        leases: config
+            .state_table
+            .clone()
+            .map(|table| LeaseManager::new(ddb.clone(), table, worker_id, config.lease_duration())),
+        shutdown,
+        config,
+    };
+    worker.run().await;
+    println!("Waiting for webhook deliveries...");
+    worker.notifier.finish().await;
+    println!("Shutdown complete. Exiting...");
+}
+
+pub(crate) fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time error")
+        .as_secs()
 }
@@ -0,0 +1,227 @@
+//! Reads users' current ladder ratings from Showdown and works out which of them are new.
+
+use serde_json::Value;
+use std::ops::RangeInclusive;
+use user_stats_core::model::{Rating, Record};
+
+/// Where profiles are fetched from unless configured otherwise.
+pub const SHOWDOWN_URL: &str = "https://pokemonshowdown.com";
+
+/// Default bounds of believable elos. Showdown has been seen to report ratings outside them.
+pub const MIN_ELO: f64 = 1000.0;
+pub const MAX_ELO: f64 = 10000.0;
+
+/// A user's profile as Showdown reports it.
+pub struct Profile {
+    /// The raw response, logged when it holds bad data.
+    pub body: String,
+    pub json: Value,
+}
+
+/// Fetches a user's profile from the Showdown server at `base_url`.
+pub async fn fetch_profile(base_url: &str, user_id: &str) -> Result<Profile, String> {
+    let url = format!("{}/users/{}.json", base_url.trim_end_matches('/'), user_id);
+    let ps_response = match reqwest::get(url).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            return Err(format!(
+                "Error getting PS user JSON for user ID: {}, err: {}",
+                user_id, e
+            ))
+        }
+    };
+
+    let body = match ps_response.text().await {
+        Ok(resp) => resp,
+        Err(_) => {
+            return Err(format!(
+                "Error getting text from PS user for user ID: {}",
+                user_id
+            ))
+        }
+    };
+
+    match serde_json::from_str(&body) {
+        Ok(json) => Ok(Profile { body, json }),
+        Err(_) => Err(format!(
+            "Error parsing PS user JSON for user ID: {}",
+            user_id
+        )),
+    }
+}
+
+/// The display username `profile` reports for the user with id `user_id`. Fails when Showdown
+/// reports the profile under a different user id, so its ratings are never stored under the wrong
+/// user; players who moved accounts are linked explicitly with the admin `alias` tool instead.
+pub fn profile_username<'a>(user_id: &str, profile: &'a Profile) -> Result<&'a str, String> {
+    match profile.json["userid"].as_str() {
+        Some(reported) if reported == user_id => {}
+        Some(reported) => {
+            return Err(format!(
+                "Showdown reports user ID: {} for user ID: {}, skipping",
+                reported, user_id
+            ))
+        }
+        None => {
+            return Err(format!(
+                "Error parsing PS user JSON userid for user ID: {}",
+                user_id
+            ))
+        }
+    }
+    match profile.json["username"].as_str() {
+        Some(username) => Ok(username),
+        None => Err(format!(
+            "Error parsing PS user JSON username for user ID: {}",
+            user_id
+        )),
+    }
+}
+
+/// The ratings in `profile` that differ from the latest stored rating in their format, as given
+/// by `latest`, stamped with `time`. Elos outside `elo_bounds` are treated as bad data and skipped.
+pub fn changed_ratings<'a>(
+    user_id: &str,
+    profile: &Profile,
+    time: u64,
+    elo_bounds: &RangeInclusive<f64>,
+    latest: impl Fn(&str) -> Option<&'a Rating>,
+) -> Result<Vec<(String, Rating)>, String> {
+    let map = match &profile.json["ratings"] {
+        Value::Object(map) => map,
+        _ => {
+            return Err(format!(
+                "Error parsing PS user JSON ratings for user ID: {}",
+                user_id
+            ))
+        }
+    };
+
+    let mut new_ratings = Vec::new();
+    for (format, rating) in map {
+        let new_elo = match rating["elo"].as_f64() {
+            Some(resp) => resp,
+            None => {
+                println!("Error parsing PS user JSON elo for user ID: {}", user_id);
+                continue;
+            }
+        };
+
+        if !elo_bounds.contains(&new_elo) {
+            println!(
+                "Elo out of bounds for user ID: {}, elo: {}",
+                user_id, new_elo
+            );
+            println!("full ps response: {}", profile.body);
+            continue;
+        }
+
+        let new_rating = Rating::live(time, new_elo).with_record(Record::from_showdown(rating));
+        if !latest(format).is_some_and(|latest| new_rating.repeats(latest)) {
+            println!("Pushing new rating");
+            new_ratings.push((format.clone(), new_rating));
+        }
+    }
+    Ok(new_ratings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    const BOUNDS: RangeInclusive<f64> = MIN_ELO..=MAX_ELO;
+
+    fn profile(json: Value) -> Profile {
+        Profile {
+            body: json.to_string(),
+            json,
+        }
+    }
+
+    fn stored(elo: f64, record: Option<Record>) -> Rating {
+        Rating::live(100, elo).with_record(record)
+    }
+
+    fn record(w: u32) -> Option<Record> {
+        Some(Record { w, l: 4, t: 1 })
+    }
+
+    #[test]
+    fn keeps_ratings_whose_elo_or_record_changed() {
+        let profile = profile(json!({
+            "ratings": {
+                "gen9ou": {"elo": 1500.0, "w": 10, "l": 4, "t": 1},
+                "gen9uu": {"elo": 1550.0, "w": 10, "l": 4, "t": 1},
+                "gen9ubers": {"elo": 1500.0, "w": 11, "l": 4, "t": 1},
+                "gen9lc": {"elo": 1200.0},
+                "gen9randombattle": {"elo": 1300.0},
+            },
+        }));
+        let latest = HashMap::from([
+            ("gen9ou", stored(1500.0, record(10))),
+            ("gen9uu", stored(1500.0, record(10))),
+            ("gen9ubers", stored(1500.0, record(10))),
+            // Showdown reporting no record says nothing about games played.
+            ("gen9lc", stored(1200.0, record(3))),
+        ]);
+
+        let mut changed =
+            changed_ratings("someuser", &profile, 200, &BOUNDS, |f| latest.get(f)).unwrap();
+        changed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            changed,
+            [
+                ("gen9randombattle".to_string(), Rating::live(200, 1300.0)),
+                (
+                    "gen9ubers".to_string(),
+                    Rating::live(200, 1500.0).with_record(record(11))
+                ),
+                (
+                    "gen9uu".to_string(),
+                    Rating::live(200, 1550.0).with_record(record(10))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_unbelievable_and_unreadable_elos() {
+        let profile = profile(json!({
+            "ratings": {
+                "gen9ou": {"elo": 999.0},
+                "gen9uu": {"elo": 10001.0},
+                "gen9lc": {"elo": "high"},
+                "gen9ubers": {"elo": 1000.0},
+            },
+        }));
+
+        let changed = changed_ratings("someuser", &profile, 200, &BOUNDS, |_| None).unwrap();
+
+        assert_eq!(
+            changed,
+            [("gen9ubers".to_string(), Rating::live(200, 1000.0))]
+        );
+    }
+
+    #[test]
+    fn rejects_profiles_without_ratings() {
+        let profile = profile(json!({"username": "Some User", "userid": "someuser"}));
+        assert_eq!(
+            changed_ratings("someuser", &profile, 200, &BOUNDS, |_| None),
+            Err("Error parsing PS user JSON ratings for user ID: someuser".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_the_username_only_for_the_requested_user_id() {
+        let profile = profile(json!({"username": "Some User", "userid": "someuser"}));
+        assert_eq!(profile_username("someuser", &profile), Ok("Some User"));
+        assert_eq!(
+            profile_username("otheruser", &profile),
+            Err("Showdown reports user ID: someuser for user ID: otheruser, skipping".to_string())
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// Tracks whether the task has been asked to stop (SIGTERM from ECS or ctrl-c locally).
+#[derive(Clone)]
+pub(crate) struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Spawns a background task that flips the shutdown flag on the first SIGTERM or SIGINT.
+    pub(crate) fn listen() -> Shutdown {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            let mut sigterm = match signal(SignalKind::terminate()) {
+                Ok(val) => val,
+                Err(e) => {
+                    println!("ERROR: unable to listen for SIGTERM: {:?}", e);
+                    return;
+                }
+            };
+            tokio::select! {
+                _ = sigterm.recv() => println!("Received SIGTERM, finishing in-flight work..."),
+                result = tokio::signal::ctrl_c() => match result {
+                    Ok(()) => println!("Received SIGINT, finishing in-flight work..."),
+                    Err(e) => {
+                        // Not being able to listen for ctrl-c is no reason to stop; keep waiting
+                        // for SIGTERM.
+                        println!("ERROR: unable to listen for SIGINT: {:?}", e);
+                        sigterm.recv().await;
+                        println!("Received SIGTERM, finishing in-flight work...");
+                    }
+                },
+            }
+            let _ = sender.send(true);
+        });
+        Shutdown { receiver }
+    }
+
+    /// A shutdown requested by sending `true` on the returned sender rather than by a signal.
+    #[cfg(test)]
+    pub(crate) fn manual() -> (watch::Sender<bool>, Shutdown) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Shutdown { receiver })
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Sleeps for `duration`, returning early if shutdown is requested in the meantime.
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = self.requested() => {}
+        }
+    }
+
+    /// Waits until shutdown is requested.
+    pub(crate) async fn requested(&self) {
+        let mut receiver = self.receiver.clone();
+        if receiver.wait_for(|requested| *requested).await.is_err() {
+            // The signal listener could not start, so shutdown is never requested.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn sleep_runs_its_course_without_a_signal_listener() {
+        let (sender, shutdown) = Shutdown::manual();
+        drop(sender);
+        let start = Instant::now();
+        shutdown.sleep(Duration::from_millis(50)).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(!shutdown.is_requested());
+    }
+
+    #[tokio::test]
+    async fn sleep_ends_early_on_request() {
+        let (sender, shutdown) = Shutdown::manual();
+        let start = Instant::now();
+        let sleep = shutdown.sleep(Duration::from_secs(60));
+        sender.send(true).unwrap();
+        sleep.await;
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
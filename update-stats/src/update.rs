@@ -0,0 +1,467 @@
+//! Refreshes stored users from Showdown, for the DynamoDB sweep and for any other
+//! [`StorageBackend`], such as the local development server's.
+
+use crate::showdown::{changed_ratings, fetch_profile, profile_username};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::highlights::Achievement;
+use user_stats_core::model::Rating;
+use user_stats_core::storage::{Item, StoreError, StoredUser, UserStore, USER_ID_KEY};
+use user_stats_core::webhooks::RatingEvent;
+
+/// Default smallest fall in elo raised as a sharp drop event.
+pub const SHARP_DROP_ELO: f64 = 100.0;
+
+/// What refreshing a single user changed.
+pub struct UserUpdate {
+    pub user_id: String,
+    /// Ratings appended to the history, keyed by format.
+    pub new_ratings: Vec<(String, Rating)>,
+    /// New peaks, milestones and sharp drops raised by the new ratings.
+    pub events: Vec<RatingEvent>,
+    /// The user's display username changed or their stored user id did not match their key, so
+    /// they were written even if no ratings were new.
+    pub rewritten: bool,
+}
+
+pub enum UpdateError {
+    Failed(String),
+    /// The user's stored data could not be read, or Showdown reports them under another user id,
+    /// so they were quarantined for repair.
+    Quarantined(String),
+}
+
+impl From<String> for UpdateError {
+    fn from(msg: String) -> UpdateError {
+        UpdateError::Failed(msg)
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Failed(msg) => write!(f, "{}", msg),
+            UpdateError::Quarantined(msg) => write!(f, "{} (quarantined)", msg),
+        }
+    }
+}
+
+/// Refreshes stored users from Showdown. In dry-run mode the would-be changes are printed instead
+/// of written.
+pub struct Updater<S> {
+    pub store: S,
+    /// Base URL of the Showdown server profiles are fetched from.
+    pub showdown_url: String,
+    /// Elos outside this range are treated as bad data from Showdown and ignored.
+    pub elo_bounds: RangeInclusive<f64>,
+    /// Smallest fall in elo raised as a sharp drop event.
+    pub sharp_drop_elo: f64,
+    pub dry_run: bool,
+}
+
+impl<S: StorageBackend + Sync> Updater<S> {
+    /// Refreshes every stored user once, returning how many new ratings were pushed. Used where
+    /// there is no DynamoDB sweep with its checkpoints and leases, such as the local development
+    /// server.
+    pub async fn sweep(&self) -> usize {
+        let user_ids = match self.store.user_ids().await {
+            Ok(val) => val,
+            Err(e) => {
+                println!("ERROR: unable to list users: {}", e);
+                return 0;
+            }
+        };
+        let mut pushed = 0;
+        for user_id in user_ids {
+            match self.update_user_id(&user_id).await {
+                Ok(update) => pushed += update.new_ratings.len(),
+                Err(e) => println!("ERROR: {}", e),
+            }
+        }
+        pushed
+    }
+
+    /// Looks up a single tracked user by id and refreshes it.
+    pub async fn update_user_id(&self, user_id: &str) -> Result<UserUpdate, UpdateError> {
+        match self.store.load_stored(user_id).await {
+            Ok(Some(stored)) => self.update_stored(user_id, stored).await,
+            Ok(None) => Err(format!("User ID: {} is not being tracked", user_id).into()),
+            Err(e) if e.is_corrupt() => Err(self.quarantine_unreadable(user_id, e).await),
+            Err(e) => Err(format!("Error reading user ID: {}: {}", user_id, e).into()),
+        }
+    }
+
+    async fn update_stored(
+        &self,
+        user_id: &str,
+        mut stored: StoredUser,
+    ) -> Result<UserUpdate, UpdateError> {
+        println!("Processing user: {}", user_id);
+
+        let profile = fetch_profile(&self.showdown_url, user_id).await?;
+        // The account was renamed onto another user id, so every sweep would fail the same way
+        // until the two users are linked.
+        if let Some(reported) = profile.json["userid"].as_str().filter(|r| *r != user_id) {
+            let msg = format!(
+                "Showdown reports user ID: {} for user ID: {}; link them with the admin `alias` tool",
+                reported, user_id
+            );
+            return Err(self.quarantine(user_id, &msg, msg.clone()).await);
+        }
+        let username = profile_username(user_id, &profile)?;
+        let renamed = username != stored.username();
+        // Writes are keyed by the stored user id, so one that differs from the key would fork the
+        // user's history.
+        let rekeyed = stored.userid() != user_id;
+
+        let current_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(val) => val.as_secs(),
+            Err(e) => return Err(format!("Error getting current time: {:?}", e).into()),
+        };
+
+        let mut update = UserUpdate {
+            user_id: user_id.to_string(),
+            new_ratings: changed_ratings(
+                user_id,
+                &profile,
+                current_time,
+                &self.elo_bounds,
+                |format| stored.latest_rating(format),
+            )?,
+            events: Vec::new(),
+            rewritten: renamed || rekeyed,
+        };
+
+        // Nothing new from Showdown, so the stored record is already current.
+        if update.new_ratings.is_empty() && !update.rewritten {
+            println!("No rating changes for user ID: {}, skipping write", user_id);
+            return Ok(update);
+        }
+
+        if self.dry_run {
+            for (format, rating) in &update.new_ratings {
+                match serde_json::to_string(rating) {
+                    Ok(val) => println!("DRY RUN: would push {} {}: {}", user_id, format, val),
+                    Err(e) => println!("Error serializing rating: {:?}", e),
+                }
+            }
+            if renamed {
+                println!(
+                    "DRY RUN: would rename user ID: {} from {} to {}",
+                    user_id,
+                    stored.username(),
+                    username
+                );
+            }
+            if rekeyed {
+                println!(
+                    "DRY RUN: would move user ID: {} stored as {} under its key",
+                    user_id,
+                    stored.userid()
+                );
+            }
+            println!("DRY RUN: skipping write for user ID: {}", user_id);
+            return Ok(update);
+        }
+
+        if renamed {
+            println!(
+                "Renaming user ID: {} from {} to {}",
+                user_id,
+                stored.username(),
+                username
+            );
+            stored.set_username(username);
+        }
+        if rekeyed {
+            println!(
+                "Moving user ID: {} stored as {} under its key",
+                user_id,
+                stored.userid()
+            );
+            stored = match self.store.rekey(stored, user_id).await {
+                Ok(val) => val,
+                Err(e) if e.is_corrupt() => {
+                    return Err(self.quarantine_unreadable(user_id, e).await)
+                }
+                Err(e) => return Err(format!("Error moving user ID: {}: {}", user_id, e).into()),
+            };
+        }
+        let previous: HashMap<&str, Rating> = update
+            .new_ratings
+            .iter()
+            .filter_map(|(format, _)| {
+                let latest = stored.latest_rating(format)?;
+                Some((format.as_str(), latest.clone()))
+            })
+            .collect();
+        match self.store.append_stored(stored, &update.new_ratings).await {
+            Ok(achievements) => {
+                for (format, rating) in &update.new_ratings {
+                    let achieved: Vec<Achievement> = achievements
+                        .iter()
+                        .filter(|(f, _)| f == format)
+                        .map(|(_, achievement)| *achievement)
+                        .collect();
+                    update.events.extend(RatingEvent::from_rating(
+                        user_id,
+                        username,
+                        format,
+                        previous.get(format.as_str()),
+                        rating,
+                        &achieved,
+                        self.sharp_drop_elo,
+                    ));
+                }
+                Ok(update)
+            }
+            Err(e) if e.is_corrupt() => Err(self.quarantine_unreadable(user_id, e).await),
+            Err(e) => Err(format!("Error writing user ID: {}: {}", user_id, e).into()),
+        }
+    }
+
+    /// Moves a user whose stored data cannot be read out of the sweep so they can be repaired
+    /// with the admin `repair` tool.
+    async fn quarantine_unreadable(&self, user_id: &str, error: StoreError) -> UpdateError {
+        let msg = format!("Unreadable data for user ID: {}: {}", user_id, error);
+        self.quarantine(user_id, &error.to_string(), msg).await
+    }
+
+    /// Moves a user out of the sweep, recording `reason` on their head item.
+    async fn quarantine(&self, user_id: &str, reason: &str, msg: String) -> UpdateError {
+        if self.dry_run {
+            println!("DRY RUN: would quarantine user ID: {}", user_id);
+            return UpdateError::Failed(msg);
+        }
+        match self.store.quarantine(user_id, reason).await {
+            Ok(true) => UpdateError::Quarantined(msg),
+            Ok(false) => UpdateError::Failed(msg),
+            Err(e) => UpdateError::Failed(format!("{}; quarantining failed: {}", msg, e)),
+        }
+    }
+}
+
+impl Updater<UserStore> {
+    /// Fetches the latest ratings for the user whose head item is `item` and appends any changes
+    /// to their history.
+    pub async fn update_user(&self, item: &Item) -> Result<UserUpdate, UpdateError> {
+        let user_id = match item.get(USER_ID_KEY) {
+            Some(val) => val,
+            None => return Err(format!("Item {:?} is missing 'userId' key", item).into()),
+        };
+        let user_id = match user_id.as_s() {
+            Ok(val) => val,
+            Err(_) => return Err(format!("Item {:?} 'userId' key is not a string", item).into()),
+        };
+        let stored = match StoredUser::from_item(item) {
+            Ok(val) => val,
+            Err(e) if e.is_corrupt() => return Err(self.quarantine_unreadable(user_id, e).await),
+            Err(e) => return Err(format!("Error reading user ID: {}: {}", user_id, e).into()),
+        };
+        self.update_stored(user_id, stored).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::showdown::{MAX_ELO, MIN_ELO};
+    use integration_tests::dynamo::{FakeDynamo, Item as FakeItem};
+    use integration_tests::showdown::MockShowdown;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use user_stats_core::local::LocalStore;
+    use user_stats_core::model::{Record, User};
+    use user_stats_core::storage::quarantine_error;
+
+    const TABLE: &str = "users";
+
+    struct Env {
+        dynamo: FakeDynamo,
+        showdown: MockShowdown,
+        updater: Updater<UserStore>,
+    }
+
+    impl Env {
+        /// Stand-ins holding `someuser`, rated 1500 in gen9ou, updated with `dry_run`.
+        async fn start(dry_run: bool) -> Env {
+            let dynamo = FakeDynamo::start(&[(TABLE, USER_ID_KEY)]).await;
+            let showdown = MockShowdown::start().await;
+            let updater = Updater {
+                store: UserStore::new(dynamo.client(), TABLE),
+                showdown_url: showdown.url().to_string(),
+                elo_bounds: MIN_ELO..=MAX_ELO,
+                sharp_drop_elo: SHARP_DROP_ELO,
+                dry_run,
+            };
+            let user = User {
+                username: "Some User".to_string(),
+                userid: "someuser".to_string(),
+                formats: HashMap::from([(
+                    "gen9ou".to_string(),
+                    vec![Rating::live(100, 1500.0).with_record(record(10))],
+                )]),
+            };
+            updater.store.save_user(&user).await.unwrap();
+            Env {
+                dynamo,
+                showdown,
+                updater,
+            }
+        }
+
+        fn set_profile(&self, username: &str, userid: &str, elo: f64, wins: u32) {
+            self.showdown.set_profile(
+                "someuser",
+                json!({
+                    "username": username,
+                    "userid": userid,
+                    "ratings": {"gen9ou": {"elo": elo, "w": wins, "l": 4, "t": 1}},
+                }),
+            );
+        }
+
+        async fn update(&self) -> Result<UserUpdate, UpdateError> {
+            self.updater.update_user_id("someuser").await
+        }
+
+        async fn stored(&self) -> StoredUser {
+            self.updater.store.load("someuser").await.unwrap().unwrap()
+        }
+
+        fn items(&self) -> BTreeMap<String, FakeItem> {
+            self.dynamo.items(TABLE)
+        }
+    }
+
+    fn record(w: u32) -> Option<Record> {
+        Some(Record { w, l: 4, t: 1 })
+    }
+
+    fn stored_elo(stored: &StoredUser) -> Option<f64> {
+        stored.latest_rating("gen9ou").map(|rating| rating.elo)
+    }
+
+    #[tokio::test]
+    async fn skips_the_write_when_nothing_changed() {
+        let env = Env::start(false).await;
+        env.set_profile("Some User", "someuser", 1500.0, 10);
+        let before = env.items();
+
+        let update = env.update().await.unwrap_or_else(|e| panic!("{}", e));
+
+        assert!(update.new_ratings.is_empty());
+        assert!(!update.rewritten);
+        assert_eq!(env.items(), before);
+    }
+
+    #[tokio::test]
+    async fn appends_changed_ratings() {
+        let env = Env::start(false).await;
+        env.set_profile("Some User", "someuser", 1550.0, 11);
+
+        let update = env.update().await.unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(update.new_ratings.len(), 1);
+        assert_eq!(update.new_ratings[0].1.record, record(11));
+        assert!(!update.rewritten);
+        assert_eq!(stored_elo(&env.stored().await), Some(1550.0));
+    }
+
+    #[tokio::test]
+    async fn renames_users_whose_display_username_changed() {
+        let env = Env::start(false).await;
+        env.set_profile("SOME user", "someuser", 1500.0, 10);
+
+        let update = env.update().await.unwrap_or_else(|e| panic!("{}", e));
+
+        assert!(update.new_ratings.is_empty());
+        assert!(update.rewritten);
+        let stored = env.stored().await;
+        assert_eq!(stored.username(), "SOME user");
+        assert_eq!(stored_elo(&stored), Some(1500.0));
+    }
+
+    #[tokio::test]
+    async fn dry_runs_report_changes_without_writing() {
+        let env = Env::start(true).await;
+        env.set_profile("SOME user", "someuser", 1550.0, 11);
+        let before = env.items();
+
+        let update = env.update().await.unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(update.new_ratings.len(), 1);
+        assert!(update.rewritten);
+        assert_eq!(env.items(), before);
+    }
+
+    #[tokio::test]
+    async fn quarantines_users_whose_profile_names_another_user_id() {
+        let env = Env::start(false).await;
+        env.set_profile("Other User", "otheruser", 1600.0, 20);
+
+        let error = match env.update().await {
+            Ok(_) => panic!("updated from another user's profile"),
+            Err(UpdateError::Failed(msg)) => panic!("not quarantined: {}", msg),
+            Err(UpdateError::Quarantined(msg)) => msg,
+        };
+
+        let reason = "Showdown reports user ID: otheruser for user ID: someuser; link them with \
+                      the admin `alias` tool";
+        assert_eq!(error, reason);
+        let item = env.updater.store.get_head_item("someuser").await.unwrap();
+        assert_eq!(quarantine_error(item.as_ref().unwrap()), Some(reason));
+        assert_eq!(stored_elo(&env.stored().await), Some(1500.0));
+    }
+
+    #[tokio::test]
+    async fn dry_runs_do_not_quarantine_users_whose_profile_names_another_user_id() {
+        let env = Env::start(true).await;
+        env.set_profile("Other User", "otheruser", 1600.0, 20);
+        let before = env.items();
+
+        assert!(matches!(env.update().await, Err(UpdateError::Failed(_))));
+        assert_eq!(env.items(), before);
+    }
+
+    #[tokio::test]
+    async fn sweeps_users_in_local_storage() {
+        let dir = std::env::temp_dir().join(format!("update-stats-local-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let showdown = MockShowdown::start().await;
+        let updater = Updater {
+            store: LocalStore::new(&dir),
+            showdown_url: showdown.url().to_string(),
+            elo_bounds: MIN_ELO..=MAX_ELO,
+            sharp_drop_elo: SHARP_DROP_ELO,
+            dry_run: false,
+        };
+        let user = User {
+            username: "Some User".to_string(),
+            userid: "someuser".to_string(),
+            formats: HashMap::from([(
+                "gen9ou".to_string(),
+                vec![Rating::live(100, 1500.0).with_record(record(10))],
+            )]),
+        };
+        updater.store.save_user(&user).await.unwrap();
+        showdown.set_profile(
+            "someuser",
+            json!({
+                "username": "Some User",
+                "userid": "someuser",
+                "ratings": {"gen9ou": {"elo": 1550.0, "w": 11, "l": 4, "t": 1}},
+            }),
+        );
+
+        assert_eq!(updater.sweep().await, 1);
+        assert_eq!(updater.sweep().await, 0);
+
+        let user = updater.store.load_user("someuser").await.unwrap().unwrap();
+        let elos: Vec<f64> = user.formats["gen9ou"].iter().map(|r| r.elo).collect();
+        assert_eq!(elos, [1500.0, 1550.0]);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::config::Config;
+use crate::current_timestamp;
+use crate::discord::Discord;
+use crate::shutdown::Shutdown;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use user_stats_core::storage::UserStore;
+use user_stats_core::webhooks::{RatingEvent, Subscription, Target};
+
+/// Deliveries waiting to be sent before enqueueing blocks the sweep.
+const QUEUE_CAPACITY: usize = 1000;
+/// Delay before the first retry, doubled for each one after.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Delivery {
+    subscription: Subscription,
+    event: RatingEvent,
+}
+
+/// Delivers rating events to webhook subscribers in the background, so a slow or failing endpoint
+/// does not hold up the sweep. Events for Discord webhooks are collected for up to the configured
+/// batch window so that a sweep updating many users sends a few messages instead of one each.
+/// Once shutdown is requested the queue is drained: batches are sent without waiting for the
+/// window and failed deliveries are not retried, so nothing queued is lost when the task stops.
+pub(crate) struct Notifier {
+    sender: mpsc::Sender<Delivery>,
+    task: JoinHandle<()>,
+}
+
+impl Notifier {
+    /// Starts the delivery task, which drains its queue once `shutdown` is requested.
+    pub(crate) fn start(config: &Config, shutdown: Shutdown) -> Notifier {
+        let (sender, mut receiver) = mpsc::channel::<Delivery>(QUEUE_CAPACITY);
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        let max_attempts = config.webhook_max_attempts;
+        let mut discord = Discord::new(client.clone(), max_attempts, &config.site_url);
+        let mut batch_window = tokio::time::interval(config.discord_batch_window());
+        let task = tokio::spawn(async move {
+            let mut deliveries = JoinSet::new();
+            let mut draining = false;
+            loop {
+                // Reap finished deliveries so the set does not grow for the life of the process.
+                while deliveries.try_join_next().is_some() {}
+                let delivery = tokio::select! {
+                    delivery = receiver.recv() => match delivery {
+                        Some(delivery) => delivery,
+                        None => break,
+                    },
+                    _ = batch_window.tick(), if !draining => {
+                        discord.flush(&mut deliveries);
+                        continue;
+                    }
+                    _ = shutdown.requested(), if !draining => {
+                        println!("Draining {} queued webhook deliveries...", receiver.len());
+                        draining = true;
+                        discord.flush(&mut deliveries);
+                        continue;
+                    }
+                };
+                match delivery.subscription.target {
+                    Target::Json => {
+                        deliveries.spawn(deliver(
+                            client.clone(),
+                            delivery,
+                            max_attempts,
+                            shutdown.clone(),
+                        ));
+                    }
+                    Target::Discord => {
+                        discord.push(&delivery.subscription.url, &delivery.event, &mut deliveries);
+                    }
+                }
+                // Batches still fill up from whatever is queued, but do not wait for more.
+                if draining && receiver.is_empty() {
+                    discord.flush(&mut deliveries);
+                }
+            }
+            discord.flush(&mut deliveries);
+            // Lets each Discord channel task end once its queue is empty.
+            drop(discord);
+            while deliveries.join_next().await.is_some() {}
+        });
+        Notifier { sender, task }
+    }
+
+    /// Queues `events` for delivery to the user's matching webhook subscriptions. Failing to read
+    /// the subscriptions does not fail the update, since the ratings are already written.
+    pub(crate) async fn notify(&self, store: &UserStore, user_id: &str, events: &[RatingEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            println!(
+                "Event for user ID: {} {}: {} at {}",
+                user_id, event.format, event.kind, event.elo
+            );
+        }
+        let subscriptions = match store.subscriptions(user_id).await {
+            Ok(val) => val,
+            Err(e) => {
+                println!(
+                    "ERROR: unable to read subscriptions for user ID: {}: {}",
+                    user_id, e
+                );
+                return;
+            }
+        };
+        for event in events {
+            for subscription in subscriptions.iter().filter(|s| s.matches(event)) {
+                self.enqueue(subscription, event).await;
+            }
+        }
+    }
+
+    pub(crate) async fn enqueue(&self, subscription: &Subscription, event: &RatingEvent) {
+        let delivery = Delivery {
+            subscription: subscription.clone(),
+            event: event.clone(),
+        };
+        if self.sender.send(delivery).await.is_err() {
+            println!(
+                "ERROR: webhook queue is closed, dropping {} event for subscription {}",
+                event.kind, subscription.id
+            );
+        }
+    }
+
+    /// Waits for every queued delivery to succeed or run out of attempts.
+    pub(crate) async fn finish(self) {
+        drop(self.sender);
+        if let Err(e) = self.task.await {
+            println!("ERROR: webhook delivery task failed: {}", e);
+        }
+    }
+}
+
+async fn deliver(
+    client: reqwest::Client,
+    delivery: Delivery,
+    max_attempts: u32,
+    shutdown: Shutdown,
+) {
+    let Delivery {
+        subscription,
+        event,
+    } = delivery;
+    let body = match serde_json::to_vec(&event) {
+        Ok(val) => val,
+        Err(e) => {
+            println!("ERROR: unable to serialize webhook event: {}", e);
+            return;
+        }
+    };
+
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=max_attempts {
+        let timestamp = current_timestamp();
+        let mut request = client
+            .post(&subscription.url)
+            .header("content-type", "application/json")
+            .header("x-user-stats-event", event.kind.to_string())
+            .header("x-user-stats-subscription", &subscription.id)
+            .header("x-user-stats-timestamp", timestamp.to_string());
+        if let Some(secret) = &subscription.secret {
+            request = request.header(
+                "x-user-stats-signature",
+                signature(secret, timestamp, &body),
+            );
+        }
+
+        let retry = match request.body(body.clone()).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                println!(
+                    "Delivered {} event for user ID: {} to subscription {}",
+                    event.kind, event.userid, subscription.id
+                );
+                return;
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                println!(
+                    "ERROR: subscription {} replied with status code: {} (attempt {} of {})",
+                    subscription.id, status, attempt, max_attempts
+                );
+                // Other client errors will not succeed on a retry.
+                status.is_server_error() || status.as_u16() == 429 || status.as_u16() == 408
+            }
+            Err(e) => {
+                println!(
+                    "ERROR: unable to reach subscription {}: {} (attempt {} of {})",
+                    subscription.id, e, attempt, max_attempts
+                );
+                true
+            }
+        };
+        if !retry || attempt == max_attempts || shutdown.is_requested() {
+            break;
+        }
+        shutdown.sleep(delay).await;
+        delay *= 2;
+    }
+    println!(
+        "ERROR: giving up on {} event for user ID: {} to subscription {}",
+        event.kind, event.userid, subscription.id
+    );
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256, keyed with `secret`, of `timestamp`, a `.` and
+/// `body`. Signing the timestamp lets receivers reject old deliveries replayed to them.
+fn signature(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use integration_tests::webhook::MockWebhook;
+    use user_stats_core::webhooks::EventKind;
+
+    fn subscription(url: String, secret: Option<&str>) -> Subscription {
+        Subscription {
+            id: "sub".to_string(),
+            url,
+            target: Target::Json,
+            format: None,
+            events: vec![],
+            secret: secret.map(str::to_string),
+        }
+    }
+
+    fn event() -> RatingEvent {
+        RatingEvent {
+            kind: EventKind::NewPeak,
+            userid: "someuser".to_string(),
+            username: "Some User".to_string(),
+            format: "gen9ou".to_string(),
+            time: 100,
+            elo: 1600.0,
+            previous_elo: Some(1550.0),
+            milestone: None,
+        }
+    }
+
+    #[test]
+    fn signs_the_timestamp_and_body_with_hmac_sha256() {
+        assert_eq!(
+            signature(
+                "key",
+                1700000000,
+                b"The quick brown fox jumps over the lazy dog"
+            ),
+            "sha256=2f658d6aef4f246e91cd741bbcded7479e9605f9d41c9e248122a117e0e1765b"
+        );
+        assert_eq!(
+            signature("", 0, b""),
+            "sha256=b849d5a581847b281957065739df36df2463d1977ea8d6e1e4e6cf33fadc68c3"
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_with_backoff() {
+        let hooks = MockWebhook::start().await;
+        hooks.push_reply("a", 500, "");
+        hooks.push_reply("a", 503, "");
+        let delivery = Delivery {
+            subscription: subscription(hooks.url("a"), Some("secret")),
+            event: event(),
+        };
+        let (_requested, shutdown) = Shutdown::manual();
+
+        deliver(reqwest::Client::new(), delivery, 3, shutdown).await;
+
+        let deliveries = hooks.deliveries();
+        assert_eq!(deliveries.len(), 3);
+        let body = serde_json::to_vec(&event()).unwrap();
+        for delivery in &deliveries {
+            assert_eq!(delivery.body, serde_json::to_value(event()).unwrap());
+            assert_eq!(delivery.headers["x-user-stats-event"], "new-peak");
+            assert_eq!(delivery.headers["x-user-stats-subscription"], "sub");
+            let timestamp = delivery.headers["x-user-stats-timestamp"].parse().unwrap();
+            assert_eq!(
+                delivery.headers["x-user-stats-signature"],
+                signature("secret", timestamp, &body)
+            );
+        }
+        let first_wait = deliveries[1].received_at - deliveries[0].received_at;
+        let second_wait = deliveries[2].received_at - deliveries[1].received_at;
+        assert!(first_wait >= FIRST_RETRY_DELAY);
+        assert!(second_wait >= FIRST_RETRY_DELAY * 2);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let hooks = MockWebhook::start().await;
+        hooks.push_reply("a", 429, "");
+        hooks.push_reply("a", 502, "");
+        hooks.push_reply("a", 408, "");
+        let delivery = Delivery {
+            subscription: subscription(hooks.url("a"), None),
+            event: event(),
+        };
+        let (_requested, shutdown) = Shutdown::manual();
+
+        deliver(reqwest::Client::new(), delivery, 2, shutdown).await;
+
+        let deliveries = hooks.deliveries();
+        assert_eq!(deliveries.len(), 2);
+        assert!(!deliveries[0].headers.contains_key("x-user-stats-signature"));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let hooks = MockWebhook::start().await;
+        hooks.push_reply("a", 404, "");
+        let delivery = Delivery {
+            subscription: subscription(hooks.url("a"), None),
+            event: event(),
+        };
+        let (_requested, shutdown) = Shutdown::manual();
+
+        deliver(reqwest::Client::new(), delivery, 3, shutdown).await;
+
+        assert_eq!(hooks.deliveries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn drains_the_queue_without_waiting_once_shutdown_is_requested() {
+        let hooks = MockWebhook::start().await;
+        hooks.push_reply("json", 500, "");
+        let config = Config {
+            webhook_max_attempts: 3,
+            discord_batch_secs: 3600,
+            ..Config::default()
+        };
+        let (requested, shutdown) = Shutdown::manual();
+        let notifier = Notifier::start(&config, shutdown);
+
+        requested.send(true).unwrap();
+        let discord = Subscription {
+            target: Target::Discord,
+            ..subscription(hooks.url("discord"), None)
+        };
+        notifier.enqueue(&discord, &event()).await;
+        notifier
+            .enqueue(&subscription(hooks.url("json"), None), &event())
+            .await;
+        tokio::time::sleep(FIRST_RETRY_DELAY / 2).await;
+
+        let deliveries = hooks.deliveries();
+        assert_eq!(deliveries.iter().filter(|d| d.hook == "discord").count(), 1);
+        assert_eq!(deliveries.iter().filter(|d| d.hook == "json").count(), 1);
+        notifier.finish().await;
+        assert_eq!(hooks.deliveries().len(), 2);
+    }
+}
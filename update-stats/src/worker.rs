@@ -0,0 +1,462 @@
+use crate::checkpoint::{CheckpointStore, SweepCheckpoint};
+use crate::config::Config;
+use crate::current_timestamp;
+use crate::lease::{LeaseManager, Segment};
+use crate::shutdown::Shutdown;
+use crate::webhooks::Notifier;
+use aws_sdk_cloudwatch::types::{MetricDatum, StandardUnit};
+use aws_sdk_dynamodb::types::{AttributeValue, ConditionCheck};
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use update_stats::update::{UpdateError, Updater, UserUpdate};
+use user_stats_core::storage::{UserStore, USER_ID_KEY};
+
+/// How long to wait before retrying when every segment is owned by another worker.
+const STANDBY_INTERVAL: Duration = Duration::from_secs(30);
+
+enum SweepOutcome {
+    Completed,
+    Interrupted,
+    LeaseLost,
+    /// A scan failed while the segment was leased. The worker backs off only once it has given
+    /// the lease up, so the back-off can outlast the lease.
+    ScanFailed,
+}
+
+/// Counts of what a sweep did, reported as metrics and printed by one-shot runs.
+#[derive(Debug, Default)]
+pub(crate) struct SweepStats {
+    pub(crate) processed: u64,
+    pub(crate) updated: u64,
+    pub(crate) new_ratings: u64,
+    /// Users whose ratings and username had not changed, so no write was needed.
+    pub(crate) skipped_writes: u64,
+    pub(crate) failed: u64,
+    /// Users moved to quarantine because their stored data could not be read or Showdown reports
+    /// them under another user id.
+    pub(crate) quarantined: u64,
+}
+
+impl SweepStats {
+    fn record(&mut self, result: &Result<UserUpdate, UpdateError>) {
+        self.processed += 1;
+        match result {
+            Ok(update) if !update.new_ratings.is_empty() || update.rewritten => {
+                self.updated += 1;
+                self.new_ratings += update.new_ratings.len() as u64;
+            }
+            Ok(_) => self.skipped_writes += 1,
+            Err(UpdateError::Quarantined(_)) => self.quarantined += 1,
+            Err(UpdateError::Failed(_)) => self.failed += 1,
+        }
+    }
+}
+
+/// Sweeps leased segments of the user stats table one at a time until shutdown is requested.
+pub(crate) struct Worker {
+    pub(crate) cloud_watch: aws_sdk_cloudwatch::Client,
+    pub(crate) config: Config,
+    pub(crate) updater: Updater<UserStore>,
+    pub(crate) notifier: Notifier,
+    pub(crate) checkpoints: Option<CheckpointStore>,
+    pub(crate) leases: Option<LeaseManager>,
+    pub(crate) shutdown: Shutdown,
+}
+
+impl Worker {
+    /// Claims segments round-robin, sweeping each one once and releasing its lease before moving
+    /// on, so that every segment keeps being swept while any worker is running.
+    pub(crate) async fn run(&self) {
+        let total = self.config.total_segments;
+        let mut next = match &self.leases {
+            Some(leases) => leases.preferred_index(total),
+            None => 0,
+        };
+        // Without a state table there is a single worker, so it can remember its own sweeps.
+        let mut last_sweeps = HashMap::new();
+        while !self.shutdown.is_requested() {
+            let segment = match self.claim_segment(next, &last_sweeps).await {
+                Ok(segment) => segment,
+                Err(wait_time) => {
+                    println!(
+                        "No segment is due for a sweep and free to lease. Waiting for {} milis...",
+                        wait_time.as_millis()
+                    );
+                    self.shutdown.sleep(wait_time).await;
+                    continue;
+                }
+            };
+            println!("Sweeping segment {} of {}", segment.index, segment.total);
+            let outcome = self.run_segment(segment).await;
+            self.release_lease(segment).await;
+            match outcome {
+                Ok(started_at) => {
+                    last_sweeps.insert(segment.index, started_at);
+                }
+                // The checkpoint lets whichever worker takes the segment next resume it.
+                Err(SweepOutcome::ScanFailed) => {
+                    self.shutdown.sleep(self.config.error_sleep()).await;
+                }
+                Err(_) => {}
+            }
+            next = (segment.index + 1) % total;
+        }
+    }
+
+    /// Sweeps every segment once without leases or checkpoints, for debugging and backfills.
+    pub(crate) async fn run_once(&self) -> SweepStats {
+        let mut stats = SweepStats::default();
+        for index in 0..self.config.total_segments {
+            let segment = Segment {
+                index,
+                total: self.config.total_segments,
+            };
+            match self.sweep(segment, None, &mut stats).await {
+                SweepOutcome::Completed => {}
+                SweepOutcome::Interrupted | SweepOutcome::LeaseLost | SweepOutcome::ScanFailed => {
+                    break
+                }
+            }
+        }
+        stats
+    }
+
+    /// Leases the first segment from `start` on that is due for a sweep. Otherwise returns how
+    /// long to wait before trying again.
+    async fn claim_segment(
+        &self,
+        start: i32,
+        last_sweeps: &HashMap<i32, u64>,
+    ) -> Result<Segment, Duration> {
+        let total = self.config.total_segments;
+        let mut wait_time = STANDBY_INTERVAL;
+        for offset in 0..total {
+            let segment = Segment {
+                index: (start + offset) % total,
+                total,
+            };
+            let due_in = self.due_in(segment, last_sweeps).await;
+            if !due_in.is_zero() {
+                wait_time = wait_time.min(due_in);
+                continue;
+            }
+            let leases = match &self.leases {
+                Some(leases) => leases,
+                None => return Ok(segment),
+            };
+            match leases.try_acquire(segment).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                    continue;
+                }
+            }
+            // Another worker may have finished sweeping the segment since it was checked.
+            let due_in = self.due_in(segment, last_sweeps).await;
+            if !due_in.is_zero() {
+                wait_time = wait_time.min(due_in);
+                self.release_lease(segment).await;
+                continue;
+            }
+            println!(
+                "Worker {} acquired lease for segment {}",
+                leases.owner(),
+                segment.index
+            );
+            return Ok(segment);
+        }
+        Err(wait_time)
+    }
+
+    /// How long until `segment` is due for its next sweep, zero if it is due now.
+    async fn due_in(&self, segment: Segment, last_sweeps: &HashMap<i32, u64>) -> Duration {
+        let last_sweep_at = match &self.checkpoints {
+            Some(store) => match store.last_sweep_at(segment).await {
+                Ok(val) => val,
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                    None
+                }
+            },
+            None => last_sweeps.get(&segment.index).copied(),
+        };
+        match last_sweep_at {
+            Some(at) => Duration::from_secs(
+                (at + self.config.sweep_interval_secs).saturating_sub(current_timestamp()),
+            ),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Sweeps `segment` once, resuming from its checkpoint. Returns when the sweep started if it
+    /// completed, and otherwise why it stopped.
+    async fn run_segment(&self, segment: Segment) -> Result<u64, SweepOutcome> {
+        let resume = match &self.checkpoints {
+            Some(store) => match store.load(segment).await {
+                Ok(val) => val,
+                Err(e) => {
+                    println!("ERROR: {}. Starting a fresh sweep", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let started_at = current_timestamp();
+        let sweep_start_time = Instant::now();
+        let mut stats = SweepStats::default();
+        match self.sweep(segment, resume, &mut stats).await {
+            SweepOutcome::Completed => {}
+            SweepOutcome::LeaseLost => {
+                println!("Lost lease for segment {}. Stopping sweep", segment.index);
+                return Err(SweepOutcome::LeaseLost);
+            }
+            outcome => return Err(outcome),
+        };
+
+        if let Some(store) = &self.checkpoints {
+            match store
+                .complete(segment, started_at, self.lease_condition(segment))
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    println!(
+                        "Lost lease for segment {}. Not recording the sweep as complete",
+                        segment.index
+                    );
+                    return Err(SweepOutcome::LeaseLost);
+                }
+                Err(e) => println!("ERROR: {}", e),
+            }
+        }
+
+        let elapsed = sweep_start_time.elapsed();
+        let wait_time = self.config.sweep_interval().saturating_sub(elapsed);
+        println!("scan time: {} milis", elapsed.as_millis());
+        println!(
+            "Scan each segment once every {} secs. Segment {} is due again in {} milis",
+            self.config.sweep_interval_secs,
+            segment.index,
+            wait_time.as_millis()
+        );
+        self.put_sweep_metrics(wait_time, &stats).await;
+        Ok(started_at)
+    }
+
+    async fn release_lease(&self, segment: Segment) {
+        if let Some(leases) = &self.leases {
+            if let Err(e) = leases.release(segment).await {
+                println!("ERROR: {}", e);
+            }
+        }
+    }
+
+    async fn sweep(
+        &self,
+        segment: Segment,
+        resume: Option<SweepCheckpoint>,
+        stats: &mut SweepStats,
+    ) -> SweepOutcome {
+        let (sweep_id, mut exclusive_start_key) = match resume {
+            Some(checkpoint) => {
+                println!(
+                    "Resuming sweep {} of segment {} from checkpoint written at {}",
+                    checkpoint.sweep_id, segment.index, checkpoint.updated_at
+                );
+                (checkpoint.sweep_id, checkpoint.last_evaluated_key)
+            }
+            None => (current_timestamp().to_string(), None),
+        };
+        loop {
+            if self.shutdown.is_requested() {
+                break;
+            }
+            if !self.renew_lease(segment).await {
+                return SweepOutcome::LeaseLost;
+            }
+            let mut renewed_at = Instant::now();
+            let scan_page_start_time = Instant::now();
+            let (items, last_evaluated_key) = match self
+                .updater
+                .store
+                .scan_heads(
+                    exclusive_start_key.clone(),
+                    segment.index,
+                    segment.total,
+                    self.config.page_size,
+                )
+                .await
+            {
+                Ok(resp) => resp,
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                    if self.leases.is_some() {
+                        return SweepOutcome::ScanFailed;
+                    }
+                    self.shutdown.sleep(self.config.error_sleep()).await;
+                    continue;
+                }
+            };
+
+            let mut interrupted = false;
+            for item in items {
+                if self.shutdown.is_requested() {
+                    interrupted = true;
+                    break;
+                }
+                // Updating a page of users can outlast the lease when Showdown is slow.
+                if renewed_at.elapsed() >= self.config.lease_duration() / 2 {
+                    if !self.renew_lease(segment).await {
+                        return SweepOutcome::LeaseLost;
+                    }
+                    renewed_at = Instant::now();
+                }
+                let result = self.updater.update_user(&item).await;
+                match &result {
+                    Ok(update) => {
+                        self.notifier
+                            .notify(&self.updater.store, &update.user_id, &update.events)
+                            .await
+                    }
+                    Err(e) => println!("ERROR: {}", e),
+                }
+                stats.record(&result);
+                if let Some(user_id) = item.get(USER_ID_KEY) {
+                    exclusive_start_key =
+                        Some(HashMap::from([(USER_ID_KEY.to_string(), user_id.clone())]));
+                }
+            }
+            if interrupted {
+                break;
+            }
+
+            exclusive_start_key = last_evaluated_key;
+
+            if exclusive_start_key.is_none() {
+                return SweepOutcome::Completed;
+            }
+
+            if !self
+                .save_checkpoint(segment, &sweep_id, &exclusive_start_key)
+                .await
+            {
+                return SweepOutcome::LeaseLost;
+            }
+
+            let time_passed = scan_page_start_time.elapsed();
+            let wait_time = self.config.page_interval().saturating_sub(time_passed);
+
+            println!("page scan time: {} milis", time_passed.as_millis());
+            println!(
+                "Scan page once every {} milis; Waiting for {} milis...",
+                self.config.page_interval_ms,
+                wait_time.as_millis()
+            );
+            self.shutdown.sleep(wait_time).await;
+        }
+
+        println!(
+            "Sweep {} of segment {} interrupted after {} items",
+            sweep_id, segment.index, stats.processed
+        );
+        if !self
+            .save_checkpoint(segment, &sweep_id, &exclusive_start_key)
+            .await
+        {
+            return SweepOutcome::LeaseLost;
+        }
+        SweepOutcome::Interrupted
+    }
+
+    /// Returns false only if another worker has taken the lease over. Transient errors keep the
+    /// sweep going since the lease outlives several pages.
+    async fn renew_lease(&self, segment: Segment) -> bool {
+        match &self.leases {
+            Some(leases) => match leases.renew(segment).await {
+                Ok(held) => held,
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                    true
+                }
+            },
+            None => true,
+        }
+    }
+
+    /// Only the lease owner may move a segment's checkpoint, so the lease is renewed first and the
+    /// write is conditional on still holding it. Returns false if the lease was lost.
+    async fn save_checkpoint(
+        &self,
+        segment: Segment,
+        sweep_id: &str,
+        last_evaluated_key: &Option<HashMap<String, AttributeValue>>,
+    ) -> bool {
+        let store = match &self.checkpoints {
+            Some(store) => store,
+            None => return true,
+        };
+        if !self.renew_lease(segment).await {
+            return false;
+        }
+        let checkpoint = SweepCheckpoint {
+            sweep_id: sweep_id.to_string(),
+            last_evaluated_key: last_evaluated_key.clone(),
+            updated_at: current_timestamp(),
+        };
+        match store
+            .save(segment, &checkpoint, self.lease_condition(segment))
+            .await
+        {
+            Ok(held) => held,
+            Err(e) => {
+                println!("ERROR: {}", e);
+                true
+            }
+        }
+    }
+
+    fn lease_condition(&self, segment: Segment) -> Option<ConditionCheck> {
+        self.leases.as_ref().map(|leases| leases.held(segment))
+    }
+
+    async fn put_sweep_metrics(&self, wait_time: Duration, stats: &SweepStats) {
+        let datum = |name: &str, value: f64, unit: StandardUnit| {
+            MetricDatum::builder()
+                .metric_name(name)
+                .value(value)
+                .unit(unit)
+                .build()
+        };
+        let metric_data = vec![
+            datum(
+                "wait_time",
+                wait_time.as_millis() as f64,
+                StandardUnit::Milliseconds,
+            ),
+            datum("item_count", stats.processed as f64, StandardUnit::Count),
+            datum(
+                "skipped_writes",
+                stats.skipped_writes as f64,
+                StandardUnit::Count,
+            ),
+            datum(
+                "quarantined_items",
+                stats.quarantined as f64,
+                StandardUnit::Count,
+            ),
+        ];
+        if let Err(e) = self
+            .cloud_watch
+            .put_metric_data()
+            .namespace(&self.config.metrics_namespace)
+            .set_metric_data(Some(metric_data))
+            .send()
+            .await
+        {
+            println!("Error writing to CloudWatch: {:?}", e);
+        }
+    }
+}
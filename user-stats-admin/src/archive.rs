@@ -0,0 +1,176 @@
+//! Backup archives of whole users.
+//!
+//! Two formats are supported:
+//!
+//! - newline-delimited JSON, one `{"userId": ..., "user": ...}` record per line, and
+//! - a tar file with one gzipped `User` JSON file per user at `users/<userid>.json.gz`.
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use user_stats_core::model::User;
+
+const TAR_DIR: &str = "users";
+const TAR_SUFFIX: &str = ".json.gz";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Ndjson,
+    Tar,
+}
+
+impl ArchiveFormat {
+    /// Picks the format from the file extension, defaulting to newline-delimited JSON.
+    pub fn from_path(path: &Path) -> ArchiveFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tar") => ArchiveFormat::Tar,
+            _ => ArchiveFormat::Ndjson,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    #[serde(rename = "userId")]
+    user_id: &'a str,
+    user: &'a User,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    #[serde(rename = "userId")]
+    user_id: String,
+    user: User,
+}
+
+/// A user read back from an archive, with the key it was exported under.
+pub struct ArchivedUser {
+    pub user_id: String,
+    pub user: User,
+}
+
+pub enum ArchiveWriter {
+    Ndjson(BufWriter<File>),
+    Tar(tar::Builder<BufWriter<File>>),
+}
+
+impl ArchiveWriter {
+    pub fn create(path: &Path, format: ArchiveFormat) -> Result<ArchiveWriter, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("unable to create {}: {}", path.display(), e))?;
+        let file = BufWriter::new(file);
+        Ok(match format {
+            ArchiveFormat::Ndjson => ArchiveWriter::Ndjson(file),
+            ArchiveFormat::Tar => ArchiveWriter::Tar(tar::Builder::new(file)),
+        })
+    }
+
+    pub fn write(&mut self, user_id: &str, user: &User) -> Result<(), String> {
+        match self {
+            ArchiveWriter::Ndjson(out) => {
+                let line = serde_json::to_string(&RecordRef { user_id, user })
+                    .map_err(|e| format!("error serializing {}: {}", user_id, e))?;
+                writeln!(out, "{}", line).map_err(|e| format!("error writing archive: {}", e))
+            }
+            ArchiveWriter::Tar(builder) => {
+                let json = serde_json::to_vec(user)
+                    .map_err(|e| format!("error serializing {}: {}", user_id, e))?;
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(&json)
+                    .map_err(|e| format!("error compressing {}: {}", user_id, e))?;
+                let gz = encoder
+                    .finish()
+                    .map_err(|e| format!("error compressing {}: {}", user_id, e))?;
+                let mut header = tar::Header::new_gnu();
+                header.set_size(gz.len() as u64);
+                header.set_mode(0o644);
+                builder
+                    .append_data(
+                        &mut header,
+                        format!("{}/{}{}", TAR_DIR, user_id, TAR_SUFFIX),
+                        gz.as_slice(),
+                    )
+                    .map_err(|e| format!("error writing archive: {}", e))
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        let mut out = match self {
+            ArchiveWriter::Ndjson(out) => out,
+            ArchiveWriter::Tar(builder) => builder
+                .into_inner()
+                .map_err(|e| format!("error writing archive: {}", e))?,
+        };
+        out.flush()
+            .map_err(|e| format!("error writing archive: {}", e))
+    }
+}
+
+/// Reads every user in an archive. Records that cannot be parsed are returned as errors so the
+/// rest of the archive can still be restored.
+pub fn read_archive(
+    path: &Path,
+    format: ArchiveFormat,
+) -> Result<Vec<Result<ArchivedUser, String>>, String> {
+    let file = File::open(path).map_err(|e| format!("unable to open {}: {}", path.display(), e))?;
+    let mut users = Vec::new();
+    match format {
+        ArchiveFormat::Ndjson => {
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let line = line.map_err(|e| format!("error reading archive: {}", e))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                users.push(
+                    serde_json::from_str::<Record>(&line)
+                        .map(|record| ArchivedUser {
+                            user_id: record.user_id,
+                            user: record.user,
+                        })
+                        .map_err(|e| format!("line {}: {}", index + 1, e)),
+                );
+            }
+        }
+        ArchiveFormat::Tar => {
+            let mut archive = tar::Archive::new(file);
+            let entries = archive
+                .entries()
+                .map_err(|e| format!("error reading archive: {}", e))?;
+            for entry in entries {
+                let entry = entry.map_err(|e| format!("error reading archive: {}", e))?;
+                let name = match entry.path() {
+                    Ok(path) => path.to_string_lossy().into_owned(),
+                    Err(e) => {
+                        users.push(Err(format!("bad entry name: {}", e)));
+                        continue;
+                    }
+                };
+                let user_id = match name
+                    .strip_prefix(&format!("{}/", TAR_DIR))
+                    .and_then(|rest| rest.strip_suffix(TAR_SUFFIX))
+                {
+                    Some(user_id) => user_id.to_string(),
+                    None => continue,
+                };
+                let mut json = String::new();
+                users.push(
+                    GzDecoder::new(entry)
+                        .read_to_string(&mut json)
+                        .map_err(|e| format!("{}: {}", name, e))
+                        .and_then(|_| {
+                            serde_json::from_str(&json).map_err(|e| format!("{}: {}", name, e))
+                        })
+                        .map(|user| ArchivedUser { user_id, user }),
+                );
+            }
+        }
+    }
+    Ok(users)
+}
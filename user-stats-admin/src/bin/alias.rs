@@ -0,0 +1,203 @@
+//! Links the Showdown accounts of players who moved to a new name, merging the old account's
+//! history into the new one so both names show the same user.
+
+use clap::{Parser, Subcommand};
+use std::collections::HashSet;
+use user_stats_admin::connect;
+use user_stats_core::model::User;
+use user_stats_core::storage::UserStore;
+use user_stats_core::user_id::UserId;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Merges users who moved Showdown accounts and manages their alias links"
+)]
+struct Cli {
+    /// DynamoDB table holding tracked users.
+    #[arg(long, env = "USER_STATS_TABLE")]
+    user_stats_table: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Merge a tracked user's history into another user and make the old name an alias of it.
+    /// The old user's webhook subscriptions move with their history.
+    Link {
+        /// Showdown user id or username the history is moved from.
+        from: String,
+
+        /// Showdown username the history is moved to. Created if not yet tracked.
+        to: String,
+
+        /// Report what would be merged without writing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show which user a name resolves to.
+    Show {
+        /// Showdown user id or username.
+        user: String,
+    },
+    /// Remove a name's alias link. History already merged stays with the user it was merged into.
+    Remove {
+        /// Showdown user id or username of an alias.
+        user: String,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let store = connect(&cli.user_stats_table).await;
+    if let Err(e) = run(&store, cli.command).await {
+        println!("ERROR: {}. Exiting...", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(store: &UserStore, command: Command) -> Result<(), String> {
+    match command {
+        Command::Link { from, to, dry_run } => link(store, &from, &to, dry_run).await?,
+        Command::Show { user } => {
+            let user_id = parse(&user)?;
+            let mut current = user_id.to_string();
+            let mut seen = HashSet::from([current.clone()]);
+            while let Some(target) = alias_of(store, &current).await? {
+                println!("User ID: {} is an alias of {}", current, target);
+                if !seen.insert(target.clone()) {
+                    return Err(format!("alias links from {} form a cycle", user_id));
+                }
+                current = target;
+            }
+            match store.exists(&current).await {
+                Ok(true) => println!("User ID: {} is tracked", current),
+                Ok(false) => println!("User ID: {} is not being tracked", current),
+                Err(e) => return Err(format!("Error reading user ID: {}: {}", current, e)),
+            }
+        }
+        Command::Remove { user } => {
+            let user_id = parse(&user)?;
+            if alias_of(store, user_id.as_str()).await?.is_none() {
+                return Err(format!("User ID: {} is not an alias", user_id));
+            }
+            store
+                .remove_alias(user_id.as_str())
+                .await
+                .map_err(|e| format!("Error removing alias {}: {}", user_id, e))?;
+            println!("Removed alias {}", user_id);
+        }
+    }
+    Ok(())
+}
+
+/// Moves the history of `from` into `to`, then links `from` to it. The merged user is written
+/// before the old one is deleted, so a failure part way leaves both copies rather than neither.
+async fn link(store: &UserStore, from: &str, to: &str, dry_run: bool) -> Result<(), String> {
+    let from_id = parse(from)?;
+    let to_id = parse(to)?;
+    if from_id == to_id {
+        return Err(format!("User ID: {} cannot be an alias of itself", from_id));
+    }
+    if let Some(target) = alias_of(store, to_id.as_str()).await? {
+        return Err(format!(
+            "User ID: {} is an alias of {}, link to {} instead",
+            to_id, target, target
+        ));
+    }
+    let old = match load(store, &from_id).await? {
+        Some(user) => user,
+        None => match alias_of(store, from_id.as_str()).await? {
+            Some(target) => {
+                return Err(format!(
+                    "User ID: {} is already an alias of {}",
+                    from_id, target
+                ))
+            }
+            None => return Err(format!("User ID: {} is not being tracked", from_id)),
+        },
+    };
+    let mut merged = match load(store, &to_id).await? {
+        Some(user) => user,
+        None => User {
+            username: to.trim().to_string(),
+            userid: to_id.to_string(),
+            formats: Default::default(),
+        },
+    };
+    let count: usize = old.formats.values().map(Vec::len).sum();
+    merged.merge(old);
+    println!(
+        "Merging {} ratings from user ID: {} into user ID: {}",
+        count, from_id, to_id
+    );
+    if dry_run {
+        println!("DRY RUN: skipping write");
+        return Ok(());
+    }
+
+    store
+        .save_user(&merged)
+        .await
+        .map_err(|e| format!("Error writing user ID: {}: {}", to_id, e))?;
+
+    let moved = store.subscriptions(from_id.as_str()).await.map_err(|e| {
+        format!(
+            "Error reading subscriptions for user ID: {}: {}",
+            from_id, e
+        )
+    })?;
+    if !moved.is_empty() {
+        let mut subscriptions = store
+            .subscriptions(to_id.as_str())
+            .await
+            .map_err(|e| format!("Error reading subscriptions for user ID: {}: {}", to_id, e))?;
+        println!("Moving {} subscriptions to user ID: {}", moved.len(), to_id);
+        subscriptions.extend(moved);
+        store
+            .put_subscriptions(to_id.as_str(), &subscriptions)
+            .await
+            .map_err(|e| format!("Error writing subscriptions for user ID: {}: {}", to_id, e))?;
+        store
+            .put_subscriptions(from_id.as_str(), &[])
+            .await
+            .map_err(|e| {
+                format!(
+                    "Error removing subscriptions for user ID: {}: {}",
+                    from_id, e
+                )
+            })?;
+    }
+
+    store
+        .put_alias(from_id.as_str(), to_id.as_str())
+        .await
+        .map_err(|e| format!("Error writing alias {}: {}", from_id, e))?;
+    store
+        .delete_user(from_id.as_str())
+        .await
+        .map_err(|e| format!("Error deleting user ID: {}: {}", from_id, e))?;
+    println!("User ID: {} is now an alias of {}", from_id, to_id);
+    Ok(())
+}
+
+fn parse(username: &str) -> Result<UserId, String> {
+    UserId::parse(username).map_err(|e| format!("{}: {}", username, e))
+}
+
+async fn load(store: &UserStore, user_id: &UserId) -> Result<Option<User>, String> {
+    store
+        .load_user(user_id.as_str())
+        .await
+        .map_err(|e| format!("Error reading user ID: {}: {}", user_id, e))
+}
+
+async fn alias_of(store: &UserStore, user_id: &str) -> Result<Option<String>, String> {
+    store
+        .alias_of(user_id)
+        .await
+        .map_err(|e| format!("Error reading alias {}: {}", user_id, e))
+}
@@ -0,0 +1,147 @@
+//! Rebuilds a user's past ladder ratings from local Showdown replay logs and merges them into
+//! their stored history. Imported ratings are marked with the `replay` source.
+
+use clap::Parser;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use user_stats_admin::replay::parse_replay;
+use user_stats_admin::{connect, BackendArgs, BackendKind};
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::local::LocalStore;
+use user_stats_core::model::{Rating, User};
+use user_stats_core::user_id::UserId;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Backfills a user's rating history from Showdown replay logs"
+)]
+struct Cli {
+    /// Showdown user id or username of a tracked user.
+    user_id: String,
+
+    /// Replay logs, as raw battle logs or replay JSON. Directories are read one level deep.
+    #[arg(required = true)]
+    replays: Vec<PathBuf>,
+
+    /// Print the reconstructed ratings without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let result = match cli.backend.backend {
+        BackendKind::Dynamodb => {
+            let table = cli.backend.user_stats_table.clone().unwrap_or_default();
+            backfill(&connect(&table).await, &cli).await
+        }
+        BackendKind::Local => {
+            let dir = cli.backend.local_dir.clone().unwrap_or_default();
+            backfill(&LocalStore::new(dir), &cli).await
+        }
+    };
+    if let Err(e) = result {
+        println!("ERROR: {}. Exiting...", e);
+        std::process::exit(1);
+    }
+}
+
+async fn backfill<B: StorageBackend>(backend: &B, cli: &Cli) -> Result<(), String> {
+    let user_id = UserId::parse(&cli.user_id).map_err(|e| format!("{}: {}", cli.user_id, e))?;
+    let mut user = match backend.load_user(user_id.as_str()).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(format!("User ID: {} is not being tracked", user_id)),
+        Err(e) => return Err(format!("Error reading user ID: {}: {}", user_id, e)),
+    };
+
+    let mut files = Vec::new();
+    for path in &cli.replays {
+        collect_files(path, &mut files)?;
+    }
+
+    let mut imported: HashMap<String, Vec<Rating>> = HashMap::new();
+    let mut parsed = 0;
+    let mut failed = 0;
+    for file in &files {
+        let points = fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| parse_replay(&contents, user_id.as_str()));
+        match points {
+            Ok(points) => {
+                parsed += 1;
+                for (format, rating) in points {
+                    imported.entry(format).or_default().push(rating);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("ERROR: {}: {}", file.display(), e);
+            }
+        }
+    }
+    let found: usize = imported.values().map(Vec::len).sum();
+    println!(
+        "Parsed {} replays ({} failed): {} ratings across {} formats",
+        parsed,
+        failed,
+        found,
+        imported.len()
+    );
+
+    if cli.dry_run {
+        let mut formats: Vec<_> = imported.iter().collect();
+        formats.sort_by(|a, b| a.0.cmp(b.0));
+        for (format, ratings) in formats {
+            for rating in ratings {
+                println!(
+                    "DRY RUN: would import {} {}: {}",
+                    format, rating.time, rating.elo
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let before: usize = user.formats.values().map(Vec::len).sum();
+    user.merge(User {
+        username: user.username.clone(),
+        userid: user.userid.clone(),
+        formats: imported,
+    });
+    let after: usize = user.formats.values().map(Vec::len).sum();
+    backend
+        .save_user(&user)
+        .await
+        .map_err(|e| format!("Error writing user ID: {}: {}", user_id, e))?;
+    println!(
+        "Backfilled user ID: {} with {} new ratings",
+        user_id,
+        after.saturating_sub(before)
+    );
+    Ok(())
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let entries =
+        fs::read_dir(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    let mut found = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        if entry.path().is_file() {
+            found.push(entry.path());
+        }
+    }
+    found.sort();
+    files.append(&mut found);
+    Ok(())
+}
@@ -0,0 +1,360 @@
+//! Exports every tracked user to a local archive and restores archives into any storage backend.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use user_stats_admin::archive::{read_archive, ArchiveFormat, ArchiveWriter};
+use user_stats_admin::{connect, BackendArgs, BackendKind};
+use user_stats_core::backend::StorageBackend;
+use user_stats_core::local::LocalStore;
+use user_stats_core::model::User;
+
+/// How often progress is printed, in users.
+const PROGRESS_INTERVAL: u64 = 100;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Backs up and restores tracked users")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write every user to an archive.
+    Export {
+        /// Archive to create.
+        archive: PathBuf,
+
+        /// Archive format. Defaults to tar for `.tar` files and ndjson otherwise.
+        #[arg(long, value_enum)]
+        format: Option<ArchiveFormat>,
+    },
+    /// Write every user in an archive to the backend.
+    Restore {
+        /// Archive to read.
+        archive: PathBuf,
+
+        /// Archive format. Defaults to tar for `.tar` files and ndjson otherwise.
+        #[arg(long, value_enum)]
+        format: Option<ArchiveFormat>,
+
+        /// What to do with users that already exist in the backend.
+        #[arg(long, value_enum, default_value_t = OnConflict::Skip)]
+        on_conflict: OnConflict,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OnConflict {
+    /// Keep the existing user.
+    Skip,
+    /// Replace the existing user with the archived one.
+    Overwrite,
+    /// Combine the existing and archived histories.
+    Merge,
+}
+
+#[derive(Default)]
+struct Progress {
+    written: u64,
+    skipped: u64,
+    failed: u64,
+}
+
+impl Progress {
+    fn total(&self) -> u64 {
+        self.written + self.skipped + self.failed
+    }
+
+    fn print(&self, verb: &str) {
+        println!(
+            "{} users: {} {}, {} skipped, {} failed",
+            self.total(),
+            self.written,
+            verb,
+            self.skipped,
+            self.failed
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let result = match cli.backend.backend {
+        BackendKind::Dynamodb => {
+            let table = cli.backend.user_stats_table.unwrap_or_default();
+            run(&connect(&table).await, cli.command).await
+        }
+        BackendKind::Local => {
+            let dir = cli.backend.local_dir.unwrap_or_default();
+            run(&LocalStore::new(dir), cli.command).await
+        }
+    };
+    match result {
+        Ok(progress) if progress.failed == 0 => {}
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            println!("ERROR: {}. Exiting...", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run<B: StorageBackend>(backend: &B, command: Command) -> Result<Progress, String> {
+    match command {
+        Command::Export { archive, format } => {
+            let format = format.unwrap_or_else(|| ArchiveFormat::from_path(&archive));
+            export(backend, &archive, format).await
+        }
+        Command::Restore {
+            archive,
+            format,
+            on_conflict,
+        } => {
+            let format = format.unwrap_or_else(|| ArchiveFormat::from_path(&archive));
+            restore(backend, &archive, format, on_conflict).await
+        }
+    }
+}
+
+async fn export<B: StorageBackend>(
+    backend: &B,
+    path: &Path,
+    format: ArchiveFormat,
+) -> Result<Progress, String> {
+    let user_ids = backend
+        .user_ids()
+        .await
+        .map_err(|e| format!("unable to list users: {}", e))?;
+    println!("Exporting {} users to {}", user_ids.len(), path.display());
+
+    let mut writer = ArchiveWriter::create(path, format)?;
+    let mut progress = Progress::default();
+    for user_id in &user_ids {
+        match backend.load_user(user_id).await {
+            Ok(Some(user)) => {
+                writer.write(user_id, &user)?;
+                progress.written += 1;
+            }
+            // Removed since the users were listed.
+            Ok(None) => progress.skipped += 1,
+            Err(e) => {
+                println!("ERROR: reading user ID: {}: {}", user_id, e);
+                progress.failed += 1;
+            }
+        }
+        if progress.total() % PROGRESS_INTERVAL == 0 {
+            progress.print("exported");
+        }
+    }
+    writer.finish()?;
+    progress.print("exported");
+    Ok(progress)
+}
+
+async fn restore<B: StorageBackend>(
+    backend: &B,
+    path: &Path,
+    format: ArchiveFormat,
+    on_conflict: OnConflict,
+) -> Result<Progress, String> {
+    let records = read_archive(path, format)?;
+    println!("Restoring {} users from {}", records.len(), path.display());
+
+    let mut progress = Progress::default();
+    for record in records {
+        match record {
+            Ok(archived) if archived.user_id != archived.user.userid => {
+                println!(
+                    "ERROR: user ID: {} is archived under user ID: {}",
+                    archived.user.userid, archived.user_id
+                );
+                progress.failed += 1;
+            }
+            Ok(archived) => match restore_user(backend, archived.user, on_conflict).await {
+                Ok(true) => progress.written += 1,
+                Ok(false) => progress.skipped += 1,
+                Err(e) => {
+                    println!("ERROR: {}", e);
+                    progress.failed += 1;
+                }
+            },
+            Err(e) => {
+                println!("ERROR: reading archive: {}", e);
+                progress.failed += 1;
+            }
+        }
+        if progress.total() % PROGRESS_INTERVAL == 0 {
+            progress.print("restored");
+        }
+    }
+    progress.print("restored");
+    Ok(progress)
+}
+
+/// Writes one archived user, returning whether anything was written.
+async fn restore_user<B: StorageBackend>(
+    backend: &B,
+    user: User,
+    on_conflict: OnConflict,
+) -> Result<bool, String> {
+    let user = if on_conflict == OnConflict::Overwrite {
+        user
+    } else {
+        let existing = backend
+            .load_user(&user.userid)
+            .await
+            .map_err(|e| format!("reading user ID: {}: {}", user.userid, e))?;
+        match (existing, on_conflict) {
+            (None, _) => user,
+            (Some(_), OnConflict::Skip) => return Ok(false),
+            (Some(mut existing), _) => {
+                existing.merge(user);
+                existing
+            }
+        }
+    };
+    backend
+        .save_user(&user)
+        .await
+        .map_err(|e| format!("writing user ID: {}: {}", user.userid, e))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::fs;
+    use user_stats_core::model::Rating;
+
+    /// An empty directory for the test named `name`.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("backup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `User` has no `PartialEq`, so users are compared as JSON.
+    fn json(user: &User) -> Value {
+        serde_json::to_value(user).unwrap()
+    }
+
+    fn user(userid: &str, ratings: &[(u64, f64)]) -> User {
+        User {
+            username: userid.to_uppercase(),
+            userid: userid.to_string(),
+            formats: HashMap::from([(
+                "gen9ou".to_string(),
+                ratings
+                    .iter()
+                    .map(|(time, elo)| Rating::live(*time, *elo))
+                    .collect(),
+            )]),
+        }
+    }
+
+    #[tokio::test]
+    async fn exported_archives_restore_every_user() {
+        let dir = scratch("round-trip");
+        let source = LocalStore::new(dir.join("source"));
+        let users = [
+            user("someuser", &[(100, 1500.0), (200, 1520.0)]),
+            user("otheruser", &[(150, 1300.0)]),
+        ];
+        for user in &users {
+            source.save_user(user).await.unwrap();
+        }
+
+        for format in [ArchiveFormat::Ndjson, ArchiveFormat::Tar] {
+            let archive = dir.join(format!("users-{:?}", format));
+            let exported = export(&source, &archive, format).await.unwrap();
+            assert_eq!(exported.written, 2);
+
+            let target = LocalStore::new(dir.join(format!("target-{:?}", format)));
+            let restored = restore(&target, &archive, format, OnConflict::Skip)
+                .await
+                .unwrap();
+            assert_eq!((restored.written, restored.failed), (2, 0));
+            for user in &users {
+                let restored = target.load_user(&user.userid).await.unwrap().unwrap();
+                assert_eq!(json(&restored), json(user));
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn existing_users_are_skipped_overwritten_or_merged() {
+        let dir = scratch("conflicts");
+        let archived = user("someuser", &[(100, 1550.0), (200, 1560.0)]);
+        let existing = user("someuser", &[(100, 1500.0), (300, 1580.0)]);
+        let archive = dir.join("users.ndjson");
+        let mut writer = ArchiveWriter::create(&archive, ArchiveFormat::Ndjson).unwrap();
+        writer.write("someuser", &archived).unwrap();
+        writer.finish().unwrap();
+
+        let restored = |on_conflict| {
+            let store = LocalStore::new(dir.join(format!("{:?}", on_conflict)));
+            let (archive, existing) = (&archive, &existing);
+            async move {
+                store.save_user(existing).await.unwrap();
+                let progress = restore(&store, archive, ArchiveFormat::Ndjson, on_conflict)
+                    .await
+                    .unwrap();
+                let user = store.load_user("someuser").await.unwrap().unwrap();
+                (progress.written, progress.skipped, json(&user))
+            }
+        };
+
+        assert_eq!(restored(OnConflict::Skip).await, (0, 1, json(&existing)));
+        assert_eq!(
+            restored(OnConflict::Overwrite).await,
+            (1, 0, json(&archived))
+        );
+        // The existing rating wins where both have one at the same time.
+        assert_eq!(
+            restored(OnConflict::Merge).await,
+            (
+                1,
+                0,
+                json(&user(
+                    "someuser",
+                    &[(100, 1500.0), (200, 1560.0), (300, 1580.0)]
+                ))
+            )
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn users_archived_under_another_id_are_not_restored() {
+        let dir = scratch("mismatch");
+        let archive = dir.join("users.ndjson");
+        let mut writer = ArchiveWriter::create(&archive, ArchiveFormat::Ndjson).unwrap();
+        writer
+            .write("otheruser", &user("someuser", &[(100, 1500.0)]))
+            .unwrap();
+        writer.finish().unwrap();
+        fs::write(
+            &archive,
+            fs::read_to_string(&archive).unwrap() + "not json\n",
+        )
+        .unwrap();
+
+        let store = LocalStore::new(dir.join("target"));
+        let progress = restore(&store, &archive, ArchiveFormat::Ndjson, OnConflict::Skip)
+            .await
+            .unwrap();
+
+        assert_eq!((progress.written, progress.failed), (0, 2));
+        assert_eq!(store.user_ids().await.unwrap(), Vec::<String>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
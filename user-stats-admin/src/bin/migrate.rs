@@ -0,0 +1,104 @@
+//! Rewrites every tracked user whose items were written with an older schema version.
+
+use clap::Parser;
+use user_stats_admin::connect;
+use user_stats_core::schema::CURRENT_SCHEMA_VERSION;
+use user_stats_core::storage::{Item, UserStore, USER_ID_KEY};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Migrates tracked users to the current storage schema"
+)]
+struct Cli {
+    /// DynamoDB table holding tracked users.
+    #[arg(long, env = "USER_STATS_TABLE")]
+    user_stats_table: String,
+
+    /// Report the users that would be migrated without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Number of users requested per scan page.
+    #[arg(long, default_value_t = 50)]
+    page_size: i32,
+}
+
+#[derive(Default)]
+struct Progress {
+    scanned: u64,
+    migrated: u64,
+    current: u64,
+    failed: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let store = connect(&cli.user_stats_table).await;
+    let mut progress = Progress::default();
+    let mut exclusive_start_key = None;
+
+    loop {
+        let (items, last_evaluated_key) = match store
+            .scan_heads(exclusive_start_key, 0, 1, cli.page_size)
+            .await
+        {
+            Ok(val) => val,
+            Err(e) => {
+                println!("ERROR: {}. Exiting...", e);
+                progress.failed += 1;
+                break;
+            }
+        };
+        for item in &items {
+            migrate_item(&store, item, cli.dry_run, &mut progress).await;
+        }
+        println!(
+            "Scanned {} users: {} {}, {} already current, {} failed",
+            progress.scanned,
+            progress.migrated,
+            if cli.dry_run {
+                "to migrate"
+            } else {
+                "migrated"
+            },
+            progress.current,
+            progress.failed
+        );
+        exclusive_start_key = last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+
+    if progress.failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+async fn migrate_item(store: &UserStore, item: &Item, dry_run: bool, progress: &mut Progress) {
+    progress.scanned += 1;
+    let user_id = item
+        .get(USER_ID_KEY)
+        .and_then(|val| val.as_s().ok())
+        .map(String::as_str)
+        .unwrap_or("<missing userId>");
+    match store.migrate(item, dry_run).await {
+        Ok(Some(version)) => {
+            progress.migrated += 1;
+            println!(
+                "{}{}: schema version {} -> {}",
+                if dry_run { "DRY RUN: " } else { "" },
+                user_id,
+                version,
+                CURRENT_SCHEMA_VERSION
+            );
+        }
+        Ok(None) => progress.current += 1,
+        Err(e) => {
+            progress.failed += 1;
+            println!("ERROR: migrating user ID: {}: {}", user_id, e);
+        }
+    }
+}
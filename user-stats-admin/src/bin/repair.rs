@@ -0,0 +1,202 @@
+//! Rebuilds quarantined users from whatever history can still be read plus their current ratings
+//! on Showdown.
+
+use clap::Parser;
+use std::time::SystemTime;
+use update_stats::showdown::{
+    changed_ratings, fetch_profile, profile_username, Profile, MAX_ELO, MIN_ELO, SHOWDOWN_URL,
+};
+use user_stats_admin::connect;
+use user_stats_core::model::User;
+use user_stats_core::storage::{quarantine_error, Item, UserStore, USER_ID_KEY};
+use user_stats_core::user_id::UserId;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Repairs users quarantined because their data could not be read"
+)]
+struct Cli {
+    /// DynamoDB table holding tracked users.
+    #[arg(long, env = "USER_STATS_TABLE")]
+    user_stats_table: String,
+
+    /// Repair only these users instead of every quarantined one. May be repeated.
+    #[arg(long = "user")]
+    users: Vec<String>,
+
+    /// Report what would be salvaged without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Number of users requested per scan page.
+    #[arg(long, default_value_t = 50)]
+    page_size: i32,
+
+    /// Base URL of the Showdown server current ratings are fetched from.
+    #[arg(long, env = "SHOWDOWN_URL", default_value = SHOWDOWN_URL)]
+    showdown_url: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let store = connect(&cli.user_stats_table).await;
+    let mut repaired = 0;
+    let mut failed = 0;
+
+    let mut tally = |result: Result<(), String>| match result {
+        Ok(()) => repaired += 1,
+        Err(e) => {
+            failed += 1;
+            println!("ERROR: {}", e);
+        }
+    };
+
+    if !cli.users.is_empty() {
+        for user in &cli.users {
+            let user_id = match UserId::parse(user) {
+                Ok(val) => val,
+                Err(e) => {
+                    tally(Err(format!("{}: {}", user, e)));
+                    continue;
+                }
+            };
+            let result = match store.get_head_item(user_id.as_str()).await {
+                Ok(Some(item)) => repair(&store, user_id.as_str(), &item, &cli).await,
+                Ok(None) => Err(format!("User ID: {} is not being tracked", user_id)),
+                Err(e) => Err(format!("Error reading user ID: {}: {}", user_id, e)),
+            };
+            tally(result);
+        }
+    } else {
+        let mut exclusive_start_key = None;
+        loop {
+            let (items, last_evaluated_key) = match store
+                .scan_quarantined(exclusive_start_key, cli.page_size)
+                .await
+            {
+                Ok(val) => val,
+                Err(e) => {
+                    println!("ERROR: {}. Exiting...", e);
+                    failed += 1;
+                    break;
+                }
+            };
+            for item in &items {
+                let user_id = match item.get(USER_ID_KEY).and_then(|val| val.as_s().ok()) {
+                    Some(val) => val,
+                    None => {
+                        tally(Err(format!("Item {:?} is missing 'userId' key", item)));
+                        continue;
+                    }
+                };
+                tally(repair(&store, user_id, item, &cli).await);
+            }
+            exclusive_start_key = last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+    }
+
+    println!(
+        "{} users {}, {} failed",
+        repaired,
+        if cli.dry_run {
+            "repairable"
+        } else {
+            "repaired"
+        },
+        failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+async fn repair(store: &UserStore, user_id: &str, item: &Item, cli: &Cli) -> Result<(), String> {
+    match quarantine_error(item) {
+        Some(error) => println!("Repairing user ID: {}, quarantined for: {}", user_id, error),
+        None => println!("Repairing user ID: {}, which is not quarantined", user_id),
+    }
+
+    let salvage = store
+        .salvage(item)
+        .await
+        .map_err(|e| format!("Error salvaging user ID: {}: {}", user_id, e))?;
+    for problem in &salvage.problems {
+        println!("  lost {}", problem);
+    }
+    let salvaged: usize = salvage.formats.values().map(Vec::len).sum();
+    println!(
+        "  salvaged {} ratings across {} formats",
+        salvaged,
+        salvage.formats.len()
+    );
+
+    let profile = fetch_profile(&cli.showdown_url, user_id).await;
+    let mut user = match (profile, salvage.username) {
+        (Ok(profile), _) => {
+            let current = current_user(user_id, &profile)?;
+            let mut user = User {
+                username: current.username.clone(),
+                userid: current.userid.clone(),
+                formats: salvage.formats,
+            };
+            user.merge(current);
+            user
+        }
+        // Keep what was salvaged even if Showdown is unavailable.
+        (Err(e), Some(username)) if salvaged > 0 => {
+            println!("  {}; keeping salvaged history only", e);
+            User {
+                username,
+                userid: user_id.to_string(),
+                formats: salvage.formats,
+            }
+        }
+        (Err(e), _) => return Err(e),
+    };
+    user.formats.retain(|_, ratings| !ratings.is_empty());
+
+    if cli.dry_run {
+        println!("DRY RUN: would rewrite user ID: {}", user_id);
+        return Ok(());
+    }
+
+    let head = store
+        .save_user(&user)
+        .await
+        .map_err(|e| format!("Error writing user ID: {}: {}", user_id, e))?;
+    let stale = salvage
+        .lost_chunks
+        .difference(&head.chunks)
+        .cloned()
+        .collect();
+    store
+        .delete_chunks(user_id, &stale)
+        .await
+        .map_err(|e| format!("Error deleting lost chunks for user ID: {}: {}", user_id, e))?;
+    println!("Repaired user ID: {}", user_id);
+    Ok(())
+}
+
+/// The user as Showdown reports them now, with each current rating stamped `now`. Fails if the
+/// profile names another user, so their ratings are never written under `user_id`.
+fn current_user(user_id: &str, profile: &Profile) -> Result<User, String> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|e| format!("Error getting current time: {:?}", e))?
+        .as_secs();
+    let username = profile_username(user_id, profile)?.to_string();
+    let ratings = changed_ratings(user_id, profile, now, &(MIN_ELO..=MAX_ELO), |_| None)?;
+    Ok(User {
+        username,
+        userid: user_id.to_string(),
+        formats: ratings
+            .into_iter()
+            .map(|(format, rating)| (format, vec![rating]))
+            .collect(),
+    })
+}
@@ -0,0 +1,195 @@
+//! Manages webhook subscriptions to tracked users' rating events, which `update-stats` delivers
+//! when it pushes a matching rating.
+
+use clap::{Parser, Subcommand};
+use std::time::SystemTime;
+use user_stats_admin::connect;
+use user_stats_core::storage::UserStore;
+use user_stats_core::user_id::{to_id, UserId};
+use user_stats_core::webhooks::{EventKind, Subscription, Target};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Manages webhook subscriptions to users' rating events"
+)]
+struct Cli {
+    /// DynamoDB table holding tracked users.
+    #[arg(long, env = "USER_STATS_TABLE")]
+    user_stats_table: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List a user's subscriptions. Secrets are not shown.
+    List {
+        /// Showdown user id or username.
+        user: String,
+    },
+    /// Subscribe a URL to a tracked user's rating events.
+    Add {
+        /// Showdown user id or username of a tracked user.
+        user: String,
+
+        /// URL the events are posted to.
+        #[arg(long)]
+        url: String,
+
+        /// How events are posted: json or discord. Discord webhook URLs default to discord.
+        #[arg(long)]
+        target: Option<Target>,
+
+        /// Only send events in this format. All formats by default.
+        #[arg(long)]
+        format: Option<String>,
+
+        /// Only send these events: new-peak, milestone or sharp-drop. May be repeated; all events
+        /// by default.
+        #[arg(long = "event")]
+        events: Vec<EventKind>,
+
+        /// Sign payloads with HMAC-SHA256 using this secret, sent in `x-user-stats-signature`.
+        #[arg(long, env = "WEBHOOK_SECRET")]
+        secret: Option<String>,
+    },
+    /// Remove one of a user's subscriptions.
+    Remove {
+        /// Showdown user id or username.
+        user: String,
+
+        /// Id of the subscription, as shown by `list`.
+        id: String,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let store = connect(&cli.user_stats_table).await;
+    if let Err(e) = run(&store, cli.command).await {
+        println!("ERROR: {}. Exiting...", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(store: &UserStore, command: Command) -> Result<(), String> {
+    match command {
+        Command::List { user } => {
+            let user_id = parse(&user)?;
+            let subscriptions = read(store, user_id.as_str()).await?;
+            if subscriptions.is_empty() {
+                println!("User ID: {} has no subscriptions", user_id);
+            }
+            for subscription in subscriptions {
+                println!(
+                    "{} {} ({}) format: {} events: {}{}",
+                    subscription.id,
+                    subscription.url,
+                    subscription.target,
+                    subscription.format.as_deref().unwrap_or("all"),
+                    if subscription.events.is_empty() {
+                        "all".to_string()
+                    } else {
+                        subscription
+                            .events
+                            .iter()
+                            .map(EventKind::to_string)
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    },
+                    if subscription.secret.is_some() {
+                        " (signed)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        Command::Add {
+            user,
+            url,
+            target,
+            format,
+            events,
+            secret,
+        } => {
+            let user_id = parse(&user)?;
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(format!("{} is not an http(s) URL", url));
+            }
+            match store.exists(user_id.as_str()).await {
+                Ok(true) => {}
+                Ok(false) => return Err(format!("User ID: {} is not being tracked", user_id)),
+                Err(e) => return Err(format!("Error reading user ID: {}: {}", user_id, e)),
+            }
+            let mut subscriptions = read(store, user_id.as_str()).await?;
+            let subscription = Subscription {
+                id: new_id(),
+                target: target.unwrap_or_else(|| Target::for_url(&url)),
+                url,
+                format: format.as_deref().map(to_id),
+                events,
+                secret,
+            };
+            println!(
+                "Added subscription {} for user ID: {}",
+                subscription.id, user_id
+            );
+            subscriptions.push(subscription);
+            write(store, user_id.as_str(), &subscriptions).await?;
+        }
+        Command::Remove { user, id } => {
+            let user_id = parse(&user)?;
+            let mut subscriptions = read(store, user_id.as_str()).await?;
+            let count = subscriptions.len();
+            subscriptions.retain(|subscription| subscription.id != id);
+            if subscriptions.len() == count {
+                return Err(format!("User ID: {} has no subscription {}", user_id, id));
+            }
+            write(store, user_id.as_str(), &subscriptions).await?;
+            println!("Removed subscription {} for user ID: {}", id, user_id);
+        }
+    }
+    Ok(())
+}
+
+fn parse(username: &str) -> Result<UserId, String> {
+    UserId::parse(username).map_err(|e| format!("{}: {}", username, e))
+}
+
+async fn read(store: &UserStore, user_id: &str) -> Result<Vec<Subscription>, String> {
+    store.subscriptions(user_id).await.map_err(|e| {
+        format!(
+            "Error reading subscriptions for user ID: {}: {}",
+            user_id, e
+        )
+    })
+}
+
+async fn write(
+    store: &UserStore,
+    user_id: &str,
+    subscriptions: &[Subscription],
+) -> Result<(), String> {
+    store
+        .put_subscriptions(user_id, subscriptions)
+        .await
+        .map_err(|e| {
+            format!(
+                "Error writing subscriptions for user ID: {}: {}",
+                user_id, e
+            )
+        })
+}
+
+/// A short id from the current time, unique enough among one user's subscriptions.
+fn new_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}", nanos)
+}
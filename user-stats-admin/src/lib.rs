@@ -0,0 +1,40 @@
+//! Operator tools for the user stats table. Each tool is a binary under `src/bin`.
+
+use aws_config::BehaviorVersion;
+use aws_sdk_dynamodb as dynamodb;
+use clap::{Args, ValueEnum};
+use std::path::PathBuf;
+use user_stats_core::storage::UserStore;
+
+pub mod archive;
+pub mod replay;
+
+/// Connects to DynamoDB with the default AWS configuration.
+pub async fn connect(table: &str) -> UserStore {
+    let aws_config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+    UserStore::new(dynamodb::Client::new(&aws_config), table)
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// The DynamoDB user stats table.
+    Dynamodb,
+    /// A local directory with one JSON file per user.
+    Local,
+}
+
+/// Flags selecting the storage backend a tool reads from or writes to.
+#[derive(Args, Debug)]
+pub struct BackendArgs {
+    /// Where tracked users are stored.
+    #[arg(long, value_enum, default_value_t = BackendKind::Dynamodb)]
+    pub backend: BackendKind,
+
+    /// DynamoDB table holding tracked users, for the dynamodb backend.
+    #[arg(long, env = "USER_STATS_TABLE", required_if_eq("backend", "dynamodb"))]
+    pub user_stats_table: Option<String>,
+
+    /// Directory holding tracked users, for the local backend.
+    #[arg(long, required_if_eq("backend", "local"))]
+    pub local_dir: Option<PathBuf>,
+}
@@ -0,0 +1,238 @@
+//! Reconstructing ladder ratings from Showdown battle logs.
+//!
+//! Rated battles show each player's rating before the battle on their `|player|` line, and the
+//! change at the end in a `|raw|` line:
+//!
+//! ```text
+//! |player|p1|Alice|102|1500
+//! |raw|Alice's rating: 1500 &rarr; <strong>1523</strong><br />(+23 for winning)
+//! ```
+//!
+//! Logs may be raw text or replay JSON as downloaded from `replay.pokemonshowdown.com`, which
+//! wraps the log with the format id and upload time.
+
+use serde::Deserialize;
+use user_stats_core::model::{Rating, Source};
+use user_stats_core::user_id::to_id;
+
+#[derive(Deserialize)]
+struct ReplayJson {
+    log: String,
+    formatid: Option<String>,
+    uploadtime: Option<u64>,
+}
+
+/// Parses a replay file's contents, returning the (format, rating) points it shows for `user_id`.
+pub fn parse_replay(contents: &str, user_id: &str) -> Result<Vec<(String, Rating)>, String> {
+    match serde_json::from_str::<ReplayJson>(contents) {
+        Ok(replay) => parse_log(
+            &replay.log,
+            user_id,
+            replay.formatid.as_deref(),
+            replay.uploadtime,
+        ),
+        Err(_) => parse_log(contents, user_id, None, None),
+    }
+}
+
+/// Parses a raw battle log. `format` and `time` are used when the log has no `|tier|` or `|t:|`
+/// lines.
+pub fn parse_log(
+    log: &str,
+    user_id: &str,
+    format: Option<&str>,
+    time: Option<u64>,
+) -> Result<Vec<(String, Rating)>, String> {
+    let mut tier = None;
+    let mut start_time = None;
+    let mut end_time = None;
+    let mut before = None;
+    let mut change = None;
+
+    for line in log.lines() {
+        let mut parts = line.split('|').skip(1);
+        match parts.next() {
+            Some("tier") => tier = parts.next().map(to_id),
+            Some("t:") => {
+                if let Some(Ok(t)) = parts.next().map(|t| t.trim().parse::<u64>()) {
+                    start_time.get_or_insert(t);
+                    end_time = Some(t);
+                }
+            }
+            Some("player") => {
+                let (name, rating) = (parts.nth(1), parts.nth(1));
+                if let (Some(name), Some(Ok(rating))) = (name, rating.map(|r| r.parse::<f64>())) {
+                    if to_id(name) == user_id {
+                        before = Some(rating);
+                    }
+                }
+            }
+            Some("raw") => {
+                let raw = line.split_once("|raw|").map(|(_, raw)| raw).unwrap_or("");
+                if let Some((name, old, new)) = parse_rating_change(raw) {
+                    if to_id(name) == user_id {
+                        change = Some((old, new));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let format = match tier.or_else(|| format.map(to_id)) {
+        Some(format) if !format.is_empty() => format,
+        _ => return Err("log has no |tier| line".to_string()),
+    };
+    let start_time = start_time.or(time);
+    let end_time = end_time.or(time);
+
+    let mut points = Vec::new();
+    match change {
+        Some((old, new)) => {
+            let end = end_time.ok_or("log has no |t:| line")?;
+            if let Some(start) = start_time.filter(|start| *start < end) {
+                points.push((format.clone(), replay_rating(start, old)));
+            }
+            points.push((format, replay_rating(end, new)));
+        }
+        None => {
+            if let Some(elo) = before {
+                let start = start_time.ok_or("log has no |t:| line")?;
+                points.push((format, replay_rating(start, elo)));
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// Parses `Name's rating: 1500 &rarr; <strong>1523</strong>...` into the name and both ratings.
+fn parse_rating_change(raw: &str) -> Option<(&str, f64, f64)> {
+    let (name, rest) = raw.split_once("'s rating: ")?;
+    let (old, rest) = rest.split_once(" &rarr; ")?;
+    let new = rest.strip_prefix("<strong>")?.split_once("</strong>")?.0;
+    Some((name, old.trim().parse().ok()?, new.trim().parse().ok()?))
+}
+
+fn replay_rating(time: u64, elo: f64) -> Rating {
+    Rating {
+        source: Source::Replay,
+        ..Rating::live(time, elo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const LOG: &str = "\
+|j|☆Some User
+|j|☆Other
+|t:|1700000000
+|gametype|singles
+|player|p1|Some User|102|1500
+|player|p2|Other|1|1480
+|tier|[Gen 9] OU
+|rated|
+|start
+|turn|1
+|t:|1700000300
+|win|Some User
+|raw|Some User's rating: 1500 &rarr; <strong>1523</strong><br />(+23 for winning)
+|raw|Other's rating: 1480 &rarr; <strong>1458</strong><br />(-22 for losing)
+";
+
+    #[test]
+    fn parses_rating_changes() {
+        assert_eq!(
+            parse_rating_change(
+                "Some User's rating: 1500 &rarr; <strong>1523</strong><br />(+23 for winning)"
+            ),
+            Some(("Some User", 1500.0, 1523.0))
+        );
+        assert_eq!(
+            parse_rating_change("Other's rating: 1000 &rarr; <strong>1000</strong>"),
+            Some(("Other", 1000.0, 1000.0))
+        );
+        assert_eq!(parse_rating_change("Some User joined"), None);
+        assert_eq!(
+            parse_rating_change("Some User's rating: 1500 &rarr; 1523"),
+            None
+        );
+    }
+
+    #[test]
+    fn the_rating_change_gives_the_ratings_before_and_after() {
+        assert_eq!(
+            parse_log(LOG, "someuser", None, None).unwrap(),
+            [
+                ("gen9ou".to_string(), replay_rating(1700000000, 1500.0)),
+                ("gen9ou".to_string(), replay_rating(1700000300, 1523.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_users_own_change_is_used() {
+        assert_eq!(
+            parse_log(LOG, "other", None, None).unwrap(),
+            [
+                ("gen9ou".to_string(), replay_rating(1700000000, 1480.0)),
+                ("gen9ou".to_string(), replay_rating(1700000300, 1458.0)),
+            ]
+        );
+        assert_eq!(parse_log(LOG, "nobody", None, None).unwrap(), []);
+    }
+
+    #[test]
+    fn logs_without_a_change_use_the_player_rating() {
+        let log: String = LOG
+            .lines()
+            .filter(|line| !line.starts_with("|raw|"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(
+            parse_log(&log, "someuser", None, None).unwrap(),
+            [("gen9ou".to_string(), replay_rating(1700000000, 1500.0))]
+        );
+    }
+
+    #[test]
+    fn logs_without_a_tier_use_the_given_format_or_fail() {
+        let log: String = LOG
+            .lines()
+            .filter(|line| !line.starts_with("|tier|"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(
+            parse_log(&log, "someuser", None, None),
+            Err("log has no |tier| line".to_string())
+        );
+        let points = parse_log(&log, "someuser", Some("gen9ou"), None).unwrap();
+        assert_eq!(points.len(), 2);
+        assert!(points.iter().all(|(format, _)| format == "gen9ou"));
+    }
+
+    #[test]
+    fn replay_json_supplies_the_format_and_time() {
+        let log = "\
+|player|p1|Some User|102|1500
+|raw|Some User's rating: 1500 &rarr; <strong>1523</strong>
+";
+        let replay = json!({
+            "id": "gen9ou-1",
+            "formatid": "gen9ou",
+            "uploadtime": 1700000500,
+            "log": log,
+        });
+        // Without |t:| lines, the rating before the battle can't be placed before the one after.
+        assert_eq!(
+            parse_replay(&replay.to_string(), "someuser").unwrap(),
+            [("gen9ou".to_string(), replay_rating(1700000500, 1523.0))]
+        );
+        assert_eq!(
+            parse_replay(log, "someuser"),
+            Err("log has no |tier| line".to_string())
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Compares the legacy `stats.json.gz` blob with the chunk encodings for a long synthetic history.
+//! Run with `cargo bench`; encoded sizes are printed before the timings.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::Write;
+use user_stats_core::codec::{decode_blob, decode_ratings, encode_ratings, Encoding};
+use user_stats_core::model::{Rating, User};
+use user_stats_core::schema::LEGACY_SCHEMA_VERSION;
+
+const FORMATS: usize = 12;
+const POINTS_PER_FORMAT: usize = 2_000;
+
+/// A history shaped like Showdown data: a rating every few hours drifting by a few points.
+fn history() -> HashMap<String, Vec<Rating>> {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    (0..FORMATS)
+        .map(|format| {
+            let mut time = 1_700_000_000u64;
+            let mut elo = 1_200.0f64;
+            let ratings = (0..POINTS_PER_FORMAT)
+                .map(|_| {
+                    time += 3_600 + next() % 20_000;
+                    elo += (next() % 6_400) as f64 / 100.0 - 32.0;
+                    Rating::live(time, elo)
+                })
+                .collect();
+            (format!("gen9format{}", format), ratings)
+        })
+        .collect()
+}
+
+/// Encodes a user the way `stats.json.gz` was written before histories were chunked.
+fn legacy_blob(user: &User) -> Vec<u8> {
+    let json = serde_json::to_string(user).unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(json.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn encoding(c: &mut Criterion) {
+    let formats = history();
+    let columnar = encode_ratings(&formats, Encoding::Columnar).unwrap();
+    let json_gzip = encode_ratings(&formats, Encoding::JsonGzip).unwrap();
+    let legacy = legacy_blob(&User {
+        username: "Bench User".to_string(),
+        userid: "benchuser".to_string(),
+        formats,
+    });
+
+    println!(
+        "{} ratings: stats.json.gz {} bytes, json-gzip {} bytes, columnar {} bytes",
+        FORMATS * POINTS_PER_FORMAT,
+        legacy.len(),
+        json_gzip.len(),
+        columnar.len()
+    );
+
+    let mut group = c.benchmark_group("decode");
+    group.bench_function("stats.json.gz", |b| {
+        b.iter(|| decode_blob::<User>(black_box(&legacy), LEGACY_SCHEMA_VERSION).unwrap())
+    });
+    group.bench_function("json-gzip", |b| {
+        b.iter(|| decode_ratings(black_box(&json_gzip)).unwrap())
+    });
+    group.bench_function("columnar", |b| {
+        b.iter(|| decode_ratings(black_box(&columnar)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encoding);
+criterion_main!(benches);
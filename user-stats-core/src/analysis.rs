@@ -0,0 +1,322 @@
+//! Statistics derived from a user's rating history.
+
+use crate::model::{Rating, Record, User};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Games played in a format between a rating and the previous rating with a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct GamesPlayed {
+    /// Time of the later rating.
+    pub time: u64,
+    pub w: u32,
+    pub l: u32,
+    pub t: u32,
+}
+
+impl GamesPlayed {
+    pub fn games(&self) -> u32 {
+        self.w + self.l + self.t
+    }
+}
+
+/// The lowest rating on a Showdown ladder.
+const ELO_FLOOR: f64 = 1000.0;
+
+/// What a change between two consecutive ratings is taken to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Win,
+    Loss,
+    Tie,
+    /// More than one game was played between the two ratings.
+    MultiGame,
+}
+
+/// One or more games inferred from the change between two consecutive ratings in a format.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferredGame {
+    /// Time of the later rating.
+    pub time: u64,
+    pub elo_before: f64,
+    pub elo_after: f64,
+    pub outcome: Outcome,
+    /// Games played, when both ratings carry a [`Record`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub games: Option<u32>,
+    /// The opponent rating that gives this change under Showdown's Elo formula. Only estimated
+    /// for single games that did not end at the rating floor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opponent_elo: Option<f64>,
+}
+
+impl User {
+    /// Games played between consecutive recorded ratings, per format. Formats without at least two
+    /// ratings carrying a [`Record`] are left out. Summing the entries between two times gives the
+    /// wins, losses and ties over that window.
+    pub fn games_played(&self) -> HashMap<String, Vec<GamesPlayed>> {
+        self.formats
+            .iter()
+            .filter_map(|(format, ratings)| {
+                let games = games_played(ratings);
+                (!games.is_empty()).then(|| (format.clone(), games))
+            })
+            .collect()
+    }
+
+    /// The games inferred from each format's rating changes. Formats with fewer than two ratings
+    /// are left out.
+    pub fn inferred_games(&self) -> HashMap<String, Vec<InferredGame>> {
+        self.formats
+            .iter()
+            .filter_map(|(format, ratings)| {
+                let games = inferred_games(ratings);
+                (!games.is_empty()).then(|| (format.clone(), games))
+            })
+            .collect()
+    }
+}
+
+/// Differences between consecutive records in `ratings`, which must be sorted by time.
+pub fn games_played(ratings: &[Rating]) -> Vec<GamesPlayed> {
+    let mut games = Vec::new();
+    let mut previous: Option<Record> = None;
+    for rating in ratings {
+        let Some(record) = rating.record else {
+            continue;
+        };
+        if let Some(before) = previous {
+            let delta = record_delta(before, record);
+            if delta.games() > 0 {
+                games.push(GamesPlayed {
+                    time: rating.time,
+                    w: delta.w,
+                    l: delta.l,
+                    t: delta.t,
+                });
+            }
+        }
+        previous = Some(record);
+    }
+    games
+}
+
+/// Classifies each change between consecutive `ratings`, which must be sorted by time.
+///
+/// When both ratings carry a record, the record says how many games were played and, for a single
+/// game, its result. Otherwise a rise is taken as a win and a fall as a loss, unless the change is
+/// larger than one game can cause.
+pub fn inferred_games(ratings: &[Rating]) -> Vec<InferredGame> {
+    ratings
+        .windows(2)
+        .filter_map(|pair| infer_game(&pair[0], &pair[1]))
+        .collect()
+}
+
+fn infer_game(before: &Rating, after: &Rating) -> Option<InferredGame> {
+    let delta = after.elo - before.elo;
+    let played = match (before.record, after.record) {
+        (Some(b), Some(a)) => Some(record_delta(b, a)),
+        _ => None,
+    };
+    let outcome = match played {
+        Some(played) if played.games() > 1 => Outcome::MultiGame,
+        Some(played) if played.games() == 1 => {
+            if played.w == 1 {
+                Outcome::Win
+            } else if played.l == 1 {
+                Outcome::Loss
+            } else {
+                Outcome::Tie
+            }
+        }
+        // No games recorded, or no records to go by.
+        _ if delta > 0.0 => Outcome::Win,
+        _ if delta < 0.0 => Outcome::Loss,
+        _ => return None,
+    };
+
+    let opponent_elo = match outcome {
+        Outcome::MultiGame => None,
+        // A loss ending on the floor may have been cut short, so the change understates it.
+        Outcome::Loss if after.elo <= ELO_FLOOR => None,
+        _ => implied_opponent_elo(before.elo, delta, score(outcome)),
+    };
+    // Without a record, a change too large for any single game means several were played.
+    let outcome = if played.is_none() && opponent_elo.is_none() && after.elo > ELO_FLOOR {
+        Outcome::MultiGame
+    } else {
+        outcome
+    };
+
+    Some(InferredGame {
+        time: after.time,
+        elo_before: before.elo,
+        elo_after: after.elo,
+        outcome,
+        games: played.map(|p| p.games()),
+        opponent_elo,
+    })
+}
+
+fn score(outcome: Outcome) -> f64 {
+    match outcome {
+        Outcome::Win => 1.0,
+        Outcome::Loss => 0.0,
+        _ => 0.5,
+    }
+}
+
+/// Showdown's K-factor for a player rated `elo` whose game scored `score`.
+fn k_factor(elo: f64, score: f64) -> f64 {
+    if elo < 1200.0 {
+        if score < 0.5 {
+            10.0 + (elo - 1000.0) * 40.0 / 200.0
+        } else if score > 0.5 {
+            90.0 - (elo - 1000.0) * 40.0 / 200.0
+        } else {
+            50.0
+        }
+    } else if elo > 1600.0 {
+        32.0
+    } else if elo > 1350.0 {
+        40.0
+    } else {
+        50.0
+    }
+}
+
+/// Inverts Showdown's update `elo + K * (score - E)`, where `E` is the expected score against the
+/// opponent, to find the opponent's rating. `None` when no opponent rating gives `delta`.
+fn implied_opponent_elo(elo: f64, delta: f64, score: f64) -> Option<f64> {
+    let k = k_factor(elo, score);
+    if k <= 0.0 {
+        return None;
+    }
+    let expected = score - delta / k;
+    if expected <= 0.0 || expected >= 1.0 {
+        return None;
+    }
+    Some(elo + 400.0 * ((1.0 - expected) / expected).log10())
+}
+
+/// The games played between two records. A record lower than the one before means the ladder was
+/// reset, so every game in the new record was played since.
+fn record_delta(before: Record, after: Record) -> Record {
+    if after.w < before.w || after.l < before.l || after.t < before.t {
+        after
+    } else {
+        Record {
+            w: after.w - before.w,
+            l: after.l - before.l,
+            t: after.t - before.t,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(time: u64, elo: f64, record: Option<(u32, u32, u32)>) -> Rating {
+        Rating::live(time, elo).with_record(record.map(|(w, l, t)| Record { w, l, t }))
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("an opponent elo");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "expected {} but got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn k_factor_follows_showdown() {
+        assert_eq!(k_factor(1100.0, 0.0), 30.0);
+        assert_eq!(k_factor(1100.0, 1.0), 70.0);
+        assert_eq!(k_factor(1100.0, 0.5), 50.0);
+        assert_eq!(k_factor(1300.0, 1.0), 50.0);
+        assert_eq!(k_factor(1400.0, 0.0), 40.0);
+        assert_eq!(k_factor(1700.0, 1.0), 32.0);
+    }
+
+    #[test]
+    fn implied_opponent_elo_inverts_the_update() {
+        // Half of K means an even match.
+        assert_close(implied_opponent_elo(1500.0, 20.0, 1.0), 1500.0);
+        // 1500 beating 1600 expects to score 1 / (1 + 10^(100/400)) and gains K times the rest.
+        let expected = 1.0 / (1.0 + 10f64.powf(0.25));
+        assert_close(
+            implied_opponent_elo(1500.0, 40.0 * (1.0 - expected), 1.0),
+            1600.0,
+        );
+        assert_close(
+            implied_opponent_elo(1700.0, -32.0 * (1.0 - expected), 0.0),
+            1600.0,
+        );
+        // No opponent gives a win a gain of K or more, or a loss a gain.
+        assert_eq!(implied_opponent_elo(1500.0, 40.0, 1.0), None);
+        assert_eq!(implied_opponent_elo(1500.0, 5.0, 0.0), None);
+    }
+
+    #[test]
+    fn infers_a_single_win() {
+        let game = infer_game(&rating(100, 1500.0, None), &rating(200, 1520.0, None)).unwrap();
+        assert_eq!(game.time, 200);
+        assert_eq!(game.outcome, Outcome::Win);
+        assert_eq!(game.games, None);
+        assert_close(game.opponent_elo, 1500.0);
+    }
+
+    #[test]
+    fn losses_at_the_floor_estimate_no_opponent() {
+        let game = infer_game(&rating(100, 1020.0, None), &rating(200, 1000.0, None)).unwrap();
+        assert_eq!(game.outcome, Outcome::Loss);
+        assert_eq!(game.opponent_elo, None);
+    }
+
+    #[test]
+    fn changes_too_large_for_one_game_are_several() {
+        let game = infer_game(&rating(100, 1500.0, None), &rating(200, 1560.0, None)).unwrap();
+        assert_eq!(game.outcome, Outcome::MultiGame);
+        assert_eq!(game.opponent_elo, None);
+    }
+
+    #[test]
+    fn records_decide_the_result() {
+        let before = rating(100, 1500.0, Some((10, 4, 0)));
+
+        let loss = infer_game(&before, &rating(200, 1480.0, Some((10, 5, 0)))).unwrap();
+        assert_eq!(loss.outcome, Outcome::Loss);
+        assert_eq!(loss.games, Some(1));
+        assert_close(loss.opponent_elo, 1500.0);
+
+        // A record of a tie overrides the rise.
+        let tie = infer_game(&before, &rating(200, 1510.0, Some((10, 4, 1)))).unwrap();
+        assert_eq!(tie.outcome, Outcome::Tie);
+
+        let several = infer_game(&before, &rating(200, 1510.0, Some((12, 5, 0)))).unwrap();
+        assert_eq!(several.outcome, Outcome::MultiGame);
+        assert_eq!(several.games, Some(3));
+        assert_eq!(several.opponent_elo, None);
+    }
+
+    #[test]
+    fn inferred_games_skips_unchanged_ratings() {
+        let ratings = [
+            rating(100, 1500.0, None),
+            rating(200, 1500.0, None),
+            rating(300, 1520.0, None),
+            rating(400, 1490.0, None),
+        ];
+        let games: Vec<(u64, Outcome)> = inferred_games(&ratings)
+            .iter()
+            .map(|game| (game.time, game.outcome))
+            .collect();
+        assert_eq!(games, [(300, Outcome::Win), (400, Outcome::Loss)]);
+    }
+}
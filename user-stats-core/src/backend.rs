@@ -0,0 +1,260 @@
+//! Storage backends that hold whole user histories, so tools can move users between DynamoDB and
+//! local environments without caring where they live.
+
+use crate::highlights::{all_peaks, Achievement, Highlights};
+use crate::model::{Rating, User};
+use crate::storage::{StoreError, StoredUser, UserStore, UserWithHighlights};
+use std::future::Future;
+
+/// How many alias links [`StorageBackend::resolve`] follows before giving up on a cycle.
+const MAX_ALIAS_HOPS: usize = 8;
+
+/// Somewhere tracked users can be listed, read and written as whole histories.
+pub trait StorageBackend {
+    /// Ids of every stored user.
+    fn user_ids(&self) -> impl Future<Output = Result<Vec<String>, StoreError>> + Send;
+
+    /// Reads a user with their full history.
+    fn load_user(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Option<User>, StoreError>> + Send;
+
+    /// Writes a user's whole history, replacing any existing one.
+    fn save_user(&self, user: &User) -> impl Future<Output = Result<(), StoreError>> + Send;
+
+    /// Reads a user as stored, without loading more of their history than an append needs.
+    /// Backends that store whole histories read the whole user as a [`StoredUser::Legacy`].
+    fn load_stored(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Option<StoredUser>, StoreError>> + Send
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.load_user(user_id).await?.map(StoredUser::Legacy)) }
+    }
+
+    /// Adds `new_ratings` (format, rating), which are later than any rating in the user's
+    /// history, to the end of a user read with [`StorageBackend::load_stored`] and writes them
+    /// along with any change to the user's username. Returns the highlights each new rating
+    /// achieved. Backends that store whole histories rewrite the user.
+    fn append_stored(
+        &self,
+        stored: StoredUser,
+        new_ratings: &[(String, Rating)],
+    ) -> impl Future<Output = Result<Vec<(String, Achievement)>, StoreError>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            match stored {
+                StoredUser::Legacy(user) => append_to_user(self, user, new_ratings).await,
+                StoredUser::Chunked(head) => Err(not_chunked(&head.userid)),
+            }
+        }
+    }
+
+    /// Moves a user read with [`StorageBackend::load_stored`] whose data names a different user id
+    /// under the key `user_id`. Backends that store whole histories only fix the user id, which
+    /// the next write stores under `user_id`.
+    fn rekey(
+        &self,
+        stored: StoredUser,
+        user_id: &str,
+    ) -> impl Future<Output = Result<StoredUser, StoreError>> + Send {
+        let user_id = user_id.to_string();
+        async move {
+            match stored {
+                StoredUser::Legacy(mut user) => {
+                    user.userid = user_id;
+                    Ok(StoredUser::Legacy(user))
+                }
+                StoredUser::Chunked(head) => Err(not_chunked(&head.userid)),
+            }
+        }
+    }
+
+    /// Sets a stored user's username and adds `new_ratings` (format, rating), which are later than
+    /// any rating in the user's history, to the end of it.
+    fn append_ratings(
+        &self,
+        user_id: &str,
+        username: &str,
+        new_ratings: &[(String, Rating)],
+    ) -> impl Future<Output = Result<(), StoreError>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let mut stored = self
+                .load_stored(user_id)
+                .await?
+                .ok_or_else(|| not_stored(user_id))?;
+            stored.set_username(username);
+            self.append_stored(stored, new_ratings).await.map(|_| ())
+        }
+    }
+
+    /// Marks a user as quarantined so sweeps stop retrying them, recording `reason`. Returns
+    /// false for backends without quarantine, whose sweeps keep retrying the user.
+    fn quarantine(
+        &self,
+        user_id: &str,
+        reason: &str,
+    ) -> impl Future<Output = Result<bool, StoreError>> + Send {
+        let _ = (user_id, reason);
+        async { Ok(false) }
+    }
+
+    /// The user id whose history `user_id` was merged into, if `user_id` is an alias. Backends
+    /// without aliases have none.
+    fn alias_of(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Option<String>, StoreError>> + Send {
+        let _ = user_id;
+        async { Ok(None) }
+    }
+
+    /// Follows alias links from `user_id` to the user id its history is stored under, which is
+    /// `user_id` itself unless it is an alias.
+    fn resolve(&self, user_id: &str) -> impl Future<Output = Result<String, StoreError>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let mut resolved = user_id.to_string();
+            for _ in 0..MAX_ALIAS_HOPS {
+                match self.alias_of(&resolved).await? {
+                    Some(target) => resolved = target,
+                    None => return Ok(resolved),
+                }
+            }
+            Err(StoreError::Corrupt(format!(
+                "alias links from {} do not end at a user",
+                user_id
+            )))
+        }
+    }
+
+    /// Reads a user with their full history and the highlights of each format. Backends that do
+    /// not store highlights compute them from the history.
+    fn load_user_with_highlights(
+        &self,
+        user_id: &str,
+    ) -> impl Future<Output = Result<Option<UserWithHighlights>, StoreError>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            Ok(self.load_user(user_id).await?.map(|user| {
+                let highlights = user
+                    .formats
+                    .iter()
+                    .map(|(format, ratings)| {
+                        let mut highlights = Highlights::from_ratings(ratings);
+                        highlights.peaks = all_peaks(ratings);
+                        (format.clone(), highlights)
+                    })
+                    .collect();
+                (user, highlights)
+            }))
+        }
+    }
+}
+
+impl StorageBackend for UserStore {
+    async fn user_ids(&self) -> Result<Vec<String>, StoreError> {
+        UserStore::user_ids(self).await
+    }
+
+    async fn load_user(&self, user_id: &str) -> Result<Option<User>, StoreError> {
+        UserStore::load_user(self, user_id).await
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), StoreError> {
+        UserStore::save_user(self, user).await.map(|_| ())
+    }
+
+    async fn load_stored(&self, user_id: &str) -> Result<Option<StoredUser>, StoreError> {
+        self.load(user_id).await
+    }
+
+    /// Writes only the chunks the new ratings fall in and the head, rather than the whole history.
+    async fn append_stored(
+        &self,
+        stored: StoredUser,
+        new_ratings: &[(String, Rating)],
+    ) -> Result<Vec<(String, Achievement)>, StoreError> {
+        match stored {
+            StoredUser::Chunked(mut head) => self.append(&mut head, new_ratings).await,
+            // Rewriting a legacy user moves their history into the chunked layout.
+            StoredUser::Legacy(user) => append_to_user(self, user, new_ratings).await,
+        }
+    }
+
+    async fn rekey(&self, stored: StoredUser, user_id: &str) -> Result<StoredUser, StoreError> {
+        UserStore::rekey(self, stored, user_id).await
+    }
+
+    async fn quarantine(&self, user_id: &str, reason: &str) -> Result<bool, StoreError> {
+        UserStore::quarantine(self, user_id, reason)
+            .await
+            .map(|_| true)
+    }
+
+    async fn alias_of(&self, user_id: &str) -> Result<Option<String>, StoreError> {
+        UserStore::alias_of(self, user_id).await
+    }
+
+    async fn load_user_with_highlights(
+        &self,
+        user_id: &str,
+    ) -> Result<Option<UserWithHighlights>, StoreError> {
+        UserStore::load_user_with_highlights(self, user_id).await
+    }
+}
+
+/// Adds `new_ratings` to the end of `user`'s history and writes the whole user.
+async fn append_to_user<S: StorageBackend + Sync + ?Sized>(
+    store: &S,
+    mut user: User,
+    new_ratings: &[(String, Rating)],
+) -> Result<Vec<(String, Achievement)>, StoreError> {
+    let achievements = push_ratings(&mut user, new_ratings);
+    store.save_user(&user).await?;
+    Ok(achievements)
+}
+
+/// Adds `new_ratings` to the end of `user`'s history, returning the highlights each new rating
+/// achieved. Ratings no later than the last one in their format are already there and skipped.
+pub(crate) fn push_ratings(
+    user: &mut User,
+    new_ratings: &[(String, Rating)],
+) -> Vec<(String, Achievement)> {
+    let mut achievements = Vec::new();
+    for (format, rating) in new_ratings {
+        let ratings = user.formats.entry(format.clone()).or_default();
+        if ratings.last().is_some_and(|last| last.time >= rating.time) {
+            continue;
+        }
+        let mut highlights = Highlights::from_ratings(ratings);
+        for achievement in highlights.push(ratings.last(), rating) {
+            achievements.push((format.clone(), achievement));
+        }
+        ratings.push(rating.clone());
+    }
+    achievements
+}
+
+fn not_stored(user_id: &str) -> StoreError {
+    StoreError::Corrupt(format!("user ID: {} is not stored", user_id))
+}
+
+pub(crate) fn not_chunked(user_id: &str) -> StoreError {
+    StoreError::Corrupt(format!(
+        "user ID: {} is chunked, which this backend does not store",
+        user_id
+    ))
+}
@@ -0,0 +1,167 @@
+//! Encoding of the blobs stored in DynamoDB attributes.
+//!
+//! Blobs start with a header of the magic bytes `PSUS`, the schema version and the encoding of the
+//! payload. Blobs written before the header existed are bare gzipped JSON, recognised by the gzip
+//! magic bytes, and are reported with the schema version the caller knows they were written with.
+//!
+//! Any blob may be gzipped JSON. Chunk ratings may instead use the [`Encoding::Columnar`] encoding
+//! described in the `columnar` module; readers pick the decoder from the header.
+
+use crate::columnar;
+use crate::model::Rating;
+use crate::schema::{CHUNKED_SCHEMA_VERSION, CURRENT_SCHEMA_VERSION};
+use crate::storage::StoreError;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::io::Write;
+use std::str::FromStr;
+
+const MAGIC: &[u8; 4] = b"PSUS";
+const GZIP_MAGIC: &[u8; 2] = &[0x1f, 0x8b];
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// How the payload after the header is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[repr(u8)]
+pub enum Encoding {
+    /// JSON compressed with gzip.
+    #[default]
+    JsonGzip = 0,
+    /// The columnar ratings encoding. Only valid for chunk ratings.
+    Columnar = 1,
+}
+
+impl Encoding {
+    fn from_byte(byte: u8) -> Result<Encoding, StoreError> {
+        match byte {
+            0 => Ok(Encoding::JsonGzip),
+            1 => Ok(Encoding::Columnar),
+            other => Err(StoreError::Codec(format!(
+                "unknown blob encoding {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::JsonGzip => write!(f, "json-gzip"),
+            Encoding::Columnar => write!(f, "columnar"),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Encoding, String> {
+        match s {
+            "json-gzip" => Ok(Encoding::JsonGzip),
+            "columnar" => Ok(Encoding::Columnar),
+            other => Err(format!(
+                "unknown encoding '{}', expected json-gzip or columnar",
+                other
+            )),
+        }
+    }
+}
+
+/// Encodes `value` as gzipped JSON with a header for the current schema version.
+pub fn encode_blob<T: Serialize>(value: &T) -> Result<Vec<u8>, StoreError> {
+    let mut blob = header(Encoding::JsonGzip);
+    blob.extend(to_json_gz(value)?);
+    Ok(blob)
+}
+
+/// Decodes a blob, returning the schema version it was written with alongside the value.
+/// `unversioned` is the version assumed for blobs without a header.
+pub fn decode_blob<T: DeserializeOwned>(
+    bytes: &[u8],
+    unversioned: u8,
+) -> Result<(u8, T), StoreError> {
+    let (version, encoding, payload) = split_header(bytes, unversioned)?;
+    match encoding {
+        Encoding::JsonGzip => Ok((version, from_json_gz(payload)?)),
+        Encoding::Columnar => Err(StoreError::Codec(
+            "columnar encoding is only used for ratings".to_string(),
+        )),
+    }
+}
+
+/// Encodes a chunk's ratings, keyed by format, with a header for the current schema version.
+pub fn encode_ratings(
+    ratings: &HashMap<String, Vec<Rating>>,
+    encoding: Encoding,
+) -> Result<Vec<u8>, StoreError> {
+    let mut blob = header(encoding);
+    match encoding {
+        Encoding::JsonGzip => blob.extend(to_json_gz(ratings)?),
+        Encoding::Columnar => blob.extend(columnar::encode(ratings)?),
+    }
+    Ok(blob)
+}
+
+/// Decodes a chunk's ratings in any encoding, returning the schema version they were written with.
+pub fn decode_ratings(bytes: &[u8]) -> Result<(u8, HashMap<String, Vec<Rating>>), StoreError> {
+    let (version, encoding, payload) = split_header(bytes, CHUNKED_SCHEMA_VERSION)?;
+    match encoding {
+        Encoding::JsonGzip => Ok((version, from_json_gz(payload)?)),
+        Encoding::Columnar => Ok((version, columnar::decode(payload, version)?)),
+    }
+}
+
+fn header(encoding: Encoding) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(HEADER_LEN);
+    blob.extend_from_slice(MAGIC);
+    blob.push(CURRENT_SCHEMA_VERSION);
+    blob.push(encoding as u8);
+    blob
+}
+
+/// Returns the schema version, encoding and payload of a blob.
+fn split_header(bytes: &[u8], unversioned: u8) -> Result<(u8, Encoding, &[u8]), StoreError> {
+    if bytes.starts_with(GZIP_MAGIC) {
+        return Ok((unversioned, Encoding::JsonGzip, bytes));
+    }
+    if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+        return Err(StoreError::Codec(
+            "blob has an unrecognised header".to_string(),
+        ));
+    }
+    let version = bytes[MAGIC.len()];
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(StoreError::UnsupportedVersion(version));
+    }
+    let encoding = Encoding::from_byte(bytes[MAGIC.len() + 1])?;
+    Ok((version, encoding, &bytes[HEADER_LEN..]))
+}
+
+fn to_json_gz<T: Serialize>(value: &T) -> Result<Vec<u8>, StoreError> {
+    let json = serde_json::to_string(value)
+        .map_err(|e| StoreError::Codec(format!("error serializing json: {}", e)))?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(json.as_bytes())
+        .map_err(|e| StoreError::Codec(format!("error compressing json: {}", e)))?;
+    encoder
+        .finish()
+        .map_err(|e| StoreError::Codec(format!("error compressing json: {}", e)))
+}
+
+fn from_json_gz<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StoreError> {
+    let mut decoder = GzDecoder::new(bytes);
+    let mut json = String::new();
+    decoder
+        .read_to_string(&mut json)
+        .map_err(|e| StoreError::Codec(format!("error decompressing Gzipped JSON: {}", e)))?;
+    serde_json::from_str(&json).map_err(|e| StoreError::Codec(format!("error parsing JSON: {}", e)))
+}
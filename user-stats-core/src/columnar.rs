@@ -0,0 +1,230 @@
+//! Compact columnar encoding of a chunk's ratings.
+//!
+//! Before compression the payload is a sequence of LEB128 varints:
+//!
+//! ```text
+//! format count
+//! per format: name length, name bytes, rating count,
+//!             timestamps (first absolute, then zigzag deltas),
+//!             elos in hundredths (first zigzag absolute, then zigzag deltas),
+//!             sources, one byte each (from schema version 4),
+//!             records, each a 0 byte when absent or a 1 byte then wins, losses and ties
+//!             (from schema version 5)
+//! ```
+//!
+//! Elos are quantized to two decimal places, which is finer than Showdown displays them.
+
+use crate::model::{Rating, Record, Source};
+use crate::schema::{RECORD_SCHEMA_VERSION, SOURCE_SCHEMA_VERSION};
+use crate::storage::StoreError;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+const ELO_SCALE: f64 = 100.0;
+
+pub(crate) fn encode(chunk: &HashMap<String, Vec<Rating>>) -> Result<Vec<u8>, StoreError> {
+    let mut out = Vec::new();
+    // Sorted so that equal chunks encode to equal bytes.
+    let mut formats: Vec<_> = chunk.iter().collect();
+    formats.sort_by(|a, b| a.0.cmp(b.0));
+
+    write_varint(&mut out, formats.len() as u64);
+    for (format, ratings) in formats {
+        write_varint(&mut out, format.len() as u64);
+        out.extend_from_slice(format.as_bytes());
+        write_varint(&mut out, ratings.len() as u64);
+
+        let mut previous = 0i64;
+        for rating in ratings {
+            let time = rating.time as i64;
+            write_varint(&mut out, zigzag(time.wrapping_sub(previous)));
+            previous = time;
+        }
+        let mut previous = 0i64;
+        for rating in ratings {
+            let elo = quantize(rating.elo)?;
+            write_varint(&mut out, zigzag(elo.wrapping_sub(previous)));
+            previous = elo;
+        }
+        for rating in ratings {
+            out.push(source_byte(rating.source));
+        }
+        for rating in ratings {
+            match rating.record {
+                Some(record) => {
+                    out.push(1);
+                    write_varint(&mut out, record.w as u64);
+                    write_varint(&mut out, record.l as u64);
+                    write_varint(&mut out, record.t as u64);
+                }
+                None => out.push(0),
+            }
+        }
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(&out)
+        .map_err(|e| StoreError::Codec(format!("error compressing ratings: {}", e)))?;
+    encoder
+        .finish()
+        .map_err(|e| StoreError::Codec(format!("error compressing ratings: {}", e)))
+}
+
+/// Decodes a payload written with schema `version`.
+pub(crate) fn decode(
+    bytes: &[u8],
+    version: u8,
+) -> Result<HashMap<String, Vec<Rating>>, StoreError> {
+    let mut raw = Vec::new();
+    GzDecoder::new(bytes)
+        .read_to_end(&mut raw)
+        .map_err(|e| StoreError::Codec(format!("error decompressing ratings: {}", e)))?;
+    let mut input = raw.as_slice();
+
+    let format_count = read_varint(&mut input)?;
+    let mut chunk = HashMap::new();
+    for _ in 0..format_count {
+        let name_len = read_len(&mut input)?;
+        let name = take(&mut input, name_len)?;
+        let format = String::from_utf8(name.to_vec())
+            .map_err(|_| StoreError::Codec("format name is not utf-8".to_string()))?;
+        let count = read_len(&mut input)?;
+
+        let mut times = Vec::with_capacity(count);
+        let mut previous = 0i64;
+        for _ in 0..count {
+            previous = previous.wrapping_add(unzigzag(read_varint(&mut input)?));
+            times.push(previous as u64);
+        }
+        let mut ratings = Vec::with_capacity(count);
+        let mut previous = 0i64;
+        for time in times {
+            previous = previous.wrapping_add(unzigzag(read_varint(&mut input)?));
+            ratings.push(Rating::live(time, previous as f64 / ELO_SCALE));
+        }
+        if version >= SOURCE_SCHEMA_VERSION {
+            for rating in &mut ratings {
+                rating.source = read_source(&mut input)?;
+            }
+        }
+        if version >= RECORD_SCHEMA_VERSION {
+            for rating in &mut ratings {
+                rating.record = read_record(&mut input)?;
+            }
+        }
+        chunk.insert(format, ratings);
+    }
+    if !input.is_empty() {
+        return Err(StoreError::Codec(
+            "trailing bytes after ratings".to_string(),
+        ));
+    }
+    Ok(chunk)
+}
+
+/// Whether two elos are stored as the same value.
+pub(crate) fn same_elo(a: f64, b: f64) -> bool {
+    (a * ELO_SCALE).round() == (b * ELO_SCALE).round()
+}
+
+fn quantize(elo: f64) -> Result<i64, StoreError> {
+    let scaled = (elo * ELO_SCALE).round();
+    if !scaled.is_finite() || scaled.abs() >= i64::MAX as f64 {
+        return Err(StoreError::Codec(format!("elo {} cannot be encoded", elo)));
+    }
+    Ok(scaled as i64)
+}
+
+fn source_byte(source: Source) -> u8 {
+    match source {
+        Source::Live => 0,
+        Source::Replay => 1,
+    }
+}
+
+fn read_source(input: &mut &[u8]) -> Result<Source, StoreError> {
+    match take(input, 1)?[0] {
+        0 => Ok(Source::Live),
+        1 => Ok(Source::Replay),
+        other => Err(StoreError::Codec(format!(
+            "unknown rating source {}",
+            other
+        ))),
+    }
+}
+
+fn read_record(input: &mut &[u8]) -> Result<Option<Record>, StoreError> {
+    match take(input, 1)?[0] {
+        0 => Ok(None),
+        1 => Ok(Some(Record {
+            w: read_count(input)?,
+            l: read_count(input)?,
+            t: read_count(input)?,
+        })),
+        other => Err(StoreError::Codec(format!(
+            "unknown record marker {}",
+            other
+        ))),
+    }
+}
+
+fn read_count(input: &mut &[u8]) -> Result<u32, StoreError> {
+    u32::try_from(read_varint(input)?)
+        .map_err(|_| StoreError::Codec("record count is too large".to_string()))
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64, StoreError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input
+            .split_first()
+            .ok_or_else(|| StoreError::Codec("ratings ended mid-value".to_string()))?;
+        *input = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(StoreError::Codec("varint is too long".to_string()))
+}
+
+/// Reads a length, rejecting ones longer than the remaining input so corrupt data cannot trigger
+/// huge allocations. Every element takes at least one byte.
+fn read_len(input: &mut &[u8]) -> Result<usize, StoreError> {
+    let len = read_varint(input)?;
+    if len > input.len() as u64 {
+        return Err(StoreError::Codec(
+            "length exceeds remaining ratings".to_string(),
+        ));
+    }
+    Ok(len as usize)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], StoreError> {
+    if input.len() < len {
+        return Err(StoreError::Codec("ratings ended mid-value".to_string()));
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
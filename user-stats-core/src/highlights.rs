@@ -0,0 +1,340 @@
+//! Personal bests, streaks and milestones in a format's rating history.
+//!
+//! [`Highlights`] are derived from the ratings, but kept in each user's head item so the updater
+//! can extend them one rating at a time instead of rereading the whole history.
+
+use crate::model::Rating;
+use serde::{Deserialize, Serialize};
+
+/// The first milestone. Every multiple of [`MILESTONE_STEP`] from here up is one too.
+pub const FIRST_MILESTONE: u32 = 1500;
+pub const MILESTONE_STEP: u32 = 100;
+
+/// How many of the latest peaks [`Highlights`] keep. A user climbing steadily sets a new peak with
+/// most ratings, so keeping every one would grow the head item with the history.
+pub const RECENT_PEAKS: usize = 20;
+
+/// A rating that beat every rating before it in the format.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Peak {
+    pub time: u64,
+    pub elo: f64,
+}
+
+/// Whether ratings in a streak went up or down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Trend {
+    Rising,
+    Falling,
+}
+
+/// A run of consecutive ratings that each moved the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Streak {
+    pub trend: Trend,
+    /// Number of changes in the run.
+    pub length: u32,
+    /// Time of the rating the run started from.
+    pub start: u64,
+    /// Time of the last rating of the run.
+    pub end: u64,
+    /// Elo before the run began.
+    pub from_elo: f64,
+    pub to_elo: f64,
+}
+
+/// The first time a rating reached a milestone.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Milestone {
+    pub elo: u32,
+    pub time: u64,
+}
+
+/// Something a new rating achieved, as reported by [`Highlights::push`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Achievement {
+    NewPeak(Peak),
+    Milestone(Milestone),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Highlights {
+    /// The latest ratings that set a new peak, at most [`RECENT_PEAKS`] of them, oldest first.
+    /// The last one is the current peak. [`all_peaks`] derives the rest from the history.
+    pub peaks: Vec<Peak>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_streak: Option<Streak>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longest_rise: Option<Streak>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longest_fall: Option<Streak>,
+    /// Milestones reached, lowest first.
+    pub milestones: Vec<Milestone>,
+}
+
+impl Highlights {
+    /// Computes the highlights of `ratings`, which must be sorted by time.
+    pub fn from_ratings(ratings: &[Rating]) -> Highlights {
+        let mut highlights = Highlights::default();
+        let mut previous = None;
+        for rating in ratings {
+            highlights.push(previous, rating);
+            previous = Some(rating);
+        }
+        highlights
+    }
+
+    pub fn peak(&self) -> Option<&Peak> {
+        self.peaks.last()
+    }
+
+    /// Extends the highlights with `rating`, which follows `previous` in the format's history.
+    /// Returns the new peak and milestones it reached, if any.
+    pub fn push(&mut self, previous: Option<&Rating>, rating: &Rating) -> Vec<Achievement> {
+        let mut achievements = Vec::new();
+
+        if self.peak().is_none_or(|peak| rating.elo > peak.elo) {
+            let peak = Peak {
+                time: rating.time,
+                elo: rating.elo,
+            };
+            achievements.push(Achievement::NewPeak(peak));
+            self.peaks.push(peak);
+            if self.peaks.len() > RECENT_PEAKS {
+                self.peaks.remove(0);
+            }
+        }
+
+        let reached = self.milestones.last().map_or(0, |m| m.elo);
+        let mut milestone = reached.max(FIRST_MILESTONE - MILESTONE_STEP) + MILESTONE_STEP;
+        while rating.elo >= milestone as f64 {
+            let reached = Milestone {
+                elo: milestone,
+                time: rating.time,
+            };
+            achievements.push(Achievement::Milestone(reached));
+            self.milestones.push(reached);
+            milestone += MILESTONE_STEP;
+        }
+
+        match previous {
+            Some(previous) => {
+                self.extend_streak(previous, rating);
+                achievements
+            }
+            // The first rating is where the history starts rather than an achievement.
+            None => Vec::new(),
+        }
+    }
+
+    fn extend_streak(&mut self, previous: &Rating, rating: &Rating) {
+        let trend = if rating.elo > previous.elo {
+            Trend::Rising
+        } else if rating.elo < previous.elo {
+            Trend::Falling
+        } else {
+            return;
+        };
+        let streak = match self.current_streak {
+            Some(mut streak) if streak.trend == trend => {
+                streak.length += 1;
+                streak.end = rating.time;
+                streak.to_elo = rating.elo;
+                streak
+            }
+            _ => Streak {
+                trend,
+                length: 1,
+                start: previous.time,
+                end: rating.time,
+                from_elo: previous.elo,
+                to_elo: rating.elo,
+            },
+        };
+        self.current_streak = Some(streak);
+
+        let longest = match trend {
+            Trend::Rising => &mut self.longest_rise,
+            Trend::Falling => &mut self.longest_fall,
+        };
+        if longest.is_none_or(|longest| streak.length > longest.length) {
+            *longest = Some(streak);
+        }
+    }
+}
+
+/// Every rating in `ratings`, which must be sorted by time, that set a new peak, oldest first.
+pub fn all_peaks(ratings: &[Rating]) -> Vec<Peak> {
+    let mut peaks: Vec<Peak> = Vec::new();
+    for rating in ratings {
+        if peaks.last().is_none_or(|peak| rating.elo > peak.elo) {
+            peaks.push(Peak {
+                time: rating.time,
+                elo: rating.elo,
+            });
+        }
+    }
+    peaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratings(elos: &[f64]) -> Vec<Rating> {
+        elos.iter()
+            .enumerate()
+            .map(|(i, elo)| Rating::live(100 * (i as u64 + 1), *elo))
+            .collect()
+    }
+
+    fn push(
+        highlights: &mut Highlights,
+        previous: &Rating,
+        time: u64,
+        elo: f64,
+    ) -> Vec<Achievement> {
+        highlights.push(Some(previous), &Rating::live(time, elo))
+    }
+
+    #[test]
+    fn the_first_rating_is_recorded_without_achievements() {
+        let mut highlights = Highlights::default();
+        let achievements = highlights.push(None, &Rating::live(100, 1650.0));
+
+        assert_eq!(achievements, []);
+        assert_eq!(
+            highlights,
+            Highlights {
+                peaks: vec![Peak {
+                    time: 100,
+                    elo: 1650.0
+                }],
+                current_streak: None,
+                longest_rise: None,
+                longest_fall: None,
+                milestones: vec![
+                    Milestone {
+                        elo: 1500,
+                        time: 100
+                    },
+                    Milestone {
+                        elo: 1600,
+                        time: 100
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn equalling_the_peak_is_not_a_new_peak() {
+        let mut highlights = Highlights::from_ratings(&ratings(&[1400.0, 1450.0]));
+        let previous = Rating::live(300, 1420.0);
+
+        assert_eq!(push(&mut highlights, &previous, 400, 1450.0), []);
+        assert_eq!(
+            push(&mut highlights, &previous, 500, 1450.5),
+            [Achievement::NewPeak(Peak {
+                time: 500,
+                elo: 1450.5
+            })]
+        );
+        assert_eq!(highlights.peaks.len(), 3);
+        assert_eq!(highlights.peak().unwrap().time, 500);
+    }
+
+    #[test]
+    fn a_drop_ends_a_rise_and_starts_a_fall() {
+        let highlights =
+            Highlights::from_ratings(&ratings(&[1000.0, 1020.0, 1040.0, 1040.0, 1060.0, 1030.0]));
+
+        assert_eq!(
+            highlights.current_streak,
+            Some(Streak {
+                trend: Trend::Falling,
+                length: 1,
+                start: 500,
+                end: 600,
+                from_elo: 1060.0,
+                to_elo: 1030.0,
+            })
+        );
+        // An unchanged rating neither extends nor ends the streak.
+        assert_eq!(
+            highlights.longest_rise,
+            Some(Streak {
+                trend: Trend::Rising,
+                length: 3,
+                start: 100,
+                end: 500,
+                from_elo: 1000.0,
+                to_elo: 1060.0,
+            })
+        );
+        assert_eq!(highlights.longest_fall, highlights.current_streak);
+    }
+
+    #[test]
+    fn one_rating_can_reach_several_milestones() {
+        let mut highlights = Highlights::from_ratings(&ratings(&[1450.0, 1520.0]));
+        let previous = Rating::live(200, 1520.0);
+        let achievements = push(&mut highlights, &previous, 300, 1810.0);
+
+        assert_eq!(
+            achievements,
+            [
+                Achievement::NewPeak(Peak {
+                    time: 300,
+                    elo: 1810.0
+                }),
+                Achievement::Milestone(Milestone {
+                    elo: 1600,
+                    time: 300
+                }),
+                Achievement::Milestone(Milestone {
+                    elo: 1700,
+                    time: 300
+                }),
+                Achievement::Milestone(Milestone {
+                    elo: 1800,
+                    time: 300
+                }),
+            ]
+        );
+        let reached: Vec<u32> = highlights.milestones.iter().map(|m| m.elo).collect();
+        assert_eq!(reached, [1500, 1600, 1700, 1800]);
+
+        // Falling back and climbing again does not reach them a second time.
+        push(&mut highlights, &Rating::live(300, 1810.0), 400, 1550.0);
+        assert_eq!(
+            push(&mut highlights, &Rating::live(400, 1550.0), 500, 1805.0),
+            []
+        );
+    }
+
+    #[test]
+    fn only_the_latest_peaks_are_kept() {
+        let elos: Vec<f64> = (0..RECENT_PEAKS + 5).map(|i| 1000.0 + i as f64).collect();
+        let ratings = ratings(&elos);
+        let highlights = Highlights::from_ratings(&ratings);
+
+        let all = all_peaks(&ratings);
+        assert_eq!(all.len(), RECENT_PEAKS + 5);
+        assert_eq!(highlights.peaks, all[5..]);
+        assert_eq!(highlights.peak(), all.last());
+    }
+
+    #[test]
+    fn from_ratings_matches_pushing_one_at_a_time() {
+        let ratings = ratings(&[1480.0, 1530.0, 1510.0, 1620.0]);
+        let mut highlights = Highlights::default();
+        for (i, rating) in ratings.iter().enumerate() {
+            highlights.push(i.checked_sub(1).map(|i| &ratings[i]), rating);
+        }
+        assert_eq!(Highlights::from_ratings(&ratings), highlights);
+    }
+}
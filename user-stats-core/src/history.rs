@@ -0,0 +1,212 @@
+//! Combining histories of the same user from different sources, such as a backup and the live
+//! table, a stored user and a fresh snapshot from Showdown, or ratings rebuilt from replays.
+
+use crate::columnar::same_elo;
+use crate::model::{Rating, User};
+
+impl User {
+    /// Adds `other`'s ratings to this user's history, then [normalizes](Self::normalize) it.
+    /// Where both users have a rating for a format at the same time, this user's rating is kept,
+    /// as are this user's username and userid.
+    pub fn merge(&mut self, other: User) {
+        for (format, mut ratings) in other.formats {
+            self.formats.entry(format).or_default().append(&mut ratings);
+        }
+        self.normalize();
+    }
+
+    /// Sorts every format's ratings by time, keeps one rating per timestamp and collapses runs of
+    /// consecutive identical elos into their first rating. A later rating in a run is kept if it
+    /// reports a different record, since games were played even though the elo did not move.
+    pub fn normalize(&mut self) {
+        for ratings in self.formats.values_mut() {
+            // Stable, so on equal timestamps the rating that came first is kept.
+            ratings.sort_by_key(|r| r.time);
+            ratings.dedup_by_key(|r| r.time);
+            ratings.dedup_by(|current, previous| current.repeats(previous));
+        }
+    }
+
+    /// The latest rating of each of `snapshot`'s formats that [`merge`](Self::merge) would keep at
+    /// the end of this user's history: one later than the format's last rating that does not
+    /// [repeat](Rating::repeats) it. Appending these is the same as merging `snapshot`, without
+    /// rewriting the history.
+    pub fn new_ratings(&self, snapshot: User) -> Vec<(String, Rating)> {
+        snapshot
+            .formats
+            .into_iter()
+            .filter_map(|(format, mut ratings)| {
+                let rating = ratings.pop()?;
+                let latest = self.formats.get(&format).and_then(|r| r.last());
+                latest
+                    .is_none_or(|latest| rating.time > latest.time && !rating.repeats(latest))
+                    .then_some((format, rating))
+            })
+            .collect()
+    }
+}
+
+// Elos are compared at the precision chunks store them in, so that a rating read back from a
+// columnar chunk still matches the one it was written from.
+impl Rating {
+    /// Whether this rating adds nothing to a history ending with `previous`: the elo did not move
+    /// and it reports no record, or the same one.
+    pub fn repeats(&self, previous: &Rating) -> bool {
+        same_elo(self.elo, previous.elo)
+            && (self.record.is_none() || self.record == previous.record)
+    }
+
+    /// Whether this rating is `other` once stored.
+    pub fn stores_as(&self, other: &Rating) -> bool {
+        self.time == other.time
+            && same_elo(self.elo, other.elo)
+            && self.source == other.source
+            && self.record == other.record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{Rating, Record, Source, User};
+    use std::collections::HashMap;
+
+    fn rating(time: u64, elo: f64, record: Option<(u32, u32)>) -> Rating {
+        Rating {
+            time,
+            elo,
+            source: Source::Live,
+            record: record.map(|(w, l)| Record { w, l, t: 0 }),
+        }
+    }
+
+    fn user(ratings: Vec<Rating>) -> User {
+        User {
+            username: "Some User".to_string(),
+            userid: "someuser".to_string(),
+            formats: HashMap::from([("gen9ou".to_string(), ratings)]),
+        }
+    }
+
+    fn times(user: &User) -> Vec<u64> {
+        user.formats["gen9ou"].iter().map(|r| r.time).collect()
+    }
+
+    #[test]
+    fn normalize_sorts_and_keeps_the_first_rating_per_timestamp() {
+        let mut user = user(vec![
+            rating(30, 1100.0, None),
+            rating(10, 1000.0, None),
+            rating(30, 1200.0, None),
+            rating(20, 1050.0, None),
+        ]);
+        user.normalize();
+        assert_eq!(
+            user.formats["gen9ou"],
+            [
+                rating(10, 1000.0, None),
+                rating(20, 1050.0, None),
+                rating(30, 1100.0, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn normalize_collapses_runs_of_identical_elos() {
+        let mut user = user(vec![
+            rating(10, 1000.0, None),
+            rating(20, 1000.0, None),
+            rating(30, 1050.0, None),
+            rating(40, 1050.0, None),
+            rating(50, 1000.0, None),
+        ]);
+        user.normalize();
+        // Returning to an earlier elo after a change starts a new run.
+        assert_eq!(times(&user), [10, 30, 50]);
+    }
+
+    #[test]
+    fn normalize_keeps_ratings_whose_record_changed() {
+        let mut user = user(vec![
+            rating(10, 1000.0, Some((1, 1))),
+            rating(20, 1000.0, Some((1, 1))),
+            rating(30, 1000.0, Some((2, 2))),
+            // A rating without a record says nothing about games played.
+            rating(40, 1000.0, None),
+            rating(50, 1000.0, Some((2, 2))),
+        ]);
+        user.normalize();
+        assert_eq!(times(&user), [10, 30]);
+    }
+
+    #[test]
+    fn elos_are_compared_at_stored_precision() {
+        let stored = rating(10, 1500.12, Some((1, 1)));
+        assert!(rating(20, 1500.1234, Some((1, 1))).repeats(&stored));
+        assert!(!rating(20, 1500.13, Some((1, 1))).repeats(&stored));
+        assert!(rating(10, 1500.1234, Some((1, 1))).stores_as(&stored));
+        assert!(!rating(20, 1500.1234, Some((1, 1))).stores_as(&stored));
+        assert!(!rating(10, 1500.1234, Some((2, 1))).stores_as(&stored));
+    }
+
+    #[test]
+    fn merge_prefers_this_users_ratings_on_timestamp_ties() {
+        let mut live = user(vec![rating(10, 1000.0, None), rating(30, 1100.0, None)]);
+        let mut backup = user(vec![
+            rating(10, 1500.0, None),
+            rating(20, 1050.0, None),
+            rating(30, 1100.0, None),
+        ]);
+        backup.username = "Old Name".to_string();
+        backup
+            .formats
+            .insert("gen9uu".to_string(), vec![rating(5, 1200.0, None)]);
+
+        live.merge(backup);
+        assert_eq!(live.username, "Some User");
+        assert_eq!(
+            live.formats["gen9ou"],
+            [
+                rating(10, 1000.0, None),
+                rating(20, 1050.0, None),
+                rating(30, 1100.0, None),
+            ]
+        );
+        assert_eq!(live.formats["gen9uu"], [rating(5, 1200.0, None)]);
+    }
+
+    #[test]
+    fn merge_collapses_identical_elos_across_sources() {
+        let mut live = user(vec![rating(20, 1000.0, None), rating(40, 1100.0, None)]);
+        live.merge(user(vec![
+            rating(10, 1000.0, None),
+            rating(30, 1100.0, None),
+        ]));
+        assert_eq!(times(&live), [10, 30]);
+    }
+
+    #[test]
+    fn new_ratings_are_the_ones_merge_would_append() {
+        let mut stored = user(vec![rating(10, 1000.0, Some((1, 1)))]);
+        stored.formats.extend([
+            ("gen9uu".to_string(), vec![rating(10, 1200.0, Some((1, 1)))]),
+            ("gen9lc".to_string(), vec![rating(30, 1100.0, None)]),
+        ]);
+        let mut snapshot = user(vec![rating(20, 1000.0, Some((1, 1)))]);
+        snapshot.formats.extend([
+            ("gen9uu".to_string(), vec![rating(20, 1200.0, Some((2, 1)))]),
+            // Not later than the stored history, so merging would not put it at the end.
+            ("gen9lc".to_string(), vec![rating(20, 1150.0, None)]),
+            ("gen9ubers".to_string(), vec![rating(20, 1300.0, None)]),
+        ]);
+
+        let mut new_ratings = stored.new_ratings(snapshot);
+        new_ratings.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            new_ratings,
+            [
+                ("gen9ubers".to_string(), rating(20, 1300.0, None)),
+                ("gen9uu".to_string(), rating(20, 1200.0, Some((2, 1)))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,15 @@
+//! Code shared by the lambdas and the updater for reading and writing tracked users.
+
+pub mod analysis;
+pub mod backend;
+pub mod codec;
+mod columnar;
+pub mod highlights;
+pub mod history;
+pub mod local;
+pub mod model;
+pub mod schema;
+pub mod storage;
+pub mod time;
+pub mod user_id;
+pub mod webhooks;
@@ -0,0 +1,132 @@
+//! A [`StorageBackend`] that keeps each user as a JSON file in a local directory, for development
+//! environments and tests.
+
+use crate::backend::{not_chunked, push_ratings, StorageBackend};
+use crate::highlights::Achievement;
+use crate::model::{Rating, User};
+use crate::storage::{StoreError, StoredUser};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+const EXTENSION: &str = "json";
+
+/// Stores every user as `<dir>/<userid>.json`. Clones share a lock, so a server and the sweep
+/// running beside it can each hold one.
+#[derive(Clone)]
+pub struct LocalStore {
+    dir: PathBuf,
+    /// Held while writing, and from reading a user to writing them back when appending, so
+    /// concurrent writers neither share a temporary file nor drop each other's ratings.
+    lock: Arc<Mutex<()>>,
+}
+
+impl LocalStore {
+    pub fn new(dir: impl Into<PathBuf>) -> LocalStore {
+        LocalStore {
+            dir: dir.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn path(&self, user_id: &str) -> Result<PathBuf, StoreError> {
+        // User ids are normalized to lowercase alphanumerics, so anything else is not a user and
+        // must not be allowed to escape the directory.
+        if user_id.is_empty() || !user_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(StoreError::Corrupt(format!(
+                "invalid user id '{}'",
+                user_id
+            )));
+        }
+        Ok(self.dir.join(format!("{}.{}", user_id, EXTENSION)))
+    }
+
+    async fn read(&self, user_id: &str) -> Result<Option<User>, StoreError> {
+        let path = self.path(user_id)?;
+        let json = match fs::read_to_string(&path).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| StoreError::Codec(format!("error parsing {}: {}", path.display(), e)))
+    }
+
+    /// Writes `user`. Callers hold the lock.
+    async fn write(&self, user: &User) -> Result<(), StoreError> {
+        let path = self.path(&user.userid)?;
+        let json = serde_json::to_string(user)
+            .map_err(|e| StoreError::Codec(format!("error serializing json: {}", e)))?;
+        fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| io_error(&self.dir, e))?;
+        // Write to a temporary file first so a crash never leaves a half-written user behind.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json).await.map_err(|e| io_error(&tmp, e))?;
+        fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| io_error(&path, e))
+    }
+}
+
+impl StorageBackend for LocalStore {
+    async fn user_ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(&self.dir, e)),
+        };
+        let mut user_ids = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| io_error(&self.dir, e))?
+        {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            if let Some(user_id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                user_ids.push(user_id.to_string());
+            }
+        }
+        user_ids.sort();
+        Ok(user_ids)
+    }
+
+    async fn load_user(&self, user_id: &str) -> Result<Option<User>, StoreError> {
+        self.read(user_id).await
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), StoreError> {
+        let _guard = self.lock.lock().await;
+        self.write(user).await
+    }
+
+    /// Appends to the user as they are on disk now rather than as `stored` was read, so ratings
+    /// written since then are kept. The username and user id are taken from `stored`.
+    async fn append_stored(
+        &self,
+        stored: StoredUser,
+        new_ratings: &[(String, Rating)],
+    ) -> Result<Vec<(String, Achievement)>, StoreError> {
+        let mut user = match stored {
+            StoredUser::Legacy(user) => user,
+            StoredUser::Chunked(head) => return Err(not_chunked(&head.userid)),
+        };
+        let _guard = self.lock.lock().await;
+        if let Some(current) = self.read(&user.userid).await? {
+            user.formats = current.formats;
+        }
+        let achievements = push_ratings(&mut user, new_ratings);
+        self.write(&user).await?;
+        Ok(achievements)
+    }
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> StoreError {
+    StoreError::Io(format!("{}: {}", path.display(), error))
+}
@@ -0,0 +1,87 @@
+//! The tracked user types.
+//!
+//! These serialize to the same JSON as `pokemon-showdown-user-stats-model`, which the front end
+//! and older stored blobs use, with optional extra fields that are left out when unset.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Where a rating came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// Recorded by `update-stats` or when the user was added.
+    #[default]
+    Live,
+    /// Reconstructed from a battle replay by the backfill tool.
+    Replay,
+}
+
+impl Source {
+    pub fn is_live(&self) -> bool {
+        *self == Source::Live
+    }
+}
+
+/// A user's ladder rating in one format at one point in time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    /// Unix timestamp in seconds.
+    pub time: u64,
+    pub elo: f64,
+    #[serde(default, skip_serializing_if = "Source::is_live")]
+    pub source: Source,
+    /// The user's ladder record in the format when the rating was taken, if Showdown reported one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<Record>,
+}
+
+/// Wins, losses and ties on a format's ladder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Record {
+    pub w: u32,
+    pub l: u32,
+    pub t: u32,
+}
+
+impl Record {
+    /// Reads the `w`, `l` and `t` counts from one format's entry in a Showdown user's `ratings`,
+    /// when all three are present.
+    pub fn from_showdown(rating: &serde_json::Value) -> Option<Record> {
+        let count = |key: &str| rating[key].as_u64().and_then(|n| u32::try_from(n).ok());
+        Some(Record {
+            w: count("w")?,
+            l: count("l")?,
+            t: count("t")?,
+        })
+    }
+
+    pub fn games(&self) -> u32 {
+        self.w + self.l + self.t
+    }
+}
+
+impl Rating {
+    /// A rating recorded live from Showdown.
+    pub fn live(time: u64, elo: f64) -> Rating {
+        Rating {
+            time,
+            elo,
+            source: Source::Live,
+            record: None,
+        }
+    }
+
+    pub fn with_record(mut self, record: Option<Record>) -> Rating {
+        self.record = record;
+        self
+    }
+}
+
+/// A tracked user with their rating history per format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub userid: String,
+    pub formats: HashMap<String, Vec<Rating>>,
+}
@@ -0,0 +1,24 @@
+//! Versions of the stored user schema.
+//!
+//! Readers accept every version listed here. Writers always produce [`CURRENT_SCHEMA_VERSION`],
+//! and the `migrate` tool in `user-stats-admin` rewrites older users in place.
+
+/// The whole history as raw [`crate::model::User`] JSON in a single `stats.json.gz` attribute.
+pub const LEGACY_SCHEMA_VERSION: u8 = 1;
+
+/// A head item plus one chunk item per month, each blob bare gzipped JSON.
+pub const CHUNKED_SCHEMA_VERSION: u8 = 2;
+
+/// As [`CHUNKED_SCHEMA_VERSION`], with every blob prefixed by a header naming its schema version
+/// and encoding.
+pub const HEADER_SCHEMA_VERSION: u8 = 3;
+
+/// Ratings may record their [`crate::model::Source`]; the columnar encoding gains a source column.
+pub const SOURCE_SCHEMA_VERSION: u8 = 4;
+
+/// Ratings may carry a win/loss/tie [`crate::model::Record`]; the columnar encoding gains a record
+/// column.
+pub const RECORD_SCHEMA_VERSION: u8 = 5;
+
+/// The version every blob is written with.
+pub const CURRENT_SCHEMA_VERSION: u8 = RECORD_SCHEMA_VERSION;
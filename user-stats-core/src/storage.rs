@@ -0,0 +1,870 @@
+//! Storage layout for tracked users.
+//!
+//! A DynamoDB item is limited to 400 KB, so a user's history is not kept in a single item.
+//! Instead every user has:
+//!
+//! - a head item keyed by `userId`, holding the username, the latest rating per format and the
+//!   list of months that have history, and
+//! - one chunk item per month keyed by `userId#YYYY-MM`, holding that month's ratings per format.
+//!
+//! Users with webhook subscriptions also have an item keyed by `userId#webhooks`.
+//!
+//! When a player moves to a new Showdown account, the old user's history can be merged into the
+//! new one. The old user is then replaced by an alias item keyed by `oldUserId#alias`, naming the
+//! user id the history now lives under so lookups of the old name still find it.
+//!
+//! Appending a rating only touches the head and the current month's chunk. Head items written
+//! before this layout existed store the whole [`User`] in `stats.json.gz`; they are still read and
+//! are rewritten in the chunked layout the next time they are saved. See [`crate::schema`] for the
+//! full list of versions and [`UserStore::migrate`] for upgrading a user in place.
+//!
+//! A head item that cannot be decoded is quarantined by adding `quarantinedAt` and
+//! `quarantineError` attributes. Quarantined users are left out of [`UserStore::scan_heads`] until
+//! they are repaired, which rewrites the head item without those attributes.
+
+use crate::codec::{decode_blob, decode_ratings, encode_blob, encode_ratings, Encoding};
+use crate::highlights::{all_peaks, Achievement, Highlights};
+use crate::model::{Rating, User};
+use crate::schema::{CHUNKED_SCHEMA_VERSION, CURRENT_SCHEMA_VERSION, LEGACY_SCHEMA_VERSION};
+use crate::time::month_of;
+use crate::webhooks::Subscription;
+use aws_sdk_dynamodb as dynamodb;
+use dynamodb::types::{AttributeValue, KeysAndAttributes};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::time::SystemTime;
+
+pub const USER_ID_KEY: &str = "userId";
+const HEAD_ATTRIBUTE: &str = "head.json.gz";
+const CHUNK_ATTRIBUTE: &str = "ratings.json.gz";
+const LEGACY_ATTRIBUTE: &str = "stats.json.gz";
+const CHUNK_SEPARATOR: &str = "#";
+const QUARANTINED_AT_ATTRIBUTE: &str = "quarantinedAt";
+const QUARANTINE_ERROR_ATTRIBUTE: &str = "quarantineError";
+/// Suffix of the key of the item holding a user's webhook subscriptions, after the separator.
+const WEBHOOKS_SUFFIX: &str = "webhooks";
+const SUBSCRIPTIONS_ATTRIBUTE: &str = "subscriptions.json";
+/// Suffix of the key of an alias item, after the separator.
+const ALIAS_SUFFIX: &str = "alias";
+const ALIAS_OF_ATTRIBUTE: &str = "aliasOf";
+/// BatchGetItem accepts at most 100 keys per request.
+const BATCH_GET_LIMIT: usize = 100;
+
+pub type Item = HashMap<String, AttributeValue>;
+type Chunk = HashMap<String, Vec<Rating>>;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// A DynamoDB request failed.
+    Dynamo(String),
+    /// An item exists but does not have the expected attributes.
+    Corrupt(String),
+    /// Encoding or decoding a stored blob failed.
+    Codec(String),
+    /// A blob was written with a newer schema version than this build understands.
+    UnsupportedVersion(u8),
+    /// Reading or writing a local file failed.
+    Io(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Dynamo(msg) => write!(f, "database error: {}", msg),
+            StoreError::Corrupt(msg) => write!(f, "corrupt item: {}", msg),
+            StoreError::Codec(msg) => write!(f, "{}", msg),
+            StoreError::Io(msg) => write!(f, "io error: {}", msg),
+            StoreError::UnsupportedVersion(version) => write!(
+                f,
+                "schema version {} is newer than the supported version {}",
+                version, CURRENT_SCHEMA_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl StoreError {
+    /// Whether the error comes from unreadable stored data rather than from DynamoDB or a newer
+    /// schema. Retrying will not help; the user needs repairing.
+    pub fn is_corrupt(&self) -> bool {
+        matches!(self, StoreError::Corrupt(_) | StoreError::Codec(_))
+    }
+}
+
+/// History recovered from a user whose items could not all be decoded.
+#[derive(Debug, Default)]
+pub struct Salvage {
+    pub username: Option<String>,
+    /// Ratings per format that could still be read, sorted by time.
+    pub formats: HashMap<String, Vec<Rating>>,
+    /// Months whose chunk items were referenced but could not be read.
+    pub lost_chunks: BTreeSet<String>,
+    /// Why parts of the history were lost.
+    pub problems: Vec<String>,
+}
+
+/// Current state of a tracked user, small enough to read on every update.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserHead {
+    pub username: String,
+    pub userid: String,
+    /// Most recent rating per format.
+    pub latest: HashMap<String, Rating>,
+    /// Months (`YYYY-MM`) that have a chunk item.
+    pub chunks: BTreeSet<String>,
+    /// Peaks, streaks and milestones per format. Heads written before these were tracked have
+    /// none; they are filled in the next time the user is written.
+    #[serde(default)]
+    pub highlights: HashMap<String, Highlights>,
+}
+
+/// A tracked user as read from its head item.
+#[derive(Debug)]
+pub enum StoredUser {
+    Chunked(UserHead),
+    /// Written before histories were split into chunks; the head item holds the whole history.
+    /// Backends that store whole histories read every user this way.
+    Legacy(User),
+}
+
+impl StoredUser {
+    /// Decodes a head item, as returned by [`UserStore::scan_heads`] or [`UserStore::load`].
+    pub fn from_item(item: &Item) -> Result<StoredUser, StoreError> {
+        Ok(StoredUser::decode(item)?.1)
+    }
+
+    /// Decodes a head item along with the schema version its head blob was written with.
+    fn decode(item: &Item) -> Result<(u8, StoredUser), StoreError> {
+        if let Some(head) = item.get(HEAD_ATTRIBUTE) {
+            let bytes = head.as_b().map_err(|_| {
+                StoreError::Corrupt(format!("'{}' is not a binary", HEAD_ATTRIBUTE))
+            })?;
+            let (version, head) = decode_blob(bytes.as_ref(), CHUNKED_SCHEMA_VERSION)?;
+            return Ok((version, StoredUser::Chunked(head)));
+        }
+        if let Some(stats) = item.get(LEGACY_ATTRIBUTE) {
+            let bytes = stats.as_b().map_err(|_| {
+                StoreError::Corrupt(format!("'{}' is not a binary", LEGACY_ATTRIBUTE))
+            })?;
+            let (version, user) = decode_blob(bytes.as_ref(), LEGACY_SCHEMA_VERSION)?;
+            return Ok((version, StoredUser::Legacy(user)));
+        }
+        Err(StoreError::Corrupt(format!(
+            "item is missing both '{}' and '{}'",
+            HEAD_ATTRIBUTE, LEGACY_ATTRIBUTE
+        )))
+    }
+
+    pub fn userid(&self) -> &str {
+        match self {
+            StoredUser::Chunked(head) => &head.userid,
+            StoredUser::Legacy(user) => &user.userid,
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        match self {
+            StoredUser::Chunked(head) => &head.username,
+            StoredUser::Legacy(user) => &user.username,
+        }
+    }
+
+    /// Changes the display username, keeping the user id.
+    pub fn set_username(&mut self, username: impl Into<String>) {
+        match self {
+            StoredUser::Chunked(head) => head.username = username.into(),
+            StoredUser::Legacy(user) => user.username = username.into(),
+        }
+    }
+
+    pub fn latest_rating(&self, format: &str) -> Option<&Rating> {
+        match self {
+            StoredUser::Chunked(head) => head.latest.get(format),
+            StoredUser::Legacy(user) => user.formats.get(format).and_then(|ratings| ratings.last()),
+        }
+    }
+}
+
+/// A user's full history with the highlights of each format.
+pub type UserWithHighlights = (User, HashMap<String, Highlights>);
+
+/// Reads and writes tracked users in the user stats table.
+pub struct UserStore {
+    ddb: dynamodb::Client,
+    table: String,
+    chunk_encoding: Encoding,
+}
+
+impl UserStore {
+    pub fn new(ddb: dynamodb::Client, table: impl Into<String>) -> UserStore {
+        UserStore {
+            ddb,
+            table: table.into(),
+            chunk_encoding: Encoding::default(),
+        }
+    }
+
+    /// Sets the encoding used when writing chunk items. Chunks in any encoding can be read.
+    pub fn with_chunk_encoding(mut self, encoding: Encoding) -> UserStore {
+        self.chunk_encoding = encoding;
+        self
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    pub async fn exists(&self, user_id: &str) -> Result<bool, StoreError> {
+        Ok(self.get_item(user_id).await?.is_some())
+    }
+
+    /// Reads a user's head item without its history.
+    pub async fn load(&self, user_id: &str) -> Result<Option<StoredUser>, StoreError> {
+        match self.get_item(user_id).await? {
+            Some(item) => Ok(Some(StoredUser::from_item(&item)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a user with their full history.
+    pub async fn load_user(&self, user_id: &str) -> Result<Option<User>, StoreError> {
+        match self.load(user_id).await? {
+            Some(stored) => Ok(Some(self.expand(stored).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a user with their full history and the highlights of each format, listing every
+    /// peak rather than only the latest ones the head keeps.
+    pub async fn load_user_with_highlights(
+        &self,
+        user_id: &str,
+    ) -> Result<Option<UserWithHighlights>, StoreError> {
+        let stored = match self.load(user_id).await? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let mut highlights = match &stored {
+            StoredUser::Chunked(head) => head.highlights.clone(),
+            StoredUser::Legacy(_) => HashMap::new(),
+        };
+        let user = self.expand(stored).await?;
+        // Heads written before highlights were tracked have none stored, and heads keep only the
+        // latest peaks.
+        for (format, ratings) in &user.formats {
+            highlights
+                .entry(format.clone())
+                .or_insert_with(|| Highlights::from_ratings(ratings))
+                .peaks = all_peaks(ratings);
+        }
+        Ok(Some((user, highlights)))
+    }
+
+    /// Reads every chunk referenced by `stored` and assembles the full history.
+    pub async fn expand(&self, stored: StoredUser) -> Result<User, StoreError> {
+        let head = match stored {
+            StoredUser::Chunked(head) => head,
+            StoredUser::Legacy(user) => return Ok(user),
+        };
+        let chunks = self.get_chunks(&head.userid, &head.chunks).await?;
+        assemble(head, chunks)
+    }
+
+    /// Rewrites a user in the current schema if their head item or any of their chunks was written
+    /// with an older one. Returns the oldest version found when the user needed migrating; with
+    /// `dry_run` nothing is written.
+    pub async fn migrate(&self, item: &Item, dry_run: bool) -> Result<Option<u8>, StoreError> {
+        let (head_version, stored) = StoredUser::decode(item)?;
+        let (version, user) = match stored {
+            StoredUser::Chunked(head) => {
+                let chunks = self.get_chunks(&head.userid, &head.chunks).await?;
+                let version = chunks
+                    .values()
+                    .map(|(version, _)| *version)
+                    .fold(head_version, u8::min);
+                (version, assemble(head, chunks)?)
+            }
+            StoredUser::Legacy(user) => (head_version, user),
+        };
+        if version >= CURRENT_SCHEMA_VERSION {
+            return Ok(None);
+        }
+        if !dry_run {
+            self.save_user(&user).await?;
+        }
+        Ok(Some(version))
+    }
+
+    /// Writes a user's whole history, replacing any existing head item. Chunks only the replaced
+    /// head referenced are deleted once the new head is written.
+    pub async fn save_user(&self, user: &User) -> Result<UserHead, StoreError> {
+        let previous_chunks = self.referenced_chunks(&user.userid).await?;
+        let mut by_month: BTreeMap<String, Chunk> = BTreeMap::new();
+        let mut latest = HashMap::new();
+        let mut highlights = HashMap::new();
+        for (format, ratings) in &user.formats {
+            for rating in ratings {
+                by_month
+                    .entry(month_of(rating.time))
+                    .or_default()
+                    .entry(format.clone())
+                    .or_default()
+                    .push(rating.clone());
+            }
+            if let Some(rating) = ratings.last() {
+                latest.insert(format.clone(), rating.clone());
+            }
+            highlights.insert(format.clone(), Highlights::from_ratings(ratings));
+        }
+
+        for (month, chunk) in &by_month {
+            self.put_chunk(&user.userid, month, chunk).await?;
+        }
+
+        let head = UserHead {
+            username: user.username.clone(),
+            userid: user.userid.clone(),
+            latest,
+            chunks: by_month.into_keys().collect(),
+            highlights,
+        };
+        self.put_head(&head).await?;
+        let orphaned = previous_chunks.difference(&head.chunks).cloned().collect();
+        self.delete_chunks(&user.userid, &orphaned).await?;
+        Ok(head)
+    }
+
+    /// The months of the chunks keyed by `user_id` that its head references. A head that cannot be
+    /// read, or whose data names another user id, references none of them.
+    async fn referenced_chunks(&self, user_id: &str) -> Result<BTreeSet<String>, StoreError> {
+        match self.load(user_id).await {
+            Ok(Some(StoredUser::Chunked(head))) if head.userid == user_id => Ok(head.chunks),
+            Ok(_) => Ok(BTreeSet::new()),
+            Err(e) if e.is_corrupt() => Ok(BTreeSet::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Appends `new_ratings` (format, rating) to the user's history and updates the head,
+    /// returning what each new rating achieved.
+    pub async fn append(
+        &self,
+        head: &mut UserHead,
+        new_ratings: &[(String, Rating)],
+    ) -> Result<Vec<(String, Achievement)>, StoreError> {
+        let missing_highlights = new_ratings.iter().any(|(format, _)| {
+            head.latest.contains_key(format) && !head.highlights.contains_key(format)
+        });
+        if missing_highlights {
+            let chunks = self.get_chunks(&head.userid, &head.chunks).await?;
+            let user = assemble(head.clone(), chunks)?;
+            for (format, ratings) in &user.formats {
+                head.highlights
+                    .entry(format.clone())
+                    .or_insert_with(|| Highlights::from_ratings(ratings));
+            }
+        }
+
+        let mut by_month: BTreeMap<String, Vec<&(String, Rating)>> = BTreeMap::new();
+        for entry in new_ratings {
+            by_month
+                .entry(month_of(entry.1.time))
+                .or_default()
+                .push(entry);
+        }
+
+        for (month, entries) in by_month {
+            let mut chunk = if head.chunks.contains(&month) {
+                self.get_chunk(&head.userid, &month)
+                    .await?
+                    .map(|(_, chunk)| chunk)
+                    .unwrap_or_default()
+            } else {
+                HashMap::new()
+            };
+            for (format, rating) in entries {
+                let ratings = chunk.entry(format.clone()).or_default();
+                // A previous append may have written the chunk but failed to write the head.
+                if !ratings.last().is_some_and(|last| last.stores_as(rating)) {
+                    ratings.push(rating.clone());
+                }
+            }
+            self.put_chunk(&head.userid, &month, &chunk).await?;
+            head.chunks.insert(month);
+        }
+
+        let mut achievements = Vec::new();
+        for (format, rating) in new_ratings {
+            let previous = head.latest.insert(format.clone(), rating.clone());
+            let highlights = head.highlights.entry(format.clone()).or_default();
+            for achievement in highlights.push(previous.as_ref(), rating) {
+                achievements.push((format.clone(), achievement));
+            }
+        }
+        self.put_head(head).await?;
+        Ok(achievements)
+    }
+
+    /// Rewrites a user stored under the key `user_id` whose data names a different user id, so
+    /// their head and chunks are all keyed by `user_id` again. Chunks under the other id are then
+    /// deleted, unless that user id's own head references them.
+    pub async fn rekey(&self, stored: StoredUser, user_id: &str) -> Result<StoredUser, StoreError> {
+        let moved = match &stored {
+            StoredUser::Chunked(head) if head.userid != user_id => {
+                Some((head.userid.clone(), head.chunks.clone()))
+            }
+            _ => None,
+        };
+        let mut user = self.expand(stored).await?;
+        user.userid = user_id.to_string();
+        let head = self.save_user(&user).await?;
+        if let Some((other_id, months)) = moved {
+            let referenced = self.referenced_chunks(&other_id).await?;
+            let orphaned = months.difference(&referenced).cloned().collect();
+            self.delete_chunks(&other_id, &orphaned).await?;
+        }
+        Ok(StoredUser::Chunked(head))
+    }
+
+    /// Deletes a user's head item and every chunk it references. Their webhook subscriptions and
+    /// alias links are kept.
+    pub async fn delete_user(&self, user_id: &str) -> Result<(), StoreError> {
+        if let Some(StoredUser::Chunked(head)) = self.load(user_id).await? {
+            self.delete_chunks(user_id, &head.chunks).await?;
+        }
+        self.delete_item(user_id).await
+    }
+
+    /// Marks a user as quarantined so sweeps stop retrying them, recording `reason` for whoever
+    /// repairs them.
+    pub async fn quarantine(&self, user_id: &str, reason: &str) -> Result<(), StoreError> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.ddb
+            .update_item()
+            .table_name(&self.table)
+            .key(USER_ID_KEY, AttributeValue::S(user_id.to_string()))
+            .update_expression("SET #quarantinedAt = :now, #quarantineError = :error")
+            .condition_expression("attribute_exists(#userId)")
+            .expression_attribute_names("#userId", USER_ID_KEY)
+            .expression_attribute_names("#quarantinedAt", QUARANTINED_AT_ATTRIBUTE)
+            .expression_attribute_names("#quarantineError", QUARANTINE_ERROR_ATTRIBUTE)
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .expression_attribute_values(":error", AttributeValue::S(reason.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error quarantining {}: {:?}", user_id, e)))?;
+        Ok(())
+    }
+
+    /// Recovers whatever history can still be read for the user whose head item is `item`.
+    /// Only DynamoDB failures are returned as errors; unreadable data is reported in the result.
+    pub async fn salvage(&self, item: &Item) -> Result<Salvage, StoreError> {
+        let mut salvage = Salvage::default();
+        let head = match StoredUser::from_item(item) {
+            Ok(StoredUser::Legacy(user)) => {
+                salvage.username = Some(user.username);
+                salvage.formats = user.formats;
+                return Ok(salvage);
+            }
+            Ok(StoredUser::Chunked(head)) => head,
+            Err(e) => {
+                salvage.problems.push(format!("head: {}", e));
+                return Ok(salvage);
+            }
+        };
+
+        for month in &head.chunks {
+            match self.get_chunk(&head.userid, month).await {
+                Ok(Some((_, chunk))) => {
+                    for (format, mut ratings) in chunk {
+                        salvage
+                            .formats
+                            .entry(format)
+                            .or_default()
+                            .append(&mut ratings);
+                    }
+                }
+                Ok(None) => {
+                    salvage.lost_chunks.insert(month.clone());
+                    salvage.problems.push(format!("chunk {}: missing", month));
+                }
+                Err(e) if e.is_corrupt() => {
+                    salvage.lost_chunks.insert(month.clone());
+                    salvage.problems.push(format!("chunk {}: {}", month, e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // The head keeps the latest rating even if its chunk was lost.
+        for (format, rating) in &head.latest {
+            let ratings = salvage.formats.entry(format.clone()).or_default();
+            if !ratings.iter().any(|r| r.time == rating.time) {
+                ratings.push(rating.clone());
+            }
+        }
+        for ratings in salvage.formats.values_mut() {
+            ratings.sort_by_key(|r| r.time);
+        }
+        salvage.username = Some(head.username);
+        Ok(salvage)
+    }
+
+    /// Deletes a user's chunk items for `months`.
+    pub async fn delete_chunks(
+        &self,
+        user_id: &str,
+        months: &BTreeSet<String>,
+    ) -> Result<(), StoreError> {
+        for month in months {
+            self.delete_item(&chunk_key(user_id, month)).await?;
+        }
+        Ok(())
+    }
+
+    /// The user id whose history `user_id` was merged into, if `user_id` is an alias.
+    pub async fn alias_of(&self, user_id: &str) -> Result<Option<String>, StoreError> {
+        let item = match self.get_item(&alias_key(user_id)).await? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        match item.get(ALIAS_OF_ATTRIBUTE).map(|val| val.as_s()) {
+            Some(Ok(target)) => Ok(Some(target.clone())),
+            _ => Err(StoreError::Corrupt(format!(
+                "'{}' is not a string",
+                ALIAS_OF_ATTRIBUTE
+            ))),
+        }
+    }
+
+    /// Links `user_id` to the user id its history was merged into, replacing any existing link.
+    pub async fn put_alias(&self, user_id: &str, alias_of: &str) -> Result<(), StoreError> {
+        let key = alias_key(user_id);
+        self.ddb
+            .put_item()
+            .table_name(&self.table)
+            .item(USER_ID_KEY, AttributeValue::S(key.clone()))
+            .item(ALIAS_OF_ATTRIBUTE, AttributeValue::S(alias_of.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error writing {}: {:?}", key, e)))?;
+        Ok(())
+    }
+
+    /// Removes the alias link from `user_id`, if it has one.
+    pub async fn remove_alias(&self, user_id: &str) -> Result<(), StoreError> {
+        self.delete_item(&alias_key(user_id)).await
+    }
+
+    /// Reads the webhook subscriptions to a user's rating events.
+    pub async fn subscriptions(&self, user_id: &str) -> Result<Vec<Subscription>, StoreError> {
+        let item = match self.get_item(&webhooks_key(user_id)).await? {
+            Some(item) => item,
+            None => return Ok(Vec::new()),
+        };
+        let json = item
+            .get(SUBSCRIPTIONS_ATTRIBUTE)
+            .and_then(|val| val.as_s().ok())
+            .ok_or_else(|| {
+                StoreError::Corrupt(format!("'{}' is not a string", SUBSCRIPTIONS_ATTRIBUTE))
+            })?;
+        serde_json::from_str(json).map_err(|e| {
+            StoreError::Corrupt(format!("unreadable subscriptions for {}: {}", user_id, e))
+        })
+    }
+
+    /// Replaces a user's webhook subscriptions.
+    pub async fn put_subscriptions(
+        &self,
+        user_id: &str,
+        subscriptions: &[Subscription],
+    ) -> Result<(), StoreError> {
+        let key = webhooks_key(user_id);
+        if subscriptions.is_empty() {
+            return self.delete_item(&key).await;
+        }
+        let json = serde_json::to_string(subscriptions)
+            .map_err(|e| StoreError::Codec(format!("error serializing subscriptions: {}", e)))?;
+        self.ddb
+            .put_item()
+            .table_name(&self.table)
+            .item(USER_ID_KEY, AttributeValue::S(key.clone()))
+            .item(SUBSCRIPTIONS_ATTRIBUTE, AttributeValue::S(json))
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error writing {}: {:?}", key, e)))?;
+        Ok(())
+    }
+
+    /// Reads one page of head items, skipping chunk items and quarantined users.
+    pub async fn scan_heads(
+        &self,
+        exclusive_start_key: Option<Item>,
+        segment: i32,
+        total_segments: i32,
+        limit: i32,
+    ) -> Result<(Vec<Item>, Option<Item>), StoreError> {
+        self.scan(
+            exclusive_start_key,
+            Some((segment, total_segments)),
+            limit,
+            Some(false),
+        )
+        .await
+    }
+
+    /// Reads one page of quarantined head items.
+    pub async fn scan_quarantined(
+        &self,
+        exclusive_start_key: Option<Item>,
+        limit: i32,
+    ) -> Result<(Vec<Item>, Option<Item>), StoreError> {
+        self.scan(exclusive_start_key, None, limit, Some(true))
+            .await
+    }
+
+    /// Lists the ids of every tracked user, quarantined or not.
+    pub async fn user_ids(&self) -> Result<Vec<String>, StoreError> {
+        let mut user_ids = Vec::new();
+        let mut exclusive_start_key = None;
+        loop {
+            let (items, last_evaluated_key) =
+                self.scan(exclusive_start_key, None, 1000, None).await?;
+            for item in items {
+                if let Some(Ok(user_id)) = item.get(USER_ID_KEY).map(|val| val.as_s()) {
+                    user_ids.push(user_id.clone());
+                }
+            }
+            exclusive_start_key = last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                return Ok(user_ids);
+            }
+        }
+    }
+
+    /// Reads a user's head item as stored, without decoding it.
+    pub async fn get_head_item(&self, user_id: &str) -> Result<Option<Item>, StoreError> {
+        self.get_item(user_id).await
+    }
+
+    async fn scan(
+        &self,
+        exclusive_start_key: Option<Item>,
+        segment: Option<(i32, i32)>,
+        limit: i32,
+        quarantined: Option<bool>,
+    ) -> Result<(Vec<Item>, Option<Item>), StoreError> {
+        let mut filter_expression = "NOT contains(#userId, :chunkSeparator)".to_string();
+        match quarantined {
+            Some(true) => filter_expression.push_str(" AND attribute_exists(#quarantinedAt)"),
+            Some(false) => filter_expression.push_str(" AND attribute_not_exists(#quarantinedAt)"),
+            None => {}
+        }
+        let mut request = self
+            .ddb
+            .scan()
+            .table_name(&self.table)
+            .set_exclusive_start_key(exclusive_start_key)
+            .set_segment(segment.map(|(segment, _)| segment))
+            .set_total_segments(segment.map(|(_, total)| total))
+            .limit(limit)
+            .filter_expression(filter_expression)
+            .expression_attribute_names("#userId", USER_ID_KEY)
+            .expression_attribute_values(
+                ":chunkSeparator",
+                AttributeValue::S(CHUNK_SEPARATOR.to_string()),
+            );
+        // DynamoDB rejects expression attribute names the filter does not use.
+        if quarantined.is_some() {
+            request =
+                request.expression_attribute_names("#quarantinedAt", QUARANTINED_AT_ATTRIBUTE);
+        }
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error scanning table: {:?}", e)))?;
+        Ok((resp.items.unwrap_or_default(), resp.last_evaluated_key))
+    }
+
+    async fn get_item(&self, key: &str) -> Result<Option<Item>, StoreError> {
+        let resp = self
+            .ddb
+            .get_item()
+            .table_name(&self.table)
+            .key(USER_ID_KEY, AttributeValue::S(key.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error reading {}: {:?}", key, e)))?;
+        Ok(resp.item)
+    }
+
+    async fn delete_item(&self, key: &str) -> Result<(), StoreError> {
+        self.ddb
+            .delete_item()
+            .table_name(&self.table)
+            .key(USER_ID_KEY, AttributeValue::S(key.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error deleting {}: {:?}", key, e)))?;
+        Ok(())
+    }
+
+    async fn put_head(&self, head: &UserHead) -> Result<(), StoreError> {
+        self.ddb
+            .put_item()
+            .table_name(&self.table)
+            .item(USER_ID_KEY, AttributeValue::S(head.userid.clone()))
+            .item(HEAD_ATTRIBUTE, AttributeValue::B(encode_blob(head)?.into()))
+            .send()
+            .await
+            .map_err(|e| {
+                StoreError::Dynamo(format!("error writing head for {}: {:?}", head.userid, e))
+            })?;
+        Ok(())
+    }
+
+    async fn put_chunk(&self, user_id: &str, month: &str, chunk: &Chunk) -> Result<(), StoreError> {
+        let key = chunk_key(user_id, month);
+        self.ddb
+            .put_item()
+            .table_name(&self.table)
+            .item(USER_ID_KEY, AttributeValue::S(key.clone()))
+            .item(
+                CHUNK_ATTRIBUTE,
+                AttributeValue::B(encode_ratings(chunk, self.chunk_encoding)?.into()),
+            )
+            .send()
+            .await
+            .map_err(|e| StoreError::Dynamo(format!("error writing {}: {:?}", key, e)))?;
+        Ok(())
+    }
+
+    async fn get_chunk(
+        &self,
+        user_id: &str,
+        month: &str,
+    ) -> Result<Option<(u8, Chunk)>, StoreError> {
+        match self.get_item(&chunk_key(user_id, month)).await? {
+            Some(item) => Ok(Some(decode_chunk(&item)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_chunks(
+        &self,
+        user_id: &str,
+        months: &BTreeSet<String>,
+    ) -> Result<HashMap<String, (u8, Chunk)>, StoreError> {
+        let mut chunks = HashMap::new();
+        let months: Vec<&String> = months.iter().collect();
+        for batch in months.chunks(BATCH_GET_LIMIT) {
+            let mut keys = batch
+                .iter()
+                .map(|month| {
+                    HashMap::from([(
+                        USER_ID_KEY.to_string(),
+                        AttributeValue::S(chunk_key(user_id, month)),
+                    )])
+                })
+                .collect::<Vec<_>>();
+            while !keys.is_empty() {
+                let request = KeysAndAttributes::builder()
+                    .set_keys(Some(keys))
+                    .build()
+                    .map_err(|e| StoreError::Dynamo(format!("{:?}", e)))?;
+                let resp = self
+                    .ddb
+                    .batch_get_item()
+                    .request_items(&self.table, request)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        StoreError::Dynamo(format!("error reading chunks for {}: {:?}", user_id, e))
+                    })?;
+                let items = resp
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table))
+                    .unwrap_or_default();
+                for item in items {
+                    let key = item
+                        .get(USER_ID_KEY)
+                        .and_then(|val| val.as_s().ok())
+                        .ok_or_else(|| StoreError::Corrupt("chunk is missing 'userId'".into()))?;
+                    let month = match key.split_once(CHUNK_SEPARATOR) {
+                        Some((_, month)) => month.to_string(),
+                        None => return Err(StoreError::Corrupt(format!("{} is not a chunk", key))),
+                    };
+                    chunks.insert(month, decode_chunk(&item)?);
+                }
+                keys = resp
+                    .unprocessed_keys
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table))
+                    .map(|request| request.keys)
+                    .unwrap_or_default();
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+/// Builds the full history from a head and its chunks, as returned by `get_chunks`.
+fn assemble(head: UserHead, mut chunks: HashMap<String, (u8, Chunk)>) -> Result<User, StoreError> {
+    let mut user = User {
+        username: head.username,
+        userid: head.userid,
+        formats: HashMap::new(),
+    };
+    for month in &head.chunks {
+        let chunk = match chunks.remove(month) {
+            Some((_, chunk)) => chunk,
+            None => {
+                return Err(StoreError::Corrupt(format!(
+                    "chunk {} for user {} is missing",
+                    month, user.userid
+                )))
+            }
+        };
+        for (format, mut ratings) in chunk {
+            user.formats.entry(format).or_default().append(&mut ratings);
+        }
+    }
+    Ok(user)
+}
+
+/// Decodes a chunk item along with the schema version it was written with.
+fn decode_chunk(item: &Item) -> Result<(u8, Chunk), StoreError> {
+    let bytes = item
+        .get(CHUNK_ATTRIBUTE)
+        .ok_or_else(|| StoreError::Corrupt(format!("chunk is missing '{}'", CHUNK_ATTRIBUTE)))?
+        .as_b()
+        .map_err(|_| StoreError::Corrupt(format!("'{}' is not a binary", CHUNK_ATTRIBUTE)))?;
+    decode_ratings(bytes.as_ref())
+}
+
+/// The error recorded when `item` was quarantined, if it was.
+pub fn quarantine_error(item: &Item) -> Option<&str> {
+    item.get(QUARANTINED_AT_ATTRIBUTE)?;
+    item.get(QUARANTINE_ERROR_ATTRIBUTE)
+        .and_then(|val| val.as_s().ok())
+        .map(String::as_str)
+        .or(Some("unknown error"))
+}
+
+fn chunk_key(user_id: &str, month: &str) -> String {
+    format!("{}{}{}", user_id, CHUNK_SEPARATOR, month)
+}
+
+/// Shares the chunk separator so scans for head items skip it.
+fn webhooks_key(user_id: &str) -> String {
+    format!("{}{}{}", user_id, CHUNK_SEPARATOR, WEBHOOKS_SUFFIX)
+}
+
+/// Shares the chunk separator so scans for head items skip it.
+fn alias_key(user_id: &str) -> String {
+    format!("{}{}{}", user_id, CHUNK_SEPARATOR, ALIAS_SUFFIX)
+}
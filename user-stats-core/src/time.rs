@@ -0,0 +1,69 @@
+//! UTC calendar formatting of the unix timestamps ratings are stored with.
+
+/// Splits a unix timestamp into its UTC `(year, month, day)`.
+fn civil_date(time: u64) -> (i64, i64, i64) {
+    // Civil-from-days conversion, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (time / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats a unix timestamp as the UTC month `YYYY-MM` it falls in.
+pub fn month_of(time: u64) -> String {
+    let (year, month, _) = civil_date(time);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Formats a unix timestamp as an RFC 3339 UTC date and time, such as `2024-05-01T12:00:00Z`.
+pub fn rfc3339(time: u64) -> String {
+    let (year, month, day) = civil_date(time);
+    let seconds = time % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Formats a unix timestamp as an HTTP date, such as `Wed, 01 May 2024 12:00:00 GMT`, for
+/// headers like `last-modified`.
+pub fn http_date(time: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day) = civil_date(time);
+    let seconds = time % 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(time / 86_400 % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Formats a unix timestamp as the UTC date `YYYY-MM-DD` it falls on.
+pub fn date(time: u64) -> String {
+    let (year, month, day) = civil_date(time);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,87 @@
+//! Showdown user ids.
+//!
+//! Showdown identifies users by their username lowercased with everything but ASCII letters and
+//! digits removed, so `Some User!` and `someuser` are the same user. Usernames are at most 18
+//! characters long, which bounds ids too.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The longest user id Showdown allows.
+pub const MAX_USER_ID_LEN: usize = 18;
+
+/// A valid Showdown user id. Parsing a username normalizes it and checks it could be a user on
+/// Showdown, so requests for names that cannot exist are rejected before any lookup.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UserId(String);
+
+impl UserId {
+    /// The id of the user named `username`.
+    pub fn parse(username: &str) -> Result<UserId, InvalidUserId> {
+        let id = to_id(username);
+        if id.is_empty() {
+            return Err(InvalidUserId::Empty);
+        }
+        if id.len() > MAX_USER_ID_LEN {
+            return Err(InvalidUserId::TooLong(id.len()));
+        }
+        Ok(UserId(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for UserId {
+    type Err = InvalidUserId;
+
+    fn from_str(username: &str) -> Result<UserId, InvalidUserId> {
+        UserId::parse(username)
+    }
+}
+
+impl AsRef<str> for UserId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Why a username cannot be a Showdown user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidUserId {
+    /// The username has no letters or digits.
+    Empty,
+    /// The user id has more characters than Showdown allows.
+    TooLong(usize),
+}
+
+impl fmt::Display for InvalidUserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidUserId::Empty => write!(f, "username must contain a letter or digit"),
+            InvalidUserId::TooLong(len) => write!(
+                f,
+                "username is {} characters long without spaces and symbols, at most {} are allowed",
+                len, MAX_USER_ID_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidUserId {}
+
+/// Normalizes `text` the way Showdown's `toID` does, without checking the result. Format names
+/// are normalized the same way.
+pub fn to_id(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect()
+}
@@ -0,0 +1,322 @@
+//! Webhook subscriptions to a tracked user's rating events.
+//!
+//! Subscriptions are stored next to the user they follow and read by `update-stats` whenever it
+//! pushes ratings that raise an event. They are managed with the `webhooks` tool in
+//! `user-stats-admin`.
+
+use crate::highlights::Achievement;
+use crate::model::Rating;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Kinds of rating events a subscription can ask for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    /// A rating beat the user's previous peak in the format.
+    NewPeak,
+    /// A rating reached a milestone for the first time.
+    Milestone,
+    /// A rating fell by at least the updater's sharp drop threshold.
+    SharpDrop,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 3] = [
+        EventKind::NewPeak,
+        EventKind::Milestone,
+        EventKind::SharpDrop,
+    ];
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::NewPeak => write!(f, "new-peak"),
+            EventKind::Milestone => write!(f, "milestone"),
+            EventKind::SharpDrop => write!(f, "sharp-drop"),
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EventKind, String> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown event kind '{}', expected new-peak, milestone or sharp-drop",
+                    s
+                )
+            })
+    }
+}
+
+/// How events are posted to a subscription's URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+    /// The [`RatingEvent`] as JSON, optionally signed.
+    #[default]
+    Json,
+    /// A Discord webhook, sent messages with one embed per event.
+    Discord,
+}
+
+impl Target {
+    /// Discord for Discord webhook URLs, otherwise JSON.
+    pub fn for_url(url: &str) -> Target {
+        let discord = [
+            "https://discord.com/api/webhooks/",
+            "https://discordapp.com/api/webhooks/",
+        ];
+        if discord.iter().any(|prefix| url.starts_with(prefix)) {
+            Target::Discord
+        } else {
+            Target::Json
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        *self == Target::Json
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Json => write!(f, "json"),
+            Target::Discord => write!(f, "discord"),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, String> {
+        match s {
+            "json" => Ok(Target::Json),
+            "discord" => Ok(Target::Discord),
+            _ => Err(format!("unknown target '{}', expected json or discord", s)),
+        }
+    }
+}
+
+/// A request to be sent a user's rating events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    /// Identifies the subscription within the user's subscriptions.
+    pub id: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Target::is_json")]
+    pub target: Target,
+    /// Only events in this format are sent. All formats when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Only events of these kinds are sent. All kinds when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventKind>,
+    /// Key for the HMAC-SHA256 signature of each payload. Payloads are unsigned when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl Subscription {
+    pub fn matches(&self, event: &RatingEvent) -> bool {
+        self.format
+            .as_ref()
+            .is_none_or(|format| *format == event.format)
+            && (self.events.is_empty() || self.events.contains(&event.kind))
+    }
+}
+
+/// The JSON payload delivered to subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RatingEvent {
+    pub kind: EventKind,
+    pub userid: String,
+    pub username: String,
+    pub format: String,
+    /// Time of the rating that raised the event.
+    pub time: u64,
+    pub elo: f64,
+    /// The user's rating in the format before this one, if they had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_elo: Option<f64>,
+    /// The milestone reached, for [`EventKind::Milestone`] events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub milestone: Option<u32>,
+}
+
+impl RatingEvent {
+    /// The events raised by pushing `rating`, which follows `previous` in the format and achieved
+    /// `achievements`. A fall of at least `sharp_drop` is a [`EventKind::SharpDrop`].
+    pub fn from_rating(
+        userid: &str,
+        username: &str,
+        format: &str,
+        previous: Option<&Rating>,
+        rating: &Rating,
+        achievements: &[Achievement],
+        sharp_drop: f64,
+    ) -> Vec<RatingEvent> {
+        let event = |kind, milestone| RatingEvent {
+            kind,
+            userid: userid.to_string(),
+            username: username.to_string(),
+            format: format.to_string(),
+            time: rating.time,
+            elo: rating.elo,
+            previous_elo: previous.map(|r| r.elo),
+            milestone,
+        };
+        let mut events: Vec<RatingEvent> = achievements
+            .iter()
+            .map(|achievement| match achievement {
+                Achievement::NewPeak(_) => event(EventKind::NewPeak, None),
+                Achievement::Milestone(milestone) => {
+                    event(EventKind::Milestone, Some(milestone.elo))
+                }
+            })
+            .collect();
+        if previous.is_some_and(|previous| previous.elo - rating.elo >= sharp_drop) {
+            events.push(event(EventKind::SharpDrop, None));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlights::{Milestone, Peak};
+
+    fn event(kind: EventKind, format: &str) -> RatingEvent {
+        RatingEvent {
+            kind,
+            userid: "someuser".to_string(),
+            username: "Some User".to_string(),
+            format: format.to_string(),
+            time: 200,
+            elo: 1500.0,
+            previous_elo: None,
+            milestone: None,
+        }
+    }
+
+    fn subscription(format: Option<&str>, events: Vec<EventKind>) -> Subscription {
+        Subscription {
+            id: "sub".to_string(),
+            url: "https://example.com/hook".to_string(),
+            target: Target::Json,
+            format: format.map(str::to_string),
+            events,
+            secret: None,
+        }
+    }
+
+    fn from_rating(
+        previous: Option<f64>,
+        elo: f64,
+        achievements: &[Achievement],
+    ) -> Vec<(EventKind, Option<u32>)> {
+        let previous = previous.map(|elo| Rating::live(100, elo));
+        RatingEvent::from_rating(
+            "someuser",
+            "Some User",
+            "gen9ou",
+            previous.as_ref(),
+            &Rating::live(200, elo),
+            achievements,
+            100.0,
+        )
+        .into_iter()
+        .map(|event| (event.kind, event.milestone))
+        .collect()
+    }
+
+    #[test]
+    fn subscriptions_without_filters_match_everything() {
+        let subscription = subscription(None, vec![]);
+        for kind in EventKind::ALL {
+            assert!(subscription.matches(&event(kind, "gen9ou")));
+            assert!(subscription.matches(&event(kind, "gen9uu")));
+        }
+    }
+
+    #[test]
+    fn subscriptions_match_only_their_format_and_kinds() {
+        let subscription = subscription(
+            Some("gen9ou"),
+            vec![EventKind::NewPeak, EventKind::SharpDrop],
+        );
+        assert!(subscription.matches(&event(EventKind::NewPeak, "gen9ou")));
+        assert!(subscription.matches(&event(EventKind::SharpDrop, "gen9ou")));
+        assert!(!subscription.matches(&event(EventKind::Milestone, "gen9ou")));
+        assert!(!subscription.matches(&event(EventKind::NewPeak, "gen9uu")));
+    }
+
+    #[test]
+    fn classifies_peaks_and_milestones() {
+        let achievements = [
+            Achievement::NewPeak(Peak {
+                time: 200,
+                elo: 1620.0,
+            }),
+            Achievement::Milestone(Milestone {
+                elo: 1600,
+                time: 200,
+            }),
+        ];
+        assert_eq!(
+            from_rating(Some(1580.0), 1620.0, &achievements),
+            [
+                (EventKind::NewPeak, None),
+                (EventKind::Milestone, Some(1600))
+            ]
+        );
+        assert_eq!(from_rating(Some(1580.0), 1590.0, &[]), []);
+    }
+
+    #[test]
+    fn classifies_drops_of_at_least_the_threshold_as_sharp() {
+        assert_eq!(
+            from_rating(Some(1600.0), 1500.0, &[]),
+            [(EventKind::SharpDrop, None)]
+        );
+        assert_eq!(from_rating(Some(1600.0), 1500.5, &[]), []);
+        assert_eq!(from_rating(None, 1000.0, &[]), []);
+    }
+
+    #[test]
+    fn events_describe_the_rating_and_the_one_before() {
+        let events = RatingEvent::from_rating(
+            "someuser",
+            "Some User",
+            "gen9ou",
+            Some(&Rating::live(100, 1700.0)),
+            &Rating::live(200, 1550.0),
+            &[],
+            100.0,
+        );
+        assert_eq!(
+            events,
+            [RatingEvent {
+                kind: EventKind::SharpDrop,
+                userid: "someuser".to_string(),
+                username: "Some User".to_string(),
+                format: "gen9ou".to_string(),
+                time: 200,
+                elo: 1550.0,
+                previous_elo: Some(1700.0),
+                milestone: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use proptest::prelude::*;
+use std::collections::HashMap;
+use user_stats_core::codec::{decode_ratings, encode_ratings, Encoding};
+use user_stats_core::model::{Rating, Record, Source};
+use user_stats_core::schema::CURRENT_SCHEMA_VERSION;
+
+/// The columnar encoding keeps elos to two decimal places.
+const ELO_TOLERANCE: f64 = 0.005 + 1e-9;
+
+fn ratings() -> impl Strategy<Value = Vec<Rating>> {
+    let source = prop_oneof![Just(Source::Live), Just(Source::Replay)];
+    let record =
+        prop::option::of(any::<(u32, u32, u32)>().prop_map(|(w, l, t)| Record { w, l, t }));
+    prop::collection::vec((0u64..=u64::MAX, 0.0f64..10_000.0, source, record), 0..200).prop_map(
+        |points| {
+            points
+                .into_iter()
+                .map(|(time, elo, source, record)| Rating {
+                    time,
+                    elo,
+                    source,
+                    record,
+                })
+                .collect()
+        },
+    )
+}
+
+fn chunk() -> impl Strategy<Value = HashMap<String, Vec<Rating>>> {
+    prop::collection::hash_map("[a-z0-9]{0,24}", ratings(), 0..8)
+}
+
+fn assert_same_times(
+    expected: &HashMap<String, Vec<Rating>>,
+    actual: &HashMap<String, Vec<Rating>>,
+) -> Result<(), TestCaseError> {
+    prop_assert_eq!(expected.len(), actual.len());
+    for (format, ratings) in expected {
+        let decoded = actual.get(format);
+        prop_assert!(decoded.is_some(), "format {} is missing", format);
+        let decoded = decoded.unwrap();
+        prop_assert_eq!(ratings.len(), decoded.len());
+        for (a, b) in ratings.iter().zip(decoded) {
+            prop_assert_eq!(a.time, b.time);
+            prop_assert_eq!(a.source, b.source);
+            prop_assert_eq!(a.record, b.record);
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn json_gzip_round_trips(chunk in chunk()) {
+        let bytes = encode_ratings(&chunk, Encoding::JsonGzip).unwrap();
+        let (version, decoded) = decode_ratings(&bytes).unwrap();
+        prop_assert_eq!(version, CURRENT_SCHEMA_VERSION);
+        assert_same_times(&chunk, &decoded)?;
+        for (format, ratings) in &chunk {
+            // serde_json's default float parsing may be off by one ulp.
+            for (a, b) in ratings.iter().zip(&decoded[format]) {
+                prop_assert!((a.elo - b.elo).abs() <= a.elo.abs() * 1e-15, "{} != {}", a.elo, b.elo);
+            }
+        }
+    }
+
+    #[test]
+    fn columnar_round_trips_within_quantization(chunk in chunk()) {
+        let bytes = encode_ratings(&chunk, Encoding::Columnar).unwrap();
+        let (version, decoded) = decode_ratings(&bytes).unwrap();
+        prop_assert_eq!(version, CURRENT_SCHEMA_VERSION);
+        assert_same_times(&chunk, &decoded)?;
+        for (format, ratings) in &chunk {
+            for (a, b) in ratings.iter().zip(&decoded[format]) {
+                prop_assert!((a.elo - b.elo).abs() <= ELO_TOLERANCE, "{} != {}", a.elo, b.elo);
+            }
+        }
+    }
+
+    #[test]
+    fn columnar_is_stable_after_one_round_trip(chunk in chunk()) {
+        let bytes = encode_ratings(&chunk, Encoding::Columnar).unwrap();
+        let (_, decoded) = decode_ratings(&bytes).unwrap();
+        prop_assert_eq!(encode_ratings(&decoded, Encoding::Columnar).unwrap(), bytes);
+    }
+
+    #[test]
+    fn decoding_arbitrary_bytes_does_not_panic(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = decode_ratings(&bytes);
+        let mut blob = b"PSUS".to_vec();
+        blob.extend([CURRENT_SCHEMA_VERSION, Encoding::Columnar as u8]);
+        blob.extend(&bytes);
+        let _ = decode_ratings(&blob);
+    }
+}
@@ -0,0 +1,38 @@
+use proptest::prelude::*;
+use user_stats_core::user_id::{InvalidUserId, UserId, MAX_USER_ID_LEN};
+
+#[test]
+fn normalizes_usernames_like_showdown() {
+    let cases = [
+        ("someuser", "someuser"),
+        ("Some User!", "someuser"),
+        ("  The_Brucey  ", "thebrucey"),
+        ("Ünïcode 42", "ncode42"),
+        ("abcdefghijklmnopqr", "abcdefghijklmnopqr"),
+    ];
+    for (username, id) in cases {
+        assert_eq!(UserId::parse(username).unwrap().as_str(), id);
+    }
+}
+
+#[test]
+fn rejects_usernames_showdown_would_not_allow() {
+    assert_eq!(UserId::parse(""), Err(InvalidUserId::Empty));
+    assert_eq!(UserId::parse("!! __ ??"), Err(InvalidUserId::Empty));
+    assert_eq!(
+        UserId::parse("abcdefghijklmnopqrs"),
+        Err(InvalidUserId::TooLong(19))
+    );
+    // Spaces and symbols do not count towards the limit.
+    assert!(UserId::parse("a b c d e f g h i j k l m n o p q r").is_ok());
+}
+
+proptest! {
+    #[test]
+    fn parsing_an_id_gives_it_back(username in "\\PC{0,40}") {
+        if let Ok(id) = UserId::parse(&username) {
+            prop_assert!(!id.as_str().is_empty() && id.as_str().len() <= MAX_USER_ID_LEN);
+            prop_assert_eq!(UserId::parse(id.as_str()), Ok(id.clone()));
+        }
+    }
+}